@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::scan::{parse_source_files, SourceFiles};
+use crate::traversal::{invalid_glob, PathFilter};
+use crate::{relativize_paths, ParsedFile, ScanOptions};
+
+/// The parts of the packaged `Cargo.toml` that tell where the Rust source files are.
+#[derive(Deserialize)]
+struct Manifest {
+    lib: Option<Target>,
+    #[serde(default)]
+    bin: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    path: Option<String>,
+}
+
+impl Manifest {
+    fn target_paths(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.lib
+            .iter()
+            .chain(self.bin.iter())
+            .filter_map(|target| target.path.as_deref())
+            .map(normalize)
+    }
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+// Archives use forward slashes, while the module tree relies on `Path` operations
+fn normalize(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// Scans a packaged crate, i.e. a `.crate` file as published to crates.io or any `.tar.gz` with the
+/// same layout, without unpacking it to disk. Requires the `archive` feature.
+///
+/// The package root is the directory of the outermost `Cargo.toml` in the archive. The same files are
+/// scanned as by [parse_for_no_mangle_pub_extern_c_fns_with_options](crate::parse_for_no_mangle_pub_extern_c_fns_with_options),
+/// i.e. the Rust source files in `src/**`, along with the `[lib]` and `[[bin]]` targets declared in
+/// the packaged `Cargo.toml` and the Rust source files next to them, if they are elsewhere.
+/// The paths in the output are relative to the package root, e.g. `src/lib.rs`, and so are the globs
+/// in [ScanOptions::include] and [ScanOptions::exclude].
+///
+/// # Example
+///
+/// ```no_run
+/// use no_mangle_pub_export_c_fn::{parse_crate_archive_for_no_mangle_pub_extern_c_fns, ScanOptions};
+///
+/// let parsed_files = parse_crate_archive_for_no_mangle_pub_extern_c_fns(
+///     "vendor/libc-0.2.150.crate",
+///     &ScanOptions::default(),
+/// ).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// Fails if the archive can't be read or decompressed, if it doesn't contain a valid `Cargo.toml`,
+/// or if a pattern in `options` isn't a valid glob.
+pub fn parse_crate_archive_for_no_mangle_pub_extern_c_fns<P: AsRef<Path>>(
+    archive_path: P,
+    options: &ScanOptions,
+) -> std::io::Result<Vec<ParsedFile>> {
+    let archive = std::fs::File::open(archive_path)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+
+    // Text files only. Non-UTF-8 files can't be Rust source files or manifests anyway
+    let mut files: HashMap<PathBuf, String> = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = normalize(&entry.path()?.to_string_lossy());
+        let is_relevant = path
+            .extension()
+            .map_or(false, |extension| extension == "rs")
+            || path
+                .file_name()
+                .map_or(false, |file_name| file_name == "Cargo.toml");
+        let mut contents = String::new();
+        if is_relevant && entry.read_to_string(&mut contents).is_ok() {
+            files.insert(path, contents);
+        }
+    }
+
+    let manifest_path = files
+        .keys()
+        .filter(|path| {
+            path.file_name()
+                .map_or(false, |file_name| file_name == "Cargo.toml")
+        })
+        .min_by_key(|path| (path.components().count(), path.to_path_buf()))
+        .cloned()
+        .ok_or_else(|| invalid_data("the archive doesn't contain `Cargo.toml`".to_string()))?;
+    let package_root = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+    let manifest: Manifest = toml::from_str(&files[&manifest_path])
+        .map_err(|err| invalid_data(format!("invalid `Cargo.toml`: {}", err)))?;
+
+    let sources: HashMap<PathBuf, String> = files
+        .into_iter()
+        .filter_map(|(path, contents)| {
+            let relative = path.strip_prefix(package_root).ok()?.to_path_buf();
+            Some((relative, contents))
+        })
+        .collect();
+    let src_dir = Path::new("src");
+    let target_dirs: Vec<PathBuf> = manifest
+        .target_paths()
+        .filter_map(|target_path| target_path.parent().map(Path::to_path_buf))
+        // Target roots in the package root are taken individually
+        .filter(|target_dir| !target_dir.as_os_str().is_empty())
+        .collect();
+    let target_paths: Vec<PathBuf> = manifest.target_paths().collect();
+    let path_filter = PathFilter::new(options).map_err(invalid_glob)?;
+    let paths: Vec<PathBuf> = sources
+        .keys()
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "rs")
+                && (path.starts_with(src_dir)
+                    || target_paths.contains(path)
+                    || target_dirs
+                        .iter()
+                        .any(|target_dir| path.starts_with(target_dir)))
+                && path_filter.is_match(path)
+        })
+        .cloned()
+        .collect();
+    let mut parsed_files = parse_source_files(paths, &SourceFiles::InMemory(&sources), options);
+    // The paths are already relative, but not necessarily separated by forward slashes
+    relativize_paths(&mut parsed_files, Path::new(""), options);
+    Ok(parsed_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a gzipped tarball with the given files, as `cargo package` does, and returns its path.
+    fn write_archive(dir: &Path, files: &[(&str, &str)]) -> PathBuf {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        files.iter().for_each(|(path, contents)| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        });
+        let bytes = builder.into_inner().unwrap().finish().unwrap();
+        let archive_path = dir.join("pkg-0.1.0.crate");
+        std::fs::write(&archive_path, bytes).unwrap();
+        archive_path
+    }
+
+    fn exports(parsed_files: &[ParsedFile]) -> Vec<(String, String)> {
+        parsed_files
+            .iter()
+            .flat_map(|parsed_file| {
+                parsed_file.no_mangle_pub_export_c_fns.0.iter().map(
+                    move |no_mangle_pub_export_c_fn| {
+                        (
+                            parsed_file.path.to_string_lossy().into_owned(),
+                            no_mangle_pub_export_c_fn.symbol.clone(),
+                        )
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scans_the_package_without_its_top_level_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = write_archive(
+            dir.path(),
+            &[
+                ("pkg-0.1.0/Cargo.toml", "[package]\nname = \"pkg\"\n"),
+                (
+                    "pkg-0.1.0/src/lib.rs",
+                    "mod a;\n#[no_mangle] pub extern \"C\" fn f() {}\n",
+                ),
+                (
+                    "pkg-0.1.0/src/a.rs",
+                    "#[no_mangle] pub extern \"C\" fn g() {}\n",
+                ),
+                (
+                    "pkg-0.1.0/tests/t.rs",
+                    "#[no_mangle] pub extern \"C\" fn t() {}\n",
+                ),
+                (
+                    "pkg-0.1.0/vendor/dep/Cargo.toml",
+                    "[lib]\npath = \"x.rs\"\n",
+                ),
+                (
+                    "pkg-0.1.0/vendor/dep/src/lib.rs",
+                    "#[no_mangle] pub extern \"C\" fn v() {}\n",
+                ),
+            ],
+        );
+        let options = ScanOptions {
+            relative_paths: true,
+            ..Default::default()
+        };
+        let parsed_files =
+            parse_crate_archive_for_no_mangle_pub_extern_c_fns(&archive_path, &options).unwrap();
+        assert_eq!(
+            exports(&parsed_files),
+            vec![
+                ("src/a.rs".to_string(), "g".to_string()),
+                ("src/lib.rs".to_string(), "f".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn scans_targets_outside_of_src() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = write_archive(
+            dir.path(),
+            &[
+                (
+                    "pkg-0.1.0/Cargo.toml",
+                    "[package]\nname = \"pkg\"\n\n[lib]\npath = \"ffi/lib.rs\"\n\n\
+                     [[bin]]\nname = \"tool\"\npath = \"tool.rs\"\n",
+                ),
+                (
+                    "pkg-0.1.0/ffi/lib.rs",
+                    "mod util;\n#[no_mangle] pub extern \"C\" fn f() {}\n",
+                ),
+                (
+                    "pkg-0.1.0/ffi/util.rs",
+                    "#[no_mangle] pub extern \"C\" fn g() {}\n",
+                ),
+                (
+                    "pkg-0.1.0/tool.rs",
+                    "#[no_mangle] pub extern \"C\" fn h() {}\n",
+                ),
+                (
+                    "pkg-0.1.0/build.rs",
+                    "#[no_mangle] pub extern \"C\" fn b() {}\n",
+                ),
+            ],
+        );
+        let options = ScanOptions {
+            relative_paths: true,
+            ..Default::default()
+        };
+        let parsed_files =
+            parse_crate_archive_for_no_mangle_pub_extern_c_fns(&archive_path, &options).unwrap();
+        assert_eq!(
+            exports(&parsed_files),
+            vec![
+                ("ffi/lib.rs".to_string(), "f".to_string()),
+                ("ffi/util.rs".to_string(), "g".to_string()),
+                ("tool.rs".to_string(), "h".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_without_a_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = write_archive(
+            dir.path(),
+            &[(
+                "pkg-0.1.0/src/lib.rs",
+                "#[no_mangle] pub extern \"C\" fn f() {}\n",
+            )],
+        );
+        let err = parse_crate_archive_for_no_mangle_pub_extern_c_fns(
+            &archive_path,
+            &ScanOptions::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "the archive doesn't contain `Cargo.toml`");
+
+        let archive_path = write_archive(dir.path(), &[("pkg-0.1.0/Cargo.toml", "[lib\n")]);
+        let err = parse_crate_archive_for_no_mangle_pub_extern_c_fns(
+            &archive_path,
+            &ScanOptions::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().starts_with("invalid `Cargo.toml`: "));
+
+        let err = parse_crate_archive_for_no_mangle_pub_extern_c_fns(
+            dir.path().join("missing.crate"),
+            &ScanOptions::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+
+use crate::traversal::invalid_glob;
+use crate::{
+    generate_c_header, generate_cpp_header, generate_go_bindings, generate_module_definition,
+    generate_version_script, relativize_paths, rust_source_files, scan::parse_rust_source_files,
+    CHeaderOptions, CppHeaderOptions, GoBindingsOptions, ParsedFile, ScanOptions,
+};
+
+/// The outputs that [write_build_script_outputs] should write. Relative paths are resolved against
+/// `out_dir`, absolute paths are used as is. Outputs set to [None] are not written.
+///
+/// # Example
+///
+/// `build.rs`
+///
+/// ```no_run
+/// use no_mangle_pub_export_c_fn::{write_build_script_outputs, BuildScriptOutputs};
+///
+/// fn main() -> std::io::Result<()> {
+///     write_build_script_outputs(&BuildScriptOutputs {
+///         c_header: Some("bindings.h".into()),
+///         version_script: Some("exports.map".into()),
+///         module_definition: Some("exports.def".into()),
+///         ..Default::default()
+///     })?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BuildScriptOutputs {
+    /// The directory where the outputs are written. When [None], the `OUT_DIR` environment variable is used.
+    pub out_dir: Option<PathBuf>,
+    pub scan_options: ScanOptions,
+    pub c_header: Option<PathBuf>,
+    pub c_header_options: CHeaderOptions,
+    pub cpp_header: Option<PathBuf>,
+    pub cpp_header_options: CppHeaderOptions,
+    pub go_bindings: Option<PathBuf>,
+    pub go_bindings_options: GoBindingsOptions,
+    pub version_script: Option<PathBuf>,
+    pub module_definition: Option<PathBuf>,
+}
+
+fn env_var(key: &str) -> std::io::Result<String> {
+    std::env::var(key).map_err(|err| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("environment variable `{}` is unavailable: {}", key, err),
+        )
+    })
+}
+
+// Rewriting a file with the same contents would needlessly trigger rebuilds of everything that includes it
+fn write_if_changed(path: &Path, contents: &str) -> std::io::Result<()> {
+    match std::fs::read_to_string(path) {
+        Ok(existing) if existing == contents => Ok(()),
+        _ => std::fs::write(path, contents),
+    }
+}
+
+/// The paths that the outputs depend on: the scanned files, the `src` directory, so that adding or
+/// removing a file triggers a rescan, and the ignore files when they are respected. Included files
+/// aren't listed, since files generated by the build script would make it rerun every time.
+fn rerun_if_changed_paths(
+    crate_root: &Path,
+    paths: &[PathBuf],
+    options: &ScanOptions,
+) -> Vec<PathBuf> {
+    let mut rerun_if_changed_paths = paths.to_vec();
+    rerun_if_changed_paths.push(crate_root.join("src"));
+    if options.respect_ignore_files {
+        // Ignore files in the parent directories apply as well. Missing files are left out, since
+        // Cargo would consider them changed on every build
+        crate_root
+            .ancestors()
+            .flat_map(|dir| [dir.join(".gitignore"), dir.join(".ignore")])
+            .filter(|path| path.is_file())
+            .for_each(|path| rerun_if_changed_paths.push(path));
+    }
+    rerun_if_changed_paths
+}
+
+/// Meant to be called from a [build script](https://doc.rust-lang.org/cargo/reference/build-scripts.html).
+///
+/// Scans the crate which is being built (`CARGO_MANIFEST_DIR`) with the same traversal as
+/// [parse_for_no_mangle_pub_extern_c_fns](crate::parse_for_no_mangle_pub_extern_c_fns), prints
+/// `cargo:rerun-if-changed` for every discovered Rust source file, for the `src` directory and for the
+/// respected ignore files, and writes the requested outputs.
+/// The parsed files are returned for further processing.
+///
+/// `OUT_DIR` is available to `include!` invocations unless [ScanOptions::env] sets it, so files
+/// generated by the build script are scanned as long as they are written before calling this function.
+///
+/// # Errors
+///
+/// Fails if `CARGO_MANIFEST_DIR` is unset, if `OUT_DIR` is unset and no `out_dir` was given,
+/// if a pattern in the scan options isn't a valid glob, or if an output can't be written.
+pub fn write_build_script_outputs(
+    outputs: &BuildScriptOutputs,
+) -> std::io::Result<Vec<ParsedFile>> {
+    let crate_root = env_var("CARGO_MANIFEST_DIR")?;
+    let mut scan_options = outputs.scan_options.clone();
+    // The crate sees the same `OUT_DIR` as its build script
+    if let Ok(out_dir) = std::env::var("OUT_DIR") {
+        scan_options
+            .env
+            .entry("OUT_DIR".to_string())
+            .or_insert(out_dir);
+    }
+    let paths: Vec<PathBuf> = rust_source_files(&crate_root, &scan_options)
+        .map_err(invalid_glob)?
+        .collect();
+    rerun_if_changed_paths(Path::new(&crate_root), &paths, &scan_options)
+        .iter()
+        .for_each(|path| println!("cargo:rerun-if-changed={}", path.display()));
+    let mut parsed_files = parse_rust_source_files(paths, &scan_options);
+    relativize_paths(&mut parsed_files, Path::new(&crate_root), &scan_options);
+    write_outputs(&parsed_files, outputs)?;
+    Ok(parsed_files)
+}
+
+/// Writes the requested outputs for the parsed files. Unchanged outputs aren't rewritten.
+pub(crate) fn write_outputs(
+    parsed_files: &[ParsedFile],
+    outputs: &BuildScriptOutputs,
+) -> std::io::Result<()> {
+    let out_dir = match &outputs.out_dir {
+        Some(out_dir) => out_dir.clone(),
+        None => PathBuf::from(env_var("OUT_DIR")?),
+    };
+    std::fs::create_dir_all(&out_dir)?;
+    if let Some(c_header) = &outputs.c_header {
+        write_if_changed(
+            &out_dir.join(c_header),
+            &generate_c_header(parsed_files, &outputs.c_header_options),
+        )?;
+    }
+    if let Some(cpp_header) = &outputs.cpp_header {
+        write_if_changed(
+            &out_dir.join(cpp_header),
+            &generate_cpp_header(parsed_files, &outputs.cpp_header_options),
+        )?;
+    }
+    if let Some(go_bindings) = &outputs.go_bindings {
+        write_if_changed(
+            &out_dir.join(go_bindings),
+            &generate_go_bindings(parsed_files, &outputs.go_bindings_options),
+        )?;
+    }
+    if let Some(version_script) = &outputs.version_script {
+        write_if_changed(
+            &out_dir.join(version_script),
+            &generate_version_script(parsed_files),
+        )?;
+    }
+    if let Some(module_definition) = &outputs.module_definition {
+        write_if_changed(
+            &out_dir.join(module_definition),
+            &generate_module_definition(parsed_files, None),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_for_no_mangle_pub_extern_c_fns_with_options;
+
+    #[test]
+    fn raw_idents_are_exported_without_prefix() {
+        let crate_root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(crate_root.path().join("src")).unwrap();
+        std::fs::write(
+            crate_root.path().join("src/lib.rs"),
+            "#[no_mangle]\npub extern \"C\" fn r#type() {}\n",
+        )
+        .unwrap();
+        let parsed_files = parse_for_no_mangle_pub_extern_c_fns_with_options(
+            crate_root.path().to_str().unwrap(),
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        let no_mangle_pub_export_c_fn = &parsed_files[0].no_mangle_pub_export_c_fns.0[0];
+        assert_eq!(no_mangle_pub_export_c_fn.ident, "r#type");
+        assert_eq!(no_mangle_pub_export_c_fn.symbol, "type");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        write_outputs(
+            &parsed_files,
+            &BuildScriptOutputs {
+                out_dir: Some(out_dir.path().to_path_buf()),
+                c_header: Some("bindings.h".into()),
+                version_script: Some("exports.map".into()),
+                module_definition: Some("exports.def".into()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let read = |name: &str| std::fs::read_to_string(out_dir.path().join(name)).unwrap();
+        let c_header = read("bindings.h");
+        assert!(c_header.contains("\nvoid type(void);\n"));
+        assert!(!c_header.contains("r#"));
+        assert_eq!(
+            read("exports.map"),
+            "{\n  global:\n    type;\n  local: *;\n};\n"
+        );
+        assert!(read("exports.def").contains("    type\n"));
+    }
+
+    #[test]
+    fn rerun_if_changed_covers_src_and_respected_ignore_files() {
+        let crate_root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(crate_root.path().join("src")).unwrap();
+        std::fs::write(crate_root.path().join(".gitignore"), "/target\n").unwrap();
+        let paths = vec![crate_root.path().join("src/lib.rs")];
+
+        let rerun_if_changed =
+            rerun_if_changed_paths(crate_root.path(), &paths, &ScanOptions::default());
+        assert_eq!(
+            rerun_if_changed,
+            vec![
+                crate_root.path().join("src/lib.rs"),
+                crate_root.path().join("src")
+            ]
+        );
+
+        let options = ScanOptions {
+            respect_ignore_files: true,
+            ..Default::default()
+        };
+        let rerun_if_changed = rerun_if_changed_paths(crate_root.path(), &paths, &options);
+        assert!(rerun_if_changed.contains(&crate_root.path().join(".gitignore")));
+        // Missing ignore files would make Cargo rerun the build script every time
+        assert!(!rerun_if_changed.contains(&crate_root.path().join(".ignore")));
+    }
+}
@@ -0,0 +1,1197 @@
+use std::collections::{BTreeSet, HashMap};
+
+use syn::ext::IdentExt;
+
+use crate::{CfgPredicate, FnArg, NoManglePubExportCFn, ParsedFile, TypeDef, TypeDefKind};
+
+/// Options for [generate_c_header].
+#[derive(Debug, Clone)]
+pub struct CHeaderOptions {
+    /// The name of the macro used in the `#ifndef`/`#define` include guard.
+    pub include_guard: String,
+    /// The name of the typedefs of function pointer types, where `{symbol}` is replaced by the
+    /// symbol of the function and `{param}` by the name of the parameter, `return` for the return
+    /// type. Function pointer types nested in function pointer types get the name of the outer
+    /// parameter joined with their own, e.g. `register_cb_ctx`. Unnamed parameters are called `arg0`,
+    /// `arg1`, etc.
+    pub callback_typedef_name: String,
+}
+
+impl Default for CHeaderOptions {
+    fn default() -> Self {
+        Self {
+            include_guard: "NO_MANGLE_PUB_EXPORT_C_FNS_H".to_string(),
+            callback_typedef_name: "{symbol}_{param}_fn".to_string(),
+        }
+    }
+}
+
+/// Generates a C header with declarations of all `#[no_mangle] pub export "C"` functions in the
+/// given parsed files.
+///
+/// The types are taken after resolution, i.e. type aliases are replaced by the aliased types.
+/// Primitive types, `core::ffi`/`std::os::raw`/`libc` C types, raw pointers and references are
+/// translated to their C counterparts. Other types defined in the parsed files are assumed to be
+/// `#[repr(C)]` types declared elsewhere and are emitted verbatim, except for the
+/// [opaque](TypeDef::is_opaque) types: pointers to them are emitted as pointers to incomplete structs,
+/// which are forward declared before the first declaration that refers to them, e.g.
+/// `typedef struct Engine Engine;`. Functions whose signatures contain types that can't be expressed
+/// this way (e.g. `&str`, `i128`, `char`, tuples, slices, types of other crates or opaque types passed
+/// by value) are not declared; a comment is emitted instead.
+///
+/// `extern "C"` function pointer types, including nullable ones in an `Option`, get typedefs named
+/// after [CHeaderOptions::callback_typedef_name], which are emitted before the first declaration that
+/// refers to them. Function pointer types with the same C signature share a typedef, named after the
+/// first parameter of that type. Parameters and return types that are nullable function pointers are
+/// marked with a `/* nullable */` comment. Parameters named after C or C++ keywords, e.g. `class`,
+/// are suffixed with `_`.
+///
+/// Functions that share a symbol, e.g. under mutually exclusive `#[cfg]`s, are declared once if their
+/// declarations are the same. Otherwise, each declaration is wrapped in an `#if` guard on its
+/// `#[cfg]` predicates, and so are both declarations of a function with
+/// `#[cfg_attr(..., export_name = "...")]`. Configuration options become macros that are expected to
+/// be defined by the includer of the header for the target, e.g. `windows` is `CFG_WINDOWS` and
+/// `feature = "gpu"` is `CFG_FEATURE_GPU`, i.e. `CFG_` followed by the name and the value in upper
+/// case, with other characters than letters and digits replaced by `_`. Scanning with
+/// [ScanOptions::cfg_set](crate::ScanOptions::cfg_set) avoids the guards.
+///
+/// Doc comments are turned into Doxygen comments. The `# Safety`, `# Errors` and `# Panics` sections
+/// become `@warning`, `@return` and `@note` paragraphs, other sections become `@par` paragraphs.
+/// Documented types defined in the parsed files are documented before the first declaration that
+/// refers to them, with `@struct`, `@enum`, `@union` or `@typedef`, so that they don't have to be
+/// declared in the header.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{generate_c_header, CHeaderOptions, ParsedFile};
+///
+/// let parsed_files: Vec<ParsedFile> = serde_json::from_str(r#"[{
+///     "path": "src/lib.rs",
+///     "no_mangle_pub_export_c_fns": [{
+///         "ident": "add",
+///         "symbol": "add",
+///         "inputs": [{ "pat": "a", "ty": "i32" }, { "pat": "b", "ty": "* const u8" }],
+///         "output": "i32",
+///         "doc": "Adds `b` to `a`.\n\n# Safety\n\n`b` must be valid.",
+///         "location": { "start_line": 1, "start_column": 0, "end_line": 4, "end_column": 1 }
+///     }, {
+///         "ident": "set_logger",
+///         "symbol": "set_logger",
+///         "inputs": [{ "pat": "logger", "ty": "Option < extern \"C\" fn(* const c_char) >" }],
+///         "output": null,
+///         "location": { "start_line": 6, "start_column": 0, "end_line": 6, "end_column": 70 }
+///     }]
+/// }]"#).unwrap();
+/// let header = generate_c_header(&parsed_files, &CHeaderOptions::default());
+/// assert!(header.contains("int32_t add(int32_t a, const uint8_t *b);"));
+/// assert!(header.contains(" * @warning `b` must be valid.\n */\nint32_t add("));
+/// assert!(header.contains("typedef void (*set_logger_logger_fn)(const char *);"));
+/// assert!(header.contains("void set_logger(/* nullable */ set_logger_logger_fn logger);"));
+/// ```
+pub fn generate_c_header(parsed_files: &[ParsedFile], options: &CHeaderOptions) -> String {
+    let mut header = format!(
+        "#ifndef {guard}\n\
+         #define {guard}\n\
+         \n\
+         #include <stdbool.h>\n\
+         #include <stddef.h>\n\
+         #include <stdint.h>\n\
+         \n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {{\n\
+         #endif\n\
+         \n",
+        guard = options.include_guard
+    );
+    let type_defs = type_defs_by_ident(parsed_files);
+    header.push_str(
+        &declarations(
+            parsed_files,
+            &type_defs,
+            &options.callback_typedef_name,
+            BTreeSet::new(),
+        )
+        .text,
+    );
+    header.push_str(&format!(
+        "\n\
+         #ifdef __cplusplus\n\
+         }} // extern \"C\"\n\
+         #endif\n\
+         \n\
+         #endif /* {} */\n",
+        options.include_guard
+    ));
+    header
+}
+
+/// The types defined in the parsed files by ident. The first definition wins.
+pub(crate) fn type_defs_by_ident(parsed_files: &[ParsedFile]) -> HashMap<&str, &TypeDef> {
+    let mut type_defs: HashMap<&str, &TypeDef> = HashMap::new();
+    parsed_files
+        .iter()
+        .flat_map(|parsed_file| parsed_file.type_defs.iter())
+        .for_each(|type_def| {
+            type_defs.entry(type_def.ident.as_str()).or_insert(type_def);
+        });
+    type_defs
+}
+
+/// The part of a header that declares the functions, shared by the C and the C++ headers.
+pub(crate) struct Declarations<'a> {
+    /// The declarations along with the typedefs and the docs of the types they need.
+    pub(crate) text: String,
+    /// The opaque types that are forward declared.
+    pub(crate) opaque_types: Vec<String>,
+    /// The functions that are declared.
+    pub(crate) declared_fns: Vec<DeclaredFn<'a>>,
+    /// The functions that aren't declared, along with the type that has no C equivalent.
+    pub(crate) undeclared_fns: Vec<(&'a NoManglePubExportCFn, String)>,
+    /// The symbols whose declarations are wrapped in `#if` guards.
+    pub(crate) guarded_symbols: BTreeSet<&'a str>,
+}
+
+/// A function declared in a header along with the C types in its signature.
+pub(crate) struct DeclaredFn<'a> {
+    pub(crate) no_mangle_pub_export_c_fn: &'a NoManglePubExportCFn,
+    /// E.g. `const uint8_t *`, with the `/* nullable */` marks.
+    pub(crate) input_c_types: Vec<String>,
+    pub(crate) output_c_type: String,
+}
+
+impl DeclaredFn<'_> {
+    /// The declaration of the function under `symbol`, which is [NoManglePubExportCFn::symbol] or
+    /// [NoManglePubExportCFn::fallback_symbol].
+    fn declaration(&self, symbol: &str) -> String {
+        let inputs: Vec<String> = self
+            .no_mangle_pub_export_c_fn
+            .inputs
+            .iter()
+            .zip(&self.input_c_types)
+            .map(|(fn_arg, c_type)| match c_param_name(&fn_arg.pat) {
+                Some(name) => c_declarator(c_type, &name),
+                None => c_type.clone(),
+            })
+            .collect();
+        let inputs = if inputs.is_empty() {
+            "void".to_string()
+        } else {
+            inputs.join(", ")
+        };
+        format!("{}({});", c_declarator(&self.output_c_type, symbol), inputs)
+    }
+}
+
+/// The symbols under which the function may be exported, along with the conditions besides its own
+/// `#[cfg]`s and `#[cfg_attr(..., no_mangle)]`s: both symbols of a conditional `export_name`, or the
+/// only symbol.
+pub(crate) fn exported_symbols(
+    no_mangle_pub_export_c_fn: &NoManglePubExportCFn,
+) -> Vec<(&str, Option<CfgPredicate>)> {
+    let symbol = no_mangle_pub_export_c_fn.symbol.as_str();
+    match (
+        &no_mangle_pub_export_c_fn.symbol_cfg,
+        &no_mangle_pub_export_c_fn.fallback_symbol,
+    ) {
+        (Some(symbol_cfg), Some(fallback_symbol)) => vec![
+            (symbol, Some(symbol_cfg.clone())),
+            (
+                fallback_symbol.as_str(),
+                Some(CfgPredicate::Not(Box::new(symbol_cfg.clone()))),
+            ),
+        ],
+        _ => vec![(symbol, None)],
+    }
+}
+
+/// The condition of an `#if` guard, e.g. `defined(CFG_WINDOWS) && !defined(CFG_FEATURE_GPU)` for
+/// `all(windows, not(feature = "gpu"))`.
+fn c_condition(predicate: &CfgPredicate) -> String {
+    // Operands that are lists with the other operator are parenthesized
+    fn operand(predicate: &CfgPredicate, operator: &str) -> String {
+        match predicate {
+            CfgPredicate::All(predicates) if predicates.len() > 1 && operator != " && " => {
+                format!("({})", c_condition(predicate))
+            }
+            CfgPredicate::Any(predicates) if predicates.len() > 1 && operator != " || " => {
+                format!("({})", c_condition(predicate))
+            }
+            _ => c_condition(predicate),
+        }
+    }
+    fn list(predicates: &[CfgPredicate], operator: &str, empty: &str) -> String {
+        match predicates {
+            [] => empty.to_string(),
+            _ => predicates
+                .iter()
+                .map(|predicate| operand(predicate, operator))
+                .collect::<Vec<String>>()
+                .join(operator),
+        }
+    }
+    let defined = |words: &[&str]| {
+        let name: String = words
+            .join("_")
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect();
+        format!("defined(CFG_{})", name)
+    };
+    match predicate {
+        CfgPredicate::Name(name) => defined(&[name]),
+        CfgPredicate::KeyValue(key, value) => defined(&[key, value]),
+        CfgPredicate::All(predicates) => list(predicates, " && ", "1"),
+        CfgPredicate::Any(predicates) => list(predicates, " || ", "0"),
+        CfgPredicate::Not(predicate) => format!("!{}", operand(predicate, "!")),
+    }
+}
+
+/// A function as it is declared, before the declarations are emitted.
+enum Translated<'a> {
+    Declared {
+        declared_fn: DeclaredFn<'a>,
+        /// The docs of the types it refers to, the typedefs it needs and its own doc.
+        prelude: String,
+    },
+    Unsupported(&'a NoManglePubExportCFn, String),
+}
+
+/// Declares the functions in the parsed files. The types in `documented_types` are documented
+/// elsewhere.
+///
+/// Functions that share a symbol, e.g. under mutually exclusive `#[cfg]`s, are declared once if their
+/// declarations are the same. Otherwise, as well as for both symbols of a conditional `export_name`,
+/// each declaration is wrapped in an `#if` guard on its `#[cfg]` predicates.
+pub(crate) fn declarations<'a, 'b>(
+    parsed_files: &'a [ParsedFile],
+    type_defs: &'b HashMap<&'b str, &'b TypeDef>,
+    callback_typedef_name: &'b str,
+    mut documented_types: BTreeSet<&'b str>,
+) -> Declarations<'a> {
+    let mut c_types = CTypes::new(callback_typedef_name, type_defs);
+    let translated: Vec<Translated> = parsed_files
+        .iter()
+        .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+        .map(
+            |no_mangle_pub_export_c_fn| match c_types.c_declaration(no_mangle_pub_export_c_fn) {
+                Ok(declared_fn) => {
+                    let mut prelude = String::new();
+                    referenced_idents(no_mangle_pub_export_c_fn)
+                        .iter()
+                        .filter_map(|ident| type_defs.get(ident.as_str()))
+                        .filter(|type_def| documented_types.insert(type_def.ident.as_str()))
+                        .for_each(|type_def| {
+                            if let Some(doc) = &type_def.doc {
+                                prelude
+                                    .push_str(&doxygen_comment(doc, Some(&type_command(type_def))));
+                            }
+                        });
+                    prelude.push_str(&c_types.emit_typedefs());
+                    if let Some(doc) = &no_mangle_pub_export_c_fn.doc {
+                        prelude.push_str(&doxygen_comment(doc, None));
+                    }
+                    Translated::Declared {
+                        declared_fn,
+                        prelude,
+                    }
+                }
+                Err(unsupported) => Translated::Unsupported(no_mangle_pub_export_c_fn, unsupported),
+            },
+        )
+        .collect();
+
+    // The distinct declarations of each symbol
+    let mut declarations_by_symbol: HashMap<&str, BTreeSet<String>> = HashMap::new();
+    translated.iter().for_each(|translated| {
+        if let Translated::Declared { declared_fn, .. } = translated {
+            exported_symbols(declared_fn.no_mangle_pub_export_c_fn)
+                .into_iter()
+                .for_each(|(symbol, _)| {
+                    declarations_by_symbol
+                        .entry(symbol)
+                        .or_default()
+                        .insert(declared_fn.declaration(symbol));
+                });
+        }
+    });
+
+    let mut text = String::new();
+    let mut declared_symbols: BTreeSet<&str> = BTreeSet::new();
+    let mut guarded_symbols: BTreeSet<&str> = BTreeSet::new();
+    let mut declared_fns = Vec::new();
+    let mut undeclared_fns = Vec::new();
+    translated.into_iter().for_each(|translated| {
+        let (declared_fn, prelude) = match translated {
+            Translated::Declared {
+                declared_fn,
+                prelude,
+            } => (declared_fn, prelude),
+            Translated::Unsupported(no_mangle_pub_export_c_fn, unsupported) => {
+                text.push_str(&format!(
+                    "/* `{}` is not declared: `{}` has no C equivalent */\n",
+                    no_mangle_pub_export_c_fn.symbol, unsupported
+                ));
+                undeclared_fns.push((no_mangle_pub_export_c_fn, unsupported));
+                return;
+            }
+        };
+        let no_mangle_pub_export_c_fn = declared_fn.no_mangle_pub_export_c_fn;
+        text.push_str(&prelude);
+        exported_symbols(no_mangle_pub_export_c_fn)
+            .into_iter()
+            .for_each(|(symbol, symbol_cfg)| {
+                let declaration = declared_fn.declaration(symbol);
+                let is_ambiguous = declarations_by_symbol[symbol].len() > 1;
+                if symbol_cfg.is_none() && !is_ambiguous {
+                    if declared_symbols.insert(symbol) {
+                        text.push_str(&declaration);
+                        text.push('\n');
+                    }
+                    return;
+                }
+                let cfgs: Vec<CfgPredicate> = no_mangle_pub_export_c_fn
+                    .cfg
+                    .iter()
+                    .chain(&no_mangle_pub_export_c_fn.export_cfg)
+                    .chain(&symbol_cfg)
+                    .cloned()
+                    .collect();
+                guarded_symbols.insert(symbol);
+                text.push_str(&format!(
+                    "#if {}\n{}\n#endif\n",
+                    c_condition(&CfgPredicate::All(cfgs)),
+                    declaration
+                ));
+            });
+        declared_fns.push(declared_fn);
+    });
+    Declarations {
+        text,
+        opaque_types: c_types.opaque_types,
+        declared_fns,
+        undeclared_fns,
+        guarded_symbols,
+    }
+}
+
+/// The resolved type, if types were resolved, since type aliases aren't declared in the header.
+pub(crate) fn input_ty(fn_arg: &FnArg) -> &str {
+    fn_arg.resolved_ty.as_deref().unwrap_or(&fn_arg.ty)
+}
+
+/// The resolved return type, the same as [input_ty].
+pub(crate) fn output_ty(no_mangle_pub_export_c_fn: &NoManglePubExportCFn) -> Option<&str> {
+    no_mangle_pub_export_c_fn
+        .resolved_output
+        .as_deref()
+        .or(no_mangle_pub_export_c_fn.output.as_deref())
+}
+
+/// A typedef of a function pointer type.
+struct CallbackTypedef {
+    /// The C signature, e.g. `void (*)(void *, int32_t)`, by which typedefs are shared.
+    signature: String,
+    name: String,
+    /// E.g. `typedef void (*register_cb_fn)(void *ctx, int32_t event);`
+    declaration: String,
+}
+
+/// Translates Rust types into C types and declares typedefs of opaque types and of function pointer
+/// types on the way.
+struct CTypes<'a> {
+    /// See [CHeaderOptions::callback_typedef_name].
+    callback_typedef_name: &'a str,
+    /// The types defined in the parsed files by ident.
+    type_defs: &'a HashMap<&'a str, &'a TypeDef>,
+    /// The opaque types that have been forward declared.
+    opaque_types: Vec<String>,
+    callback_typedefs: Vec<CallbackTypedef>,
+    /// The number of opaque types and of typedefs that have been emitted, the others are needed by
+    /// the declaration being translated.
+    emitted_opaque_types: usize,
+    emitted_callback_typedefs: usize,
+}
+
+impl<'a> CTypes<'a> {
+    fn new(callback_typedef_name: &'a str, type_defs: &'a HashMap<&'a str, &'a TypeDef>) -> Self {
+        Self {
+            callback_typedef_name,
+            type_defs,
+            opaque_types: Vec::new(),
+            callback_typedefs: Vec::new(),
+            emitted_opaque_types: 0,
+            emitted_callback_typedefs: 0,
+        }
+    }
+
+    /// Returns the declared function or the type that has no C equivalent. The typedefs needed by
+    /// the declaration are returned by [CTypes::emit_typedefs].
+    fn c_declaration<'f>(
+        &mut self,
+        no_mangle_pub_export_c_fn: &'f NoManglePubExportCFn,
+    ) -> Result<DeclaredFn<'f>, String> {
+        let declaration = self.translate_declaration(no_mangle_pub_export_c_fn);
+        if declaration.is_err() {
+            // The typedefs needed by the parameters that could be translated aren't needed anymore
+            self.opaque_types.truncate(self.emitted_opaque_types);
+            self.callback_typedefs
+                .truncate(self.emitted_callback_typedefs);
+        }
+        declaration
+    }
+
+    fn translate_declaration<'f>(
+        &mut self,
+        no_mangle_pub_export_c_fn: &'f NoManglePubExportCFn,
+    ) -> Result<DeclaredFn<'f>, String> {
+        let symbol = &no_mangle_pub_export_c_fn.symbol;
+        let output = match output_ty(no_mangle_pub_export_c_fn) {
+            None => "void".to_string(),
+            Some(ty) => self
+                .c_param_type(ty, symbol, "return")
+                .ok_or_else(|| ty.to_string())?,
+        };
+        let input_c_types = no_mangle_pub_export_c_fn
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, fn_arg)| {
+                let param = c_param_name(&fn_arg.pat).unwrap_or_else(|| format!("arg{}", index));
+                self.c_param_type(input_ty(fn_arg), symbol, &param)
+                    .ok_or_else(|| input_ty(fn_arg).to_string())
+            })
+            .collect::<Result<Vec<String>, String>>()?;
+        Ok(DeclaredFn {
+            no_mangle_pub_export_c_fn,
+            input_c_types,
+            output_c_type: output,
+        })
+    }
+
+    /// The typedefs needed by the last declaration, one per line. Opaque types come first, since
+    /// function pointer types may refer to them.
+    fn emit_typedefs(&mut self) -> String {
+        let typedefs = self.opaque_types[self.emitted_opaque_types..]
+            .iter()
+            .map(|ident| format!("typedef struct {0} {0};\n", ident))
+            .chain(
+                self.callback_typedefs[self.emitted_callback_typedefs..]
+                    .iter()
+                    .map(|callback_typedef| format!("{}\n", callback_typedef.declaration)),
+            )
+            .collect();
+        self.emitted_opaque_types = self.opaque_types.len();
+        self.emitted_callback_typedefs = self.callback_typedefs.len();
+        typedefs
+    }
+
+    /// The type of a parameter or of a return value, marked if it's a nullable function pointer.
+    fn c_param_type(&mut self, ty: &str, symbol: &str, param: &str) -> Option<String> {
+        let ty = syn::parse_str::<syn::Type>(ty).ok()?;
+        let c_type = self.c_type(&ty, symbol, param)?;
+        Some(match is_nullable_fn_pointer(&ty) {
+            true => format!("/* nullable */ {}", c_type),
+            false => c_type,
+        })
+    }
+
+    /// Translates a Rust type into the C type, if possible. `symbol` and `param` name the typedefs
+    /// of function pointer types.
+    fn c_type(&mut self, ty: &syn::Type, symbol: &str, param: &str) -> Option<String> {
+        match ty {
+            syn::Type::Paren(type_paren) => self.c_type(&type_paren.elem, symbol, param),
+            syn::Type::Group(type_group) => self.c_type(&type_group.elem, symbol, param),
+            syn::Type::Tuple(type_tuple) if type_tuple.elems.is_empty() => Some("void".to_string()),
+            syn::Type::Never(_) => Some("void".to_string()),
+            syn::Type::Ptr(type_ptr) => {
+                let pointee = self.c_pointee_type(&type_ptr.elem, symbol, param)?;
+                Some(c_pointer(&pointee, type_ptr.const_token.is_some()))
+            }
+            syn::Type::Reference(type_reference) => {
+                let pointee = self.c_pointee_type(&type_reference.elem, symbol, param)?;
+                Some(c_pointer(&pointee, type_reference.mutability.is_none()))
+            }
+            syn::Type::BareFn(type_bare_fn) => self.callback_typedef(type_bare_fn, symbol, param),
+            syn::Type::Path(type_path) if type_path.qself.is_none() => {
+                let segment = type_path.path.segments.last()?;
+                match &segment.arguments {
+                    // Opaque types can't be passed by value
+                    syn::PathArguments::None => match self.opaque_type(ty) {
+                        Some(_) => None,
+                        None => {
+                            let ident = segment.ident.to_string();
+                            c_type_of_ident(&ident).map(str::to_string).or_else(|| {
+                                self.type_defs.contains_key(ident.as_str()).then_some(ident)
+                            })
+                        }
+                    },
+                    syn::PathArguments::AngleBracketed(arguments) => {
+                        let argument = match arguments.args.first() {
+                            Some(syn::GenericArgument::Type(argument))
+                                if arguments.args.len() == 1 =>
+                            {
+                                argument
+                            }
+                            _ => return None,
+                        };
+                        match segment.ident.to_string().as_str() {
+                            "NonNull" | "Box" => {
+                                let pointee = self.c_pointee_type(argument, symbol, param)?;
+                                Some(c_pointer(&pointee, false))
+                            }
+                            // Option<&T>, Option<NonNull<T>>, Option<extern "C" fn()>, etc. are nullable pointers
+                            "Option" => self
+                                .c_type(argument, symbol, param)
+                                .filter(|c_type| c_type.ends_with('*') || is_fn_pointer(argument)),
+                            _ => None,
+                        }
+                    }
+                    syn::PathArguments::Parenthesized(_) => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The type pointed to, which may be an opaque type, forward declared if needed.
+    fn c_pointee_type(&mut self, ty: &syn::Type, symbol: &str, param: &str) -> Option<String> {
+        match self.opaque_type(ty) {
+            Some(ident) => {
+                if !self.opaque_types.contains(&ident) {
+                    self.opaque_types.push(ident.clone());
+                }
+                Some(ident)
+            }
+            None => self.c_type(ty, symbol, param),
+        }
+    }
+
+    /// The ident of the type if it's an [opaque](TypeDef::is_opaque) type defined in the parsed files.
+    fn opaque_type(&self, ty: &syn::Type) -> Option<String> {
+        match ty {
+            syn::Type::Paren(type_paren) => self.opaque_type(&type_paren.elem),
+            syn::Type::Group(type_group) => self.opaque_type(&type_group.elem),
+            syn::Type::Path(type_path) if type_path.qself.is_none() => {
+                let segment = type_path.path.segments.last()?;
+                let ident = segment.ident.to_string();
+                let type_def = self.type_defs.get(ident.as_str())?;
+                (segment.arguments.is_empty() && type_def.is_opaque()).then_some(ident)
+            }
+            _ => None,
+        }
+    }
+
+    /// The name of the typedef of an `extern "C"` function pointer type, declared if needed.
+    fn callback_typedef(
+        &mut self,
+        type_bare_fn: &syn::TypeBareFn,
+        symbol: &str,
+        param: &str,
+    ) -> Option<String> {
+        let is_c_abi = type_bare_fn.abi.as_ref().map_or(false, |abi| {
+            abi.name.as_ref().map_or(true, |name| {
+                matches!(name.value().as_str(), "C" | "C-unwind")
+            })
+        });
+        if !is_c_abi {
+            return None;
+        }
+        let output = match &type_bare_fn.output {
+            syn::ReturnType::Default => "void".to_string(),
+            syn::ReturnType::Type(_, ty) => {
+                self.c_type(ty, symbol, &format!("{}_return", param))?
+            }
+        };
+        let mut params = Vec::new();
+        let mut named_params = Vec::new();
+        for (index, bare_fn_arg) in type_bare_fn.inputs.iter().enumerate() {
+            let name = bare_fn_arg
+                .name
+                .as_ref()
+                .filter(|(ident, _)| ident != "_")
+                .map(|(ident, _)| c_ident(ident));
+            let nested_param = match &name {
+                Some(name) => format!("{}_{}", param, name),
+                None => format!("{}_arg{}", param, index),
+            };
+            let c_type = self.c_type(&bare_fn_arg.ty, symbol, &nested_param)?;
+            named_params.push(match &name {
+                Some(name) => c_declarator(&c_type, name),
+                None => c_type.clone(),
+            });
+            params.push(c_type);
+        }
+        if type_bare_fn.variadic.is_some() {
+            params.push("...".to_string());
+            named_params.push("...".to_string());
+        }
+        let c_params = |params: Vec<String>| match params.is_empty() {
+            true => "void".to_string(),
+            false => params.join(", "),
+        };
+        let signature = format!("{}({})", c_declarator(&output, "(*)"), c_params(params));
+        if let Some(callback_typedef) = self
+            .callback_typedefs
+            .iter()
+            .find(|callback_typedef| callback_typedef.signature == signature)
+        {
+            return Some(callback_typedef.name.clone());
+        }
+        let name = self
+            .callback_typedef_name
+            .replace("{symbol}", symbol)
+            .replace("{param}", param);
+        // Names only clash if the naming doesn't tell the functions or the parameters apart
+        let name = std::iter::once(name.clone())
+            .chain((2..).map(|suffix| format!("{}_{}", name, suffix)))
+            .find(|name| {
+                self.callback_typedefs
+                    .iter()
+                    .all(|callback_typedef| &callback_typedef.name != name)
+            })?;
+        self.callback_typedefs.push(CallbackTypedef {
+            signature,
+            declaration: format!(
+                "typedef {}({});",
+                c_declarator(&output, &format!("(*{})", name)),
+                c_params(named_params)
+            ),
+            name: name.clone(),
+        });
+        Some(name)
+    }
+}
+
+/// The last segments of the paths in the signature, e.g. `Handle` for `Option<&mut ffi::Handle>`,
+/// including the ones in function pointer types.
+pub(crate) fn referenced_idents(no_mangle_pub_export_c_fn: &NoManglePubExportCFn) -> Vec<String> {
+    fn visit(ty: &syn::Type, idents: &mut Vec<String>) {
+        match ty {
+            syn::Type::Paren(type_paren) => visit(&type_paren.elem, idents),
+            syn::Type::Group(type_group) => visit(&type_group.elem, idents),
+            syn::Type::Ptr(type_ptr) => visit(&type_ptr.elem, idents),
+            syn::Type::Reference(type_reference) => visit(&type_reference.elem, idents),
+            syn::Type::BareFn(type_bare_fn) => {
+                type_bare_fn
+                    .inputs
+                    .iter()
+                    .for_each(|bare_fn_arg| visit(&bare_fn_arg.ty, idents));
+                if let syn::ReturnType::Type(_, output) = &type_bare_fn.output {
+                    visit(output, idents);
+                }
+            }
+            syn::Type::Path(type_path) => {
+                if let Some(segment) = type_path.path.segments.last() {
+                    idents.push(segment.ident.to_string());
+                    if let syn::PathArguments::AngleBracketed(arguments) = &segment.arguments {
+                        arguments.args.iter().for_each(|argument| {
+                            if let syn::GenericArgument::Type(argument) = argument {
+                                visit(argument, idents)
+                            }
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut idents = Vec::new();
+    no_mangle_pub_export_c_fn
+        .inputs
+        .iter()
+        .map(input_ty)
+        .chain(output_ty(no_mangle_pub_export_c_fn))
+        .filter_map(|ty| syn::parse_str::<syn::Type>(ty).ok())
+        .for_each(|ty| visit(&ty, &mut idents));
+    idents
+}
+
+/// The Doxygen command that documents a type declared elsewhere, e.g. `@struct Handle`.
+fn type_command(type_def: &TypeDef) -> String {
+    let command = match type_def.kind {
+        TypeDefKind::Struct => "struct",
+        TypeDefKind::Enum => "enum",
+        TypeDefKind::Union => "union",
+        TypeDefKind::TypeAlias => "typedef",
+    };
+    format!("@{} {}", command, type_def.ident)
+}
+
+/// Turns a doc comment into a Doxygen comment, e.g. `# Safety` sections into `@warning` paragraphs.
+/// `command` is put on the first line, e.g. `@struct Handle`.
+pub(crate) fn doxygen_comment(doc: &str, command: Option<&str>) -> String {
+    let mut lines: Vec<String> = command.map(str::to_string).into_iter().collect();
+    // The tag of the section whose first paragraph hasn't started yet
+    let mut section_tag: Option<&str> = None;
+    let mut is_code_block = false;
+    // Comments can't be nested
+    let doc = doc.replace("*/", "*\\/");
+    doc.lines().for_each(|line| {
+        if line.trim_start().starts_with("```") {
+            is_code_block = !is_code_block;
+        }
+        let heading = match is_code_block {
+            true => None,
+            false => line.strip_prefix("# ").map(str::trim),
+        };
+        match heading {
+            Some(heading) => {
+                let tag = match heading {
+                    "Safety" => "@warning",
+                    "Errors" => "@return",
+                    "Panics" => "@note",
+                    _ => {
+                        lines.push(format!("@par {}", heading));
+                        ""
+                    }
+                };
+                section_tag = Some(tag);
+            }
+            // Paragraph commands end at blank lines
+            None if section_tag.is_some() && line.trim().is_empty() => {}
+            None => match section_tag.take() {
+                Some(tag) if !tag.is_empty() => lines.push(format!("{} {}", tag, line)),
+                _ => lines.push(line.to_string()),
+            },
+        }
+    });
+    let mut comment = "/**\n".to_string();
+    lines.iter().for_each(|line| match line.is_empty() {
+        true => comment.push_str(" *\n"),
+        false => comment.push_str(&format!(" * {}\n", line)),
+    });
+    comment.push_str(" */\n");
+    comment
+}
+
+/// Joins a C type and a name, e.g. `int32_t` and `a` into `int32_t a` but `uint8_t *` and `b` into `uint8_t *b`.
+pub(crate) fn c_declarator(c_type: &str, name: &str) -> String {
+    if c_type.ends_with('*') {
+        format!("{}{}", c_type, name)
+    } else {
+        format!("{} {}", c_type, name)
+    }
+}
+
+/// Parameters named after C or C++ keywords, e.g. `class` or `new`, are suffixed with `_`. The
+/// alternative operator spellings, e.g. `and`, and the C23 keywords count as keywords.
+fn c_ident(ident: &syn::Ident) -> String {
+    let name = ident.unraw().to_string();
+    match name.as_str() {
+        "alignas" | "alignof" | "and" | "and_eq" | "asm" | "auto" | "bitand" | "bitor" | "bool"
+        | "break" | "case" | "catch" | "char" | "char16_t" | "char32_t" | "char8_t" | "class"
+        | "co_await" | "co_return" | "co_yield" | "compl" | "concept" | "const" | "const_cast"
+        | "consteval" | "constexpr" | "constinit" | "continue" | "decltype" | "default"
+        | "delete" | "do" | "double" | "dynamic_cast" | "else" | "enum" | "explicit" | "export"
+        | "extern" | "false" | "float" | "for" | "friend" | "goto" | "if" | "inline" | "int"
+        | "long" | "mutable" | "namespace" | "new" | "noexcept" | "not" | "not_eq" | "nullptr"
+        | "operator" | "or" | "or_eq" | "private" | "protected" | "public" | "register"
+        | "reinterpret_cast" | "requires" | "restrict" | "return" | "short" | "signed"
+        | "sizeof" | "static" | "static_assert" | "static_cast" | "struct" | "switch"
+        | "template" | "this" | "thread_local" | "throw" | "true" | "try" | "typedef"
+        | "typeid" | "typename" | "typeof" | "typeof_unqual" | "union" | "unsigned" | "using"
+        | "virtual" | "void" | "volatile" | "wchar_t" | "while" | "xor" | "xor_eq" => {
+            format!("{}_", name)
+        }
+        _ => name,
+    }
+}
+
+pub(crate) fn c_param_name(pat: &str) -> Option<String> {
+    match syn::parse_str::<syn::Pat>(pat) {
+        Ok(syn::Pat::Ident(pat_ident)) => Some(c_ident(&pat_ident.ident)),
+        _ => None,
+    }
+}
+
+fn is_fn_pointer(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Paren(type_paren) => is_fn_pointer(&type_paren.elem),
+        syn::Type::Group(type_group) => is_fn_pointer(&type_group.elem),
+        syn::Type::BareFn(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether the type is `Option<extern "C" fn(...)>`, i.e. a function pointer that may be null.
+fn is_nullable_fn_pointer(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Paren(type_paren) => is_nullable_fn_pointer(&type_paren.elem),
+        syn::Type::Group(type_group) => is_nullable_fn_pointer(&type_group.elem),
+        syn::Type::Path(type_path) => type_path.path.segments.last().map_or(false, |segment| {
+            match &segment.arguments {
+                syn::PathArguments::AngleBracketed(arguments) if segment.ident == "Option" => {
+                    matches!(arguments.args.first(), Some(syn::GenericArgument::Type(argument)) if is_fn_pointer(argument))
+                }
+                _ => false,
+            }
+        }),
+        _ => false,
+    }
+}
+
+fn c_pointer(pointee: &str, is_const: bool) -> String {
+    match (is_const, pointee.ends_with('*')) {
+        (false, _) => format!("{}*", c_pointee(pointee)),
+        (true, false) => format!("const {} *", pointee),
+        (true, true) => format!("{}const *", pointee),
+    }
+}
+
+fn c_pointee(pointee: &str) -> String {
+    if pointee.ends_with('*') {
+        pointee.to_string()
+    } else {
+        format!("{} ", pointee)
+    }
+}
+
+/// The C type of a primitive or a C type alias, e.g. `int32_t` for `i32` and `int` for `c_int`.
+/// Primitives without a C counterpart, e.g. `i128` or `char`, have none.
+pub(crate) fn c_type_of_ident(ident: &str) -> Option<&'static str> {
+    Some(match ident {
+        "i8" => "int8_t",
+        "i16" => "int16_t",
+        "i32" => "int32_t",
+        "i64" => "int64_t",
+        "isize" => "intptr_t",
+        "u8" => "uint8_t",
+        "u16" => "uint16_t",
+        "u32" => "uint32_t",
+        "u64" => "uint64_t",
+        "usize" => "uintptr_t",
+        "f32" | "c_float" => "float",
+        "f64" | "c_double" => "double",
+        "bool" => "bool",
+        "c_char" => "char",
+        "c_schar" => "signed char",
+        "c_uchar" => "unsigned char",
+        "c_short" => "short",
+        "c_ushort" => "unsigned short",
+        "c_int" => "int",
+        "c_uint" => "unsigned int",
+        "c_long" => "long",
+        "c_ulong" => "unsigned long",
+        "c_longlong" => "long long",
+        "c_ulonglong" => "unsigned long long",
+        "c_void" => "void",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(source: &str, options: &CHeaderOptions) -> String {
+        let parsed_files = crate::parse_sources_for_no_mangle_pub_extern_c_fns(
+            vec![("src/lib.rs", source)],
+            &crate::ScanOptions::default(),
+        );
+        generate_c_header(&parsed_files, options)
+    }
+
+    #[test]
+    fn suffixes_keyword_params() {
+        let header = header(
+            r#"
+            #[no_mangle]
+            pub extern "C" fn f(class: i32, r#new: u8, int: *const u8, r#type: bool, value: u32) {}
+            #[no_mangle]
+            pub extern "C" fn g(cb: extern "C" fn(default: i32, this: *mut u8, _: u8)) {}
+            "#,
+            &CHeaderOptions::default(),
+        );
+        assert!(header.contains(
+            "void f(int32_t class_, uint8_t new_, const uint8_t *int_, bool type, uint32_t value);\n"
+        ));
+        assert!(header
+            .contains("typedef void (*g_cb_fn)(int32_t default_, uint8_t *this_, uint8_t);\n"));
+    }
+
+    /// The non-empty lines between the opening and the closing `extern "C"` block.
+    fn declaration_lines(header: &str) -> Vec<&str> {
+        let start = header.find("#endif\n").unwrap() + "#endif\n".len();
+        let end = header.rfind("#ifdef __cplusplus").unwrap();
+        header[start..end]
+            .lines()
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn emits_callback_typedefs() {
+        let header = header(
+            r#"
+            pub type Log = extern "C" fn(level: i32, message: *const c_char);
+            #[no_mangle]
+            pub extern "C" fn set_logger(logger: Option<Log>, fallback: Log) {}
+            #[no_mangle]
+            pub extern "C" fn register(
+                cb: extern "C" fn(ctx: extern "C" fn(*mut u8) -> bool, data: *mut u8) -> extern "C" fn(),
+            ) {
+            }
+            #[no_mangle]
+            pub extern "C" fn printf_cb(cb: unsafe extern "C" fn(fmt: *const c_char, ...)) {}
+            #[no_mangle]
+            pub extern "C" fn rust_abi(cb: fn(i32)) {}
+            #[no_mangle]
+            pub extern "C" fn on_event(
+                cb: Option<unsafe extern "C-unwind" fn(i32) -> i32>,
+            ) -> Option<extern "C" fn(i32) -> i32> {
+            }
+            "#,
+            &CHeaderOptions {
+                callback_typedef_name: "{symbol}_{param}_cb".to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            declaration_lines(&header),
+            [
+                "typedef void (*set_logger_logger_cb)(int32_t level, const char *message);",
+                "void set_logger(/* nullable */ set_logger_logger_cb logger, set_logger_logger_cb fallback);",
+                "typedef void (*register_cb_return_cb)(void);",
+                "typedef bool (*register_cb_ctx_cb)(uint8_t *);",
+                "typedef register_cb_return_cb (*register_cb_cb)(register_cb_ctx_cb ctx, uint8_t *data);",
+                "void register(register_cb_cb cb);",
+                "typedef void (*printf_cb_cb_cb)(const char *fmt, ...);",
+                "void printf_cb(printf_cb_cb_cb cb);",
+                "/* `rust_abi` is not declared: `fn (i32)` has no C equivalent */",
+                "typedef int32_t (*on_event_return_cb)(int32_t);",
+                "/* nullable */ on_event_return_cb on_event(/* nullable */ on_event_return_cb cb);",
+            ]
+        );
+    }
+
+    #[test]
+    fn suffixes_clashing_callback_typedef_names() {
+        let header = header(
+            r#"
+            #[no_mangle]
+            pub extern "C" fn f(cb: extern "C" fn(i32)) {}
+            #[no_mangle]
+            pub extern "C" fn g(cb: extern "C" fn(u8)) {}
+            #[no_mangle]
+            pub extern "C" fn h(cb: extern "C" fn(value: i32)) {}
+            "#,
+            &CHeaderOptions {
+                callback_typedef_name: "{param}_fn".to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            declaration_lines(&header),
+            [
+                "typedef void (*cb_fn)(int32_t);",
+                "void f(cb_fn cb);",
+                "typedef void (*cb_fn_2)(uint8_t);",
+                "void g(cb_fn_2 cb);",
+                "void h(cb_fn cb);",
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_declares_opaque_types() {
+        let header = header(
+            r#"
+            /// An engine.
+            pub struct Engine {
+                inner: Vec<u8>,
+            }
+            pub struct Config {
+                _private: [u8; 0],
+            }
+            #[repr(C)]
+            pub struct Point {
+                x: i32,
+                y: i32,
+            }
+            #[no_mangle]
+            pub extern "C" fn point_zero() -> Point {}
+            #[no_mangle]
+            pub extern "C" fn engine_new(config: *const Config, origin: Point) -> *mut Engine {}
+            #[no_mangle]
+            pub extern "C" fn engine_free(engine: Option<&mut Engine>) {}
+            #[no_mangle]
+            pub extern "C" fn engine_by_value(engine: Engine) {}
+            #[no_mangle]
+            pub extern "C" fn engine_run(engine: &mut Engine, cb: extern "C" fn(*mut Engine, *mut Config)) {}
+            "#,
+            &CHeaderOptions::default(),
+        );
+        assert_eq!(
+            declaration_lines(&header),
+            [
+                "Point point_zero(void);",
+                "/**",
+                " * @struct Engine",
+                " * An engine.",
+                " */",
+                "typedef struct Engine Engine;",
+                "typedef struct Config Config;",
+                "Engine *engine_new(const Config *config, Point origin);",
+                "void engine_free(Engine *engine);",
+                "/* `engine_by_value` is not declared: `crate :: Engine` has no C equivalent */",
+                "typedef void (*engine_run_cb_fn)(Engine *, Config *);",
+                "void engine_run(Engine *engine, engine_run_cb_fn cb);",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_forward_declares_opaque_types_of_declared_fns() {
+        let header = header(
+            r#"
+            pub struct Engine;
+            #[no_mangle]
+            pub extern "C" fn engine_with_slice(engine: *mut Engine, data: &[u8]) {}
+            "#,
+            &CHeaderOptions::default(),
+        );
+        assert_eq!(
+            declaration_lines(&header),
+            ["/* `engine_with_slice` is not declared: `& [u8]` has no C equivalent */"]
+        );
+    }
+
+    #[test]
+    fn does_not_declare_rust_only_types() {
+        let header = header(
+            r#"
+            #[repr(C)]
+            pub struct Point {
+                x: i32,
+                y: i32,
+            }
+            #[no_mangle]
+            pub extern "C" fn s(x: &str, y: i128) -> u128 {}
+            #[no_mangle]
+            pub extern "C" fn t(x: &str, y: i128) {}
+            #[no_mangle]
+            pub extern "C" fn u(y: i128) {}
+            #[no_mangle]
+            pub extern "C" fn c(c: char) {}
+            #[no_mangle]
+            pub extern "C" fn owned(name: String) {}
+            #[no_mangle]
+            pub extern "C" fn foreign(tv: *const libc::timeval) {}
+            #[no_mangle]
+            pub extern "C" fn point(p: Point, x: core::ffi::c_int, data: *mut c_void) -> f64 {}
+            "#,
+            &CHeaderOptions::default(),
+        );
+        assert_eq!(
+            declaration_lines(&header),
+            [
+                "/* `s` is not declared: `u128` has no C equivalent */",
+                "/* `t` is not declared: `& str` has no C equivalent */",
+                "/* `u` is not declared: `i128` has no C equivalent */",
+                "/* `c` is not declared: `char` has no C equivalent */",
+                "/* `owned` is not declared: `String` has no C equivalent */",
+                "/* `foreign` is not declared: `* const libc :: timeval` has no C equivalent */",
+                "double point(Point p, int x, void *data);",
+            ]
+        );
+    }
+
+    #[test]
+    fn guards_conflicting_declarations() {
+        let header = header(
+            r#"
+            #[cfg(unix)]
+            #[no_mangle]
+            pub extern "C" fn dup(x: i32) {}
+            #[cfg(all(windows, not(feature = "gpu-v2")))]
+            #[cfg_attr(any(target_os = "windows", test), no_mangle)]
+            pub extern "C" fn dup(x: i64) {}
+            /// Same on every platform.
+            #[cfg(unix)]
+            #[no_mangle]
+            pub extern "C" fn same(x: i32) {}
+            #[cfg(windows)]
+            #[no_mangle]
+            pub extern "C" fn same(x: i32) {}
+            #[no_mangle]
+            #[cfg_attr(windows, export_name = "f_win")]
+            pub extern "C" fn f() {}
+            "#,
+            &CHeaderOptions::default(),
+        );
+        assert_eq!(
+            declaration_lines(&header),
+            [
+                "#if defined(CFG_UNIX)",
+                "void dup(int32_t x);",
+                "#endif",
+                "#if defined(CFG_WINDOWS) && !defined(CFG_FEATURE_GPU_V2) && (defined(CFG_TARGET_OS_WINDOWS) || defined(CFG_TEST))",
+                "void dup(int64_t x);",
+                "#endif",
+                "/**",
+                " * Same on every platform.",
+                " */",
+                "void same(int32_t x);",
+                "#if defined(CFG_WINDOWS)",
+                "void f_win(void);",
+                "#endif",
+                "#if !defined(CFG_WINDOWS)",
+                "void f(void);",
+                "#endif",
+            ]
+        );
+    }
+
+    #[test]
+    fn declares_the_symbols_of_the_target() {
+        let source = r#"
+            #[cfg(unix)]
+            #[no_mangle]
+            pub extern "C" fn dup(x: i32) {}
+            #[cfg(windows)]
+            #[no_mangle]
+            pub extern "C" fn dup(x: i64) {}
+            #[no_mangle]
+            #[cfg_attr(windows, export_name = "f_win")]
+            pub extern "C" fn f() {}
+            "#;
+        let mut cfg_set = crate::CfgSet::default();
+        cfg_set.insert_cfg("windows");
+        let parsed_files = crate::parse_sources_for_no_mangle_pub_extern_c_fns(
+            vec![("src/lib.rs", source)],
+            &crate::ScanOptions {
+                cfg_set: Some(cfg_set),
+                ..Default::default()
+            },
+        );
+        let header = generate_c_header(&parsed_files, &CHeaderOptions::default());
+        assert_eq!(
+            declaration_lines(&header),
+            ["void dup(int64_t x);", "void f_win(void);"]
+        );
+    }
+
+    #[test]
+    fn translates_cfg_predicates_to_conditions() {
+        let condition = |cfg: &str| {
+            c_condition(&CfgPredicate::from_nested_meta(&syn::parse_str(cfg).unwrap()).unwrap())
+        };
+        assert_eq!(condition("unix"), "defined(CFG_UNIX)");
+        assert_eq!(
+            condition("target_os = \"macos\""),
+            "defined(CFG_TARGET_OS_MACOS)"
+        );
+        assert_eq!(condition("all()"), "1");
+        assert_eq!(condition("any()"), "0");
+        assert_eq!(
+            condition("not(any(unix, all(windows, feature = \"a.b\")))"),
+            "!(defined(CFG_UNIX) || (defined(CFG_WINDOWS) && defined(CFG_FEATURE_A_B)))"
+        );
+        assert_eq!(condition("not(not(test))"), "!!defined(CFG_TEST)");
+    }
+}
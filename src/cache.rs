@@ -0,0 +1,214 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::scan::FileContribution;
+use crate::{CfgSet, ParsedFile, ScanOptions};
+
+const CACHE_FILE_NAME: &str = "no_mangle_pub_export_c_fn.json";
+
+/// The version of the cache format. Bump it whenever the cached data, e.g. [ParsedFile], changes shape
+/// or the same sources would be scanned differently, so that caches written before are discarded.
+const CACHE_FORMAT_VERSION: u32 = 2;
+
+/// Everything besides the sources that affects the results. Entries computed with a different key are discarded.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+struct CacheKey {
+    format_version: u32,
+    cfg_set: Option<CfgSet>,
+    expand_macro_rules: bool,
+    prefilter: bool,
+    env: BTreeMap<String, String>,
+    utf16_columns: bool,
+}
+
+impl CacheKey {
+    fn new(options: &ScanOptions) -> Self {
+        Self {
+            format_version: CACHE_FORMAT_VERSION,
+            cfg_set: options.cfg_set.clone(),
+            expand_macro_rules: options.expand_macro_rules,
+            prefilter: options.prefilter,
+            env: options.env.clone(),
+            utf16_columns: options.utf16_columns,
+        }
+    }
+}
+
+/// What is known about a file with the given content.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct CacheEntry {
+    pub(crate) content_hash: String,
+    pub(crate) contribution: FileContribution,
+    /// The digest of the crate-wide information the result was computed with. The result of a file
+    /// depends on other files through `#[cfg]` on `mod` declarations and `macro_rules!` definitions.
+    pub(crate) context_digest: String,
+    /// [None] if the file isn't reported, e.g. because it can't be parsed or is disabled.
+    pub(crate) parsed_file: Option<ParsedFile>,
+}
+
+/// The results of a previous scan, by path.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Cache {
+    key: CacheKey,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl Cache {
+    /// Loads the cache from the directory. A missing, unreadable or outdated cache is treated as empty.
+    pub(crate) fn load(cache_dir: &Path, options: &ScanOptions) -> Self {
+        let key = CacheKey::new(options);
+        std::fs::read(cache_dir.join(CACHE_FILE_NAME))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Cache>(&bytes).ok())
+            .filter(|cache| cache.key == key)
+            .unwrap_or_else(|| Self {
+                key,
+                entries: HashMap::new(),
+            })
+    }
+
+    pub(crate) fn new(options: &ScanOptions, entries: HashMap<PathBuf, CacheEntry>) -> Self {
+        Self {
+            key: CacheKey::new(options),
+            entries,
+        }
+    }
+
+    /// Returns the entry for the file if its content hasn't changed.
+    pub(crate) fn get(&self, path: &Path, content_hash: &str) -> Option<&CacheEntry> {
+        self.entries
+            .get(path)
+            .filter(|entry| entry.content_hash == content_hash)
+    }
+
+    /// Saves the cache to the directory, creating the directory if needed. The file is replaced
+    /// atomically so that concurrent scans never see a partially written cache.
+    pub(crate) fn save(&self, cache_dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(cache_dir)?;
+        let bytes = serde_json::to_vec(self)?;
+        let tmp_path = cache_dir.join(format!("{}.{}.tmp", CACHE_FILE_NAME, std::process::id()));
+        std::fs::write(&tmp_path, bytes)?;
+        std::fs::rename(&tmp_path, cache_dir.join(CACHE_FILE_NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_for_no_mangle_pub_extern_c_fns_with_options;
+
+    struct Fixture {
+        crate_root: tempfile::TempDir,
+        options: ScanOptions,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let crate_root = tempfile::tempdir().unwrap();
+            std::fs::create_dir(crate_root.path().join("src")).unwrap();
+            let options = ScanOptions {
+                cache_dir: Some(crate_root.path().join("target/cache")),
+                ..Default::default()
+            };
+            let fixture = Self {
+                crate_root,
+                options,
+            };
+            fixture.write("#[no_mangle] pub extern \"C\" fn f() {}\n");
+            fixture
+        }
+
+        fn write(&self, source: &str) {
+            std::fs::write(self.crate_root.path().join("src/lib.rs"), source).unwrap();
+        }
+
+        fn symbols(&self, options: &ScanOptions) -> Vec<String> {
+            parse_for_no_mangle_pub_extern_c_fns_with_options(
+                self.crate_root.path().to_str().unwrap(),
+                options,
+            )
+            .unwrap()
+            .iter()
+            .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+            .map(|no_mangle_pub_export_c_fn| no_mangle_pub_export_c_fn.symbol.clone())
+            .collect()
+        }
+
+        /// Rewrites the cached results, so that a cache hit can be told apart from a rescan.
+        fn tamper(&self, from: &str, to: &str) {
+            let path = self
+                .crate_root
+                .path()
+                .join("target/cache")
+                .join(CACHE_FILE_NAME);
+            let cache = std::fs::read_to_string(&path).unwrap();
+            assert!(cache.contains(from));
+            std::fs::write(&path, cache.replace(from, to)).unwrap();
+        }
+    }
+
+    #[test]
+    fn reuses_results_of_unchanged_files() {
+        let fixture = Fixture::new();
+        assert_eq!(fixture.symbols(&fixture.options), vec!["f"]);
+        fixture.tamper("\"symbol\":\"f\"", "\"symbol\":\"cached\"");
+        assert_eq!(fixture.symbols(&fixture.options), vec!["cached"]);
+        assert_eq!(fixture.symbols(&fixture.options), vec!["cached"]);
+    }
+
+    #[test]
+    fn invalidates_changed_files() {
+        let fixture = Fixture::new();
+        assert_eq!(fixture.symbols(&fixture.options), vec!["f"]);
+        fixture.tamper("\"symbol\":\"f\"", "\"symbol\":\"cached\"");
+        fixture.write("#[no_mangle] pub extern \"C\" fn g() {}\n");
+        assert_eq!(fixture.symbols(&fixture.options), vec!["g"]);
+    }
+
+    #[test]
+    fn invalidates_on_option_changes() {
+        let fixture = Fixture::new();
+        fixture.write(
+            "#[no_mangle] pub extern \"C\" fn f() {}\n\
+             #[cfg(unix)] #[no_mangle] pub extern \"C\" fn g() {}\n",
+        );
+        assert_eq!(fixture.symbols(&fixture.options), vec!["f", "g"]);
+        fixture.tamper("\"symbol\":\"f\"", "\"symbol\":\"cached\"");
+        let options = ScanOptions {
+            cfg_set: Some(CfgSet::default()),
+            ..fixture.options.clone()
+        };
+        assert_eq!(fixture.symbols(&options), vec!["f"]);
+
+        fixture.tamper("\"symbol\":\"f\"", "\"symbol\":\"cached\"");
+        let options = ScanOptions {
+            env: [("OUT_DIR".to_string(), "out".to_string())]
+                .into_iter()
+                .collect(),
+            ..options
+        };
+        assert_eq!(fixture.symbols(&options), vec!["f"]);
+    }
+
+    #[test]
+    fn invalidates_other_format_versions() {
+        let fixture = Fixture::new();
+        assert_eq!(fixture.symbols(&fixture.options), vec!["f"]);
+        fixture.tamper("\"symbol\":\"f\"", "\"symbol\":\"cached\"");
+        fixture.tamper(
+            &format!("\"format_version\":{}", CACHE_FORMAT_VERSION),
+            &format!("\"format_version\":{}", CACHE_FORMAT_VERSION + 1),
+        );
+        assert_eq!(fixture.symbols(&fixture.options), vec!["f"]);
+    }
+}
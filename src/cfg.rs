@@ -0,0 +1,618 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The set of active [configuration options](https://doc.rust-lang.org/reference/conditional-compilation.html#set-configuration-options)
+/// against which `#[cfg(...)]` predicates are evaluated.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::CfgSet;
+///
+/// // The output of `rustc --print cfg --target x86_64-pc-windows-msvc` (abridged)
+/// let mut cfg_set = CfgSet::from_rustc_print_cfg("target_os=\"windows\"\nwindows\n");
+/// cfg_set.insert_feature("gpu");
+/// // The same syntax as for `rustc --cfg`
+/// cfg_set.insert_cfg("my_cfg");
+///
+/// assert!(cfg_set.contains_name("windows"));
+/// assert!(cfg_set.contains_key_value("target_os", "windows"));
+/// assert!(cfg_set.contains_key_value("feature", "gpu"));
+/// assert!(cfg_set.contains_name("my_cfg"));
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgSet {
+    /// Options without a value, e.g. `unix`, `debug_assertions` or `my_cfg` from `--cfg my_cfg`.
+    pub names: BTreeSet<String>,
+    /// Options with a value, e.g. `feature = "gpu"` or `target_os = "windows"`. The same key may have several values.
+    pub key_values: BTreeSet<(String, String)>,
+}
+
+impl CfgSet {
+    /// Parses the output of `rustc --print cfg`, one option per line.
+    pub fn from_rustc_print_cfg(output: &str) -> Self {
+        let mut cfg_set = Self::default();
+        output.lines().for_each(|line| cfg_set.insert_cfg(line));
+        cfg_set
+    }
+
+    /// Inserts an option in the syntax of `rustc --cfg`, i.e. `name` or `key="value"`.
+    pub fn insert_cfg(&mut self, cfg: &str) {
+        match cfg.split_once('=') {
+            Some((key, value)) => {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                self.key_values
+                    .insert((key.trim().to_string(), value.to_string()));
+            }
+            None if !cfg.trim().is_empty() => {
+                self.names.insert(cfg.trim().to_string());
+            }
+            None => {}
+        }
+    }
+
+    /// Inserts `feature = "<feature>"`.
+    pub fn insert_feature(&mut self, feature: &str) {
+        self.key_values
+            .insert(("feature".to_string(), feature.to_string()));
+    }
+
+    pub fn contains_name(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    pub fn contains_key_value(&self, key: &str, value: &str) -> bool {
+        self.key_values
+            .iter()
+            .any(|(k, v)| k.as_str() == key && v.as_str() == value)
+    }
+}
+
+/// A [configuration predicate](https://doc.rust-lang.org/reference/conditional-compilation.html), i.e.
+/// the contents of `#[cfg(...)]`. Its [Display] implementation prints it back in Rust syntax.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CfgPredicate {
+    Name(String),
+    KeyValue(String, String),
+    All(Vec<CfgPredicate>),
+    Any(Vec<CfgPredicate>),
+    Not(Box<CfgPredicate>),
+}
+
+impl CfgPredicate {
+    /// Evaluates the predicate against the given set of active options.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use no_mangle_pub_export_c_fn::{CfgPredicate, CfgSet};
+    ///
+    /// let mut cfg_set = CfgSet::default();
+    /// cfg_set.insert_feature("gpu");
+    /// let predicate = CfgPredicate::All(vec![
+    ///     CfgPredicate::KeyValue("feature".to_string(), "gpu".to_string()),
+    ///     CfgPredicate::Not(Box::new(CfgPredicate::Name("windows".to_string()))),
+    /// ]);
+    /// assert!(predicate.evaluate(&cfg_set));
+    /// assert_eq!(predicate.to_string(), r#"all(feature = "gpu", not(windows))"#);
+    /// ```
+    pub fn evaluate(&self, cfg_set: &CfgSet) -> bool {
+        match self {
+            CfgPredicate::Name(name) => cfg_set.contains_name(name),
+            CfgPredicate::KeyValue(key, value) => cfg_set.contains_key_value(key, value),
+            CfgPredicate::All(predicates) => predicates
+                .iter()
+                .all(|predicate| predicate.evaluate(cfg_set)),
+            CfgPredicate::Any(predicates) => predicates
+                .iter()
+                .any(|predicate| predicate.evaluate(cfg_set)),
+            CfgPredicate::Not(predicate) => !predicate.evaluate(cfg_set),
+        }
+    }
+
+    /// Combines the predicates with `all(...)`. Returns [None] when there are no predicates.
+    pub(crate) fn all(mut predicates: Vec<CfgPredicate>) -> Option<CfgPredicate> {
+        match predicates.len() {
+            0 => None,
+            1 => predicates.pop(),
+            _ => Some(CfgPredicate::All(predicates)),
+        }
+    }
+
+    pub(crate) fn from_nested_meta(nested_meta: &syn::NestedMeta) -> Option<CfgPredicate> {
+        match nested_meta {
+            syn::NestedMeta::Meta(meta) => CfgPredicate::from_meta(meta),
+            syn::NestedMeta::Lit(_) => None,
+        }
+    }
+
+    fn from_meta(meta: &syn::Meta) -> Option<CfgPredicate> {
+        match meta {
+            syn::Meta::Path(path) => path
+                .get_ident()
+                .map(|ident| CfgPredicate::Name(ident.to_string())),
+            syn::Meta::NameValue(syn::MetaNameValue {
+                path,
+                lit: syn::Lit::Str(lit_str),
+                ..
+            }) => path
+                .get_ident()
+                .map(|ident| CfgPredicate::KeyValue(ident.to_string(), lit_str.value())),
+            syn::Meta::NameValue(_) => None,
+            syn::Meta::List(meta_list) => {
+                let predicates = meta_list
+                    .nested
+                    .iter()
+                    .map(CfgPredicate::from_nested_meta)
+                    .collect::<Option<Vec<CfgPredicate>>>()?;
+                match meta_list.path.get_ident()?.to_string().as_str() {
+                    "all" => Some(CfgPredicate::All(predicates)),
+                    "any" => Some(CfgPredicate::Any(predicates)),
+                    "not" if predicates.len() == 1 => predicates
+                        .into_iter()
+                        .next()
+                        .map(Box::new)
+                        .map(CfgPredicate::Not),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for CfgPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(
+            f: &mut fmt::Formatter<'_>,
+            name: &str,
+            predicates: &[CfgPredicate],
+        ) -> fmt::Result {
+            write!(f, "{}(", name)?;
+            for (i, predicate) in predicates.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", predicate)?;
+            }
+            write!(f, ")")
+        }
+
+        match self {
+            CfgPredicate::Name(name) => write!(f, "{}", name),
+            CfgPredicate::KeyValue(key, value) => write!(f, "{} = {:?}", key, value),
+            CfgPredicate::All(predicates) => list(f, "all", predicates),
+            CfgPredicate::Any(predicates) => list(f, "any", predicates),
+            CfgPredicate::Not(predicate) => write!(f, "not({})", predicate),
+        }
+    }
+}
+
+/// An attribute along with the condition under which it applies. Attributes that come from
+/// `#[cfg_attr(...)]` are conditional, all other attributes are not.
+pub(crate) struct ConditionalMeta {
+    pub(crate) cfg: Option<CfgPredicate>,
+    pub(crate) meta: syn::Meta,
+}
+
+/// Expands `#[cfg_attr(predicate, attr1, attr2, ...)]`, including nested `cfg_attr`s, into the
+/// attributes it may produce. Attributes that can't be parsed as [syn::Meta] are ignored.
+pub(crate) fn expand_cfg_attrs<'a, I>(attrs: I) -> Vec<ConditionalMeta>
+where
+    I: IntoIterator<Item = &'a syn::Attribute>,
+{
+    fn expand(conditions: &[CfgPredicate], meta: syn::Meta, expanded: &mut Vec<ConditionalMeta>) {
+        let meta_list = match meta {
+            syn::Meta::List(meta_list) if meta_list.path.is_ident("cfg_attr") => meta_list,
+            meta => {
+                expanded.push(ConditionalMeta {
+                    cfg: CfgPredicate::all(conditions.to_vec()),
+                    meta,
+                });
+                return;
+            }
+        };
+        let mut nested = meta_list.nested.into_iter();
+        let condition = match nested
+            .next()
+            .as_ref()
+            .and_then(CfgPredicate::from_nested_meta)
+        {
+            Some(condition) => condition,
+            None => return,
+        };
+        let mut conditions = conditions.to_vec();
+        conditions.push(condition);
+        nested.for_each(|nested_meta| {
+            if let syn::NestedMeta::Meta(meta) = nested_meta {
+                expand(&conditions, meta, expanded);
+            }
+        });
+    }
+
+    let mut expanded = Vec::new();
+    attrs
+        .into_iter()
+        .filter_map(|attr| attr.parse_meta().ok())
+        .for_each(|meta| expand(&[], meta, &mut expanded));
+    expanded
+}
+
+/// Does the same as [expand_cfg_attrs] but leaves out the attributes whose conditions aren't enabled
+/// by `cfg_set`.
+pub(crate) fn enabled_attrs(
+    attrs: &[syn::Attribute],
+    cfg_set: Option<&CfgSet>,
+) -> Vec<ConditionalMeta> {
+    expand_cfg_attrs(attrs)
+        .into_iter()
+        .filter(|conditional_meta| match &conditional_meta.cfg {
+            Some(cfg) => is_cfg_enabled(cfg_set, std::slice::from_ref(cfg)),
+            None => true,
+        })
+        .collect()
+}
+
+/// Extracts the predicates of all `#[cfg(...)]` attributes, including the ones produced by
+/// `#[cfg_attr(...)]`. Malformed predicates are ignored.
+pub(crate) fn cfg_predicates(attrs: &[syn::Attribute]) -> Vec<CfgPredicate> {
+    let attrs = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("cfg") || attr.path.is_ident("cfg_attr"));
+    expand_cfg_attrs(attrs)
+        .into_iter()
+        .filter_map(|conditional_meta| {
+            let predicate = match &conditional_meta.meta {
+                syn::Meta::List(meta_list)
+                    if meta_list.path.is_ident("cfg") && meta_list.nested.len() == 1 =>
+                {
+                    meta_list
+                        .nested
+                        .first()
+                        .and_then(CfgPredicate::from_nested_meta)?
+                }
+                _ => return None,
+            };
+            // `#[cfg_attr(a, cfg(b))]` is equivalent to `#[cfg(any(not(a), b))]`
+            Some(match conditional_meta.cfg {
+                Some(condition) => {
+                    CfgPredicate::Any(vec![CfgPredicate::Not(Box::new(condition)), predicate])
+                }
+                None => predicate,
+            })
+        })
+        .collect()
+}
+
+/// Checks whether all predicates hold. Without a cfg set, nothing is pruned.
+pub(crate) fn is_cfg_enabled(cfg_set: Option<&CfgSet>, predicates: &[CfgPredicate]) -> bool {
+    cfg_set.map_or(true, |cfg_set| {
+        predicates
+            .iter()
+            .all(|predicate| predicate.evaluate(cfg_set))
+    })
+}
+
+pub(crate) fn item_attrs(item: &syn::Item) -> &[syn::Attribute] {
+    match item {
+        syn::Item::Const(item) => &item.attrs,
+        syn::Item::Enum(item) => &item.attrs,
+        syn::Item::ExternCrate(item) => &item.attrs,
+        syn::Item::Fn(item) => &item.attrs,
+        syn::Item::ForeignMod(item) => &item.attrs,
+        syn::Item::Impl(item) => &item.attrs,
+        syn::Item::Macro(item) => &item.attrs,
+        syn::Item::Macro2(item) => &item.attrs,
+        syn::Item::Mod(item) => &item.attrs,
+        syn::Item::Static(item) => &item.attrs,
+        syn::Item::Struct(item) => &item.attrs,
+        syn::Item::Trait(item) => &item.attrs,
+        syn::Item::TraitAlias(item) => &item.attrs,
+        syn::Item::Type(item) => &item.attrs,
+        syn::Item::Union(item) => &item.attrs,
+        syn::Item::Use(item) => &item.attrs,
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_sources_for_no_mangle_pub_extern_c_fns, ParsedFile, ScanOptions};
+
+    fn predicate(cfg: &str) -> CfgPredicate {
+        CfgPredicate::from_nested_meta(&syn::parse_str(cfg).unwrap()).unwrap()
+    }
+
+    fn symbols(parsed_files: &[ParsedFile]) -> Vec<String> {
+        let mut symbols: Vec<String> = parsed_files
+            .iter()
+            .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+            .map(|no_mangle_pub_export_c_fn| no_mangle_pub_export_c_fn.symbol.clone())
+            .collect();
+        symbols.sort();
+        symbols
+    }
+
+    fn cfg_set(cfgs: &[&str]) -> Option<CfgSet> {
+        let mut cfg_set = CfgSet::default();
+        cfgs.iter().for_each(|cfg| cfg_set.insert_cfg(cfg));
+        Some(cfg_set)
+    }
+
+    #[test]
+    fn parses_rustc_print_cfg() {
+        let cfg_set = CfgSet::from_rustc_print_cfg(
+            "debug_assertions\ntarget_feature=\"sse\"\ntarget_feature=\"sse2\"\n\nunix\n",
+        );
+        assert!(cfg_set.contains_name("debug_assertions"));
+        assert!(cfg_set.contains_name("unix"));
+        assert!(cfg_set.contains_key_value("target_feature", "sse"));
+        assert!(cfg_set.contains_key_value("target_feature", "sse2"));
+        assert!(!cfg_set.contains_key_value("target_feature", "avx"));
+        assert_eq!(cfg_set.names.len(), 2);
+    }
+
+    #[test]
+    fn evaluates_predicates() {
+        let cfg_set = cfg_set(&["unix", "feature=\"gpu\"", "target_os=\"linux\""]).unwrap();
+        let cases = [
+            ("unix", true),
+            ("windows", false),
+            ("feature = \"gpu\"", true),
+            ("feature = \"cpu\"", false),
+            ("all(unix, target_os = \"linux\")", true),
+            ("all(unix, windows)", false),
+            ("all()", true),
+            ("any(windows, feature = \"gpu\")", true),
+            ("any()", false),
+            ("not(any(windows, target_os = \"macos\"))", true),
+        ];
+        cases.iter().for_each(|(cfg, enabled)| {
+            let predicate = predicate(cfg);
+            assert_eq!(predicate.evaluate(&cfg_set), *enabled, "{}", cfg);
+            assert_eq!(predicate.to_string(), *cfg);
+        });
+    }
+
+    #[test]
+    fn ignores_malformed_predicates() {
+        let attrs: Vec<syn::Attribute> = vec![
+            syn::parse_quote!(#[cfg(not(a, b))]),
+            syn::parse_quote!(#[cfg(feature = 1)]),
+            syn::parse_quote!(#[cfg(a::b)]),
+            syn::parse_quote!(#[cfg(a, b)]),
+        ];
+        assert!(cfg_predicates(&attrs).is_empty());
+    }
+
+    #[test]
+    fn records_predicates_without_cfg_set() {
+        let sources = vec![
+            (
+                "src/lib.rs",
+                "#[cfg(unix)] mod sys;\n\
+                 #[cfg(feature = \"gpu\")]\n\
+                 #[no_mangle] pub extern \"C\" fn kernel() {}\n\
+                 #[no_mangle] pub extern \"C\" fn always() {}\n",
+            ),
+            (
+                "src/sys.rs",
+                "#[cfg(target_os = \"linux\")] pub mod linux {\n\
+                     #[no_mangle] pub extern \"C\" fn epoll() {}\n\
+                 }\n",
+            ),
+        ];
+        let parsed_files =
+            parse_sources_for_no_mangle_pub_extern_c_fns(sources, &ScanOptions::default());
+        let cfg = |symbol: &str| {
+            parsed_files
+                .iter()
+                .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+                .find(|no_mangle_pub_export_c_fn| no_mangle_pub_export_c_fn.symbol == symbol)
+                .unwrap()
+                .cfg
+                .as_ref()
+                .map(ToString::to_string)
+        };
+        assert_eq!(cfg("kernel").as_deref(), Some("feature = \"gpu\""));
+        assert_eq!(cfg("always"), None);
+        assert_eq!(
+            cfg("epoll").as_deref(),
+            Some("all(unix, target_os = \"linux\")")
+        );
+    }
+
+    #[test]
+    fn prunes_items_modules_and_files() {
+        let sources = vec![
+            (
+                "src/lib.rs",
+                "#[cfg(windows)] mod windows;\n\
+                 mod gpu;\n\
+                 #[cfg(feature = \"ffi\")] mod ffi {\n\
+                     #[no_mangle] pub extern \"C\" fn ffi_init() {}\n\
+                     #[cfg(not(debug_assertions))]\n\
+                     #[no_mangle] pub extern \"C\" fn ffi_release_only() {}\n\
+                 }\n\
+                 #[cfg(any(unix, windows))]\n\
+                 #[no_mangle] pub extern \"C\" fn portable() {}\n",
+            ),
+            (
+                "src/windows.rs",
+                "#[no_mangle] pub extern \"C\" fn win32() {}\n",
+            ),
+            (
+                "src/gpu.rs",
+                "#![cfg(feature = \"gpu\")]\n\
+                 #[no_mangle] pub extern \"C\" fn kernel() {}\n",
+            ),
+        ];
+        let scan = |cfgs: &[&str]| {
+            let options = ScanOptions {
+                cfg_set: cfg_set(cfgs),
+                ..Default::default()
+            };
+            symbols(&parse_sources_for_no_mangle_pub_extern_c_fns(
+                sources.clone(),
+                &options,
+            ))
+        };
+        assert_eq!(scan(&["unix"]), vec!["portable"]);
+        assert_eq!(scan(&["windows"]), vec!["portable", "win32"]);
+        assert_eq!(
+            scan(&[
+                "unix",
+                "debug_assertions",
+                "feature=\"ffi\"",
+                "feature=\"gpu\""
+            ]),
+            vec!["ffi_init", "kernel", "portable"]
+        );
+        assert_eq!(
+            scan(&["feature=\"ffi\""]),
+            vec!["ffi_init", "ffi_release_only"]
+        );
+        assert_eq!(
+            symbols(&parse_sources_for_no_mangle_pub_extern_c_fns(
+                sources.clone(),
+                &ScanOptions::default()
+            )),
+            vec![
+                "ffi_init",
+                "ffi_release_only",
+                "kernel",
+                "portable",
+                "win32"
+            ]
+        );
+    }
+
+    fn export(source: &str, cfg_set: Option<CfgSet>) -> Option<crate::NoManglePubExportCFn> {
+        let options = ScanOptions {
+            cfg_set,
+            ..Default::default()
+        };
+        parse_sources_for_no_mangle_pub_extern_c_fns(vec![("src/lib.rs", source)], &options)
+            .pop()
+            .and_then(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.into_iter().next())
+    }
+
+    #[test]
+    fn expands_nested_and_multi_attribute_cfg_attrs() {
+        let attrs: Vec<syn::Attribute> = vec![
+            syn::parse_quote!(#[inline]),
+            syn::parse_quote!(#[cfg_attr(unix, no_mangle, export_name = "f")]),
+            syn::parse_quote!(#[cfg_attr(feature = "ffi", cfg_attr(not(test), doc = "f", cold))]),
+            syn::parse_quote!(#[cfg_attr(not(a, b), no_mangle)]),
+        ];
+        let expanded: Vec<(Option<String>, String)> = expand_cfg_attrs(&attrs)
+            .into_iter()
+            .map(|conditional_meta| {
+                (
+                    conditional_meta.cfg.map(|cfg| cfg.to_string()),
+                    quote::ToTokens::to_token_stream(&conditional_meta.meta).to_string(),
+                )
+            })
+            .collect();
+        let expected = [
+            (None, "inline"),
+            (Some("unix"), "no_mangle"),
+            (Some("unix"), "export_name = \"f\""),
+            (Some("all(feature = \"ffi\", not(test))"), "doc = \"f\""),
+            (Some("all(feature = \"ffi\", not(test))"), "cold"),
+        ];
+        assert_eq!(
+            expanded,
+            expected
+                .iter()
+                .map(|(cfg, meta)| (cfg.map(str::to_string), meta.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reports_export_cfg() {
+        let source = "#[cfg_attr(feature = \"ffi\", no_mangle)] pub extern \"C\" fn f() {}";
+        let no_mangle_pub_export_c_fn = export(source, None).unwrap();
+        assert_eq!(
+            no_mangle_pub_export_c_fn.export_cfg.unwrap().to_string(),
+            "feature = \"ffi\""
+        );
+        assert_eq!(no_mangle_pub_export_c_fn.cfg, None);
+        assert!(export(source, cfg_set(&["feature=\"ffi\""])).is_some());
+        assert!(export(source, cfg_set(&[])).is_none());
+
+        // Unconditional `#[no_mangle]` wins over conditional ones
+        let source = "#[no_mangle] #[cfg_attr(unix, no_mangle)] pub extern \"C\" fn f() {}";
+        assert_eq!(export(source, None).unwrap().export_cfg, None);
+
+        let source =
+            "#[cfg_attr(unix, no_mangle)] #[cfg_attr(all(), cfg_attr(windows, no_mangle))]\n\
+                      pub extern \"C\" fn f() {}";
+        assert_eq!(
+            export(source, None)
+                .unwrap()
+                .export_cfg
+                .unwrap()
+                .to_string(),
+            "any(unix, all(all(), windows))"
+        );
+        assert_eq!(
+            export(source, cfg_set(&["windows"]))
+                .unwrap()
+                .export_cfg
+                .unwrap()
+                .to_string(),
+            "all(all(), windows)"
+        );
+    }
+
+    #[test]
+    fn applies_conditional_export_names_and_cfgs() {
+        let source =
+            "#[no_mangle] #[cfg_attr(windows, export_name = \"f_win\")] pub extern \"C\" fn f() {}";
+        let f = export(source, None).unwrap();
+        assert_eq!(f.symbol, "f_win");
+        assert_eq!(f.symbol_cfg.unwrap().to_string(), "windows");
+        assert_eq!(f.fallback_symbol.as_deref(), Some("f"));
+        let f = export(source, cfg_set(&["windows"])).unwrap();
+        assert_eq!(
+            (f.symbol.as_str(), f.symbol_cfg, f.fallback_symbol),
+            ("f_win", None, None)
+        );
+        let f = export(source, cfg_set(&["unix"])).unwrap();
+        assert_eq!(
+            (f.symbol.as_str(), f.symbol_cfg, f.fallback_symbol),
+            ("f", None, None)
+        );
+
+        // Unconditional export names win, raw idents fall back to their unraw form
+        let source = "#[no_mangle] #[cfg_attr(unix, export_name = \"f_unix\")] #[export_name = \"f_any\"] pub extern \"C\" fn f() {}";
+        let f = export(source, None).unwrap();
+        assert_eq!((f.symbol.as_str(), f.symbol_cfg), ("f_any", None));
+        let source =
+            "#[no_mangle] #[cfg_attr(unix, export_name = \"t\")] pub extern \"C\" fn r#type() {}";
+        assert_eq!(
+            export(source, None).unwrap().fallback_symbol.as_deref(),
+            Some("type")
+        );
+
+        // `#[cfg_attr(a, cfg(b))]` is equivalent to `#[cfg(any(not(a), b))]`
+        let source =
+            "#[cfg_attr(feature = \"ffi\", cfg(unix))] #[no_mangle] pub extern \"C\" fn f() {}";
+        assert_eq!(
+            export(source, None).unwrap().cfg.unwrap().to_string(),
+            "any(not(feature = \"ffi\"), unix)"
+        );
+        assert!(export(source, cfg_set(&[])).is_some());
+        assert!(export(source, cfg_set(&["feature=\"ffi\"", "unix"])).is_some());
+        assert!(export(source, cfg_set(&["feature=\"ffi\"", "windows"])).is_none());
+    }
+}
@@ -0,0 +1,474 @@
+use crate::c_header::{
+    c_type_of_ident, declarations, doxygen_comment, input_ty, output_ty, referenced_idents,
+    type_defs_by_ident, DeclaredFn,
+};
+use crate::{NoManglePubExportCFn, ParsedFile, TypeDef, TypeDefKind};
+
+/// Options for [generate_cpp_header].
+#[derive(Debug, Clone)]
+pub struct CppHeaderOptions {
+    /// The name of the macro used in the `#ifndef`/`#define` include guard.
+    pub include_guard: String,
+    /// The namespace of the declarations, e.g. `mylib` or `mylib::ffi`. Empty for the global namespace.
+    pub namespace: String,
+    /// The same as [CHeaderOptions::callback_typedef_name](crate::CHeaderOptions::callback_typedef_name).
+    pub callback_typedef_name: String,
+    /// Whether to generate `std::unique_ptr` wrappers for the opaque types that have a matching
+    /// `*_free` function.
+    pub unique_ptr_wrappers: bool,
+}
+
+impl Default for CppHeaderOptions {
+    fn default() -> Self {
+        Self {
+            include_guard: "NO_MANGLE_PUB_EXPORT_C_FNS_HPP".to_string(),
+            namespace: "ffi".to_string(),
+            callback_typedef_name: "{symbol}_{param}_fn".to_string(),
+            unique_ptr_wrappers: false,
+        }
+    }
+}
+
+/// Generates a C++ header with declarations of all `#[no_mangle] pub export "C"` functions in the
+/// given parsed files, in `extern "C"` blocks within [CppHeaderOptions::namespace].
+///
+/// The functions are declared the same way as in [generate_c_header](crate::generate_c_header).
+/// In addition, the enums without fields defined in the parsed files with `#[repr(C)]` or a primitive
+/// representation, e.g. `#[repr(u8)]`, are declared as `enum class`es before the first declaration,
+/// if they are referred to.
+///
+/// With [CppHeaderOptions::unique_ptr_wrappers], an opaque type `Engine` gets an `EngineDeleter`
+/// and an `EnginePtr` alias of `std::unique_ptr<Engine, EngineDeleter>`, if a function that takes a
+/// pointer to it and returns nothing is named `engine_free`, possibly with a prefix, e.g.
+/// `mylib_engine_free`.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{generate_cpp_header, CppHeaderOptions, ParsedFile};
+///
+/// let parsed_files: Vec<ParsedFile> = serde_json::from_str(r#"[{
+///     "path": "src/lib.rs",
+///     "no_mangle_pub_export_c_fns": [{
+///         "ident": "engine_new",
+///         "symbol": "engine_new",
+///         "inputs": [{ "pat": "mode", "ty": "Mode" }],
+///         "output": "* mut Engine",
+///         "location": { "start_line": 1, "start_column": 0, "end_line": 4, "end_column": 1 }
+///     }, {
+///         "ident": "engine_free",
+///         "symbol": "engine_free",
+///         "inputs": [{ "pat": "engine", "ty": "* mut Engine" }],
+///         "output": null,
+///         "location": { "start_line": 6, "start_column": 0, "end_line": 6, "end_column": 50 }
+///     }],
+///     "type_defs": [
+///         { "ident": "Engine", "kind": "Struct", "doc": null },
+///         {
+///             "ident": "Mode",
+///             "kind": "Enum",
+///             "repr": ["u8"],
+///             "variants": [{ "ident": "Fast", "discriminant": "1" }, { "ident": "Safe", "discriminant": null }],
+///             "doc": null
+///         }
+///     ]
+/// }]"#).unwrap();
+/// let options = CppHeaderOptions {
+///     namespace: "mylib".to_string(),
+///     unique_ptr_wrappers: true,
+///     ..Default::default()
+/// };
+/// let header = generate_cpp_header(&parsed_files, &options);
+/// assert!(header.contains("namespace mylib {"));
+/// assert!(header.contains("enum class Mode : uint8_t {\n    Fast = 1,\n    Safe,\n};"));
+/// assert!(header.contains("Engine *engine_new(Mode mode);"));
+/// assert!(header.contains("using EnginePtr = std::unique_ptr<Engine, EngineDeleter>;"));
+/// ```
+pub fn generate_cpp_header(parsed_files: &[ParsedFile], options: &CppHeaderOptions) -> String {
+    let type_defs = type_defs_by_ident(parsed_files);
+    let mut enums: Vec<&TypeDef> = Vec::new();
+    parsed_files
+        .iter()
+        .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+        .flat_map(referenced_idents)
+        .filter_map(|ident| type_defs.get(ident.as_str()))
+        .filter(|type_def| is_c_like_enum(type_def))
+        .for_each(|type_def| {
+            if !enums.iter().any(|other| other.ident == type_def.ident) {
+                enums.push(type_def);
+            }
+        });
+    let declarations = declarations(
+        parsed_files,
+        &type_defs,
+        &options.callback_typedef_name,
+        enums
+            .iter()
+            .map(|type_def| type_def.ident.as_str())
+            .collect(),
+    );
+    let wrappers: Vec<(&str, &str)> = match options.unique_ptr_wrappers {
+        true => declarations
+            .opaque_types
+            .iter()
+            .filter_map(|ident| {
+                free_fn(ident, &declarations.declared_fns)
+                    .map(|free_fn| (ident.as_str(), free_fn.symbol.as_str()))
+            })
+            .collect(),
+        false => Vec::new(),
+    };
+
+    let mut header = format!(
+        "#ifndef {guard}\n\
+         #define {guard}\n\
+         \n\
+         #include <cstddef>\n\
+         #include <cstdint>\n",
+        guard = options.include_guard
+    );
+    if !wrappers.is_empty() {
+        header.push_str("#include <memory>\n");
+    }
+    header.push('\n');
+    let namespaces: Vec<&str> = options
+        .namespace
+        .split("::")
+        .filter(|namespace| !namespace.is_empty())
+        .collect();
+    // One definition per namespace, since nested namespace definitions need C++17
+    namespaces
+        .iter()
+        .for_each(|namespace| header.push_str(&format!("namespace {} {{\n", namespace)));
+    if !namespaces.is_empty() {
+        header.push('\n');
+    }
+    enums.iter().for_each(|type_def| {
+        header.push_str(&enum_class(type_def));
+        header.push('\n');
+    });
+    header.push_str("extern \"C\" {\n\n");
+    header.push_str(&declarations.text);
+    header.push_str("\n} // extern \"C\"\n");
+    wrappers.iter().for_each(|(ident, free_fn)| {
+        header.push_str(&format!(
+            "\n\
+             /** Frees the `{ident}` with `{free_fn}`. */\n\
+             struct {ident}Deleter {{\n    \
+                 void operator()({ident} *ptr) const noexcept {{ {free_fn}(ptr); }}\n\
+             }};\n\
+             using {ident}Ptr = std::unique_ptr<{ident}, {ident}Deleter>;\n",
+            ident = ident,
+            free_fn = free_fn
+        ));
+    });
+    if !namespaces.is_empty() {
+        header.push('\n');
+    }
+    namespaces
+        .iter()
+        .rev()
+        .for_each(|namespace| header.push_str(&format!("}} // namespace {}\n", namespace)));
+    header.push_str(&format!("\n#endif /* {} */\n", options.include_guard));
+    header
+}
+
+/// Whether the type is an enum without fields whose layout is that of a C enum or of an integer.
+fn is_c_like_enum(type_def: &TypeDef) -> bool {
+    type_def.kind == TypeDefKind::Enum && !type_def.variants.is_empty() && !type_def.is_opaque()
+}
+
+fn enum_class(type_def: &TypeDef) -> String {
+    let mut enum_class = String::new();
+    if let Some(doc) = &type_def.doc {
+        enum_class.push_str(&doxygen_comment(doc, None));
+    }
+    // `#[repr(C)]` enums have the size of an `int` in practice, like `enum class`es without an underlying type
+    let underlying_type = type_def
+        .repr
+        .iter()
+        .find_map(|hint| c_type_of_ident(hint))
+        .map(|c_type| format!(" : {}", c_type))
+        .unwrap_or_default();
+    enum_class.push_str(&format!(
+        "enum class {}{} {{\n",
+        type_def.ident, underlying_type
+    ));
+    type_def
+        .variants
+        .iter()
+        .for_each(|variant| match &variant.discriminant {
+            Some(discriminant) => {
+                enum_class.push_str(&format!("    {} = {},\n", variant.ident, discriminant))
+            }
+            None => enum_class.push_str(&format!("    {},\n", variant.ident)),
+        });
+    enum_class.push_str("};\n");
+    enum_class
+}
+
+/// The function that frees the opaque type `ident`, i.e. the one named after the type in snake
+/// case followed by `_free` that takes a single pointer to it and returns nothing.
+fn free_fn<'a>(ident: &str, declared_fns: &[DeclaredFn<'a>]) -> Option<&'a NoManglePubExportCFn> {
+    let name = format!("{}_free", snake_case(ident));
+    declared_fns
+        .iter()
+        .map(|declared_fn| declared_fn.no_mangle_pub_export_c_fn)
+        .find(|no_mangle_pub_export_c_fn| {
+            let symbol = &no_mangle_pub_export_c_fn.symbol;
+            let is_named = symbol == &name || symbol.ends_with(&format!("_{}", name));
+            is_named
+                && returns_nothing(no_mangle_pub_export_c_fn)
+                && no_mangle_pub_export_c_fn.inputs.len() == 1
+                && syn::parse_str::<syn::Type>(input_ty(&no_mangle_pub_export_c_fn.inputs[0]))
+                    .ok()
+                    .and_then(|ty| pointee_ident(&ty))
+                    .map_or(false, |pointee| pointee == ident)
+        })
+}
+
+/// Whether the resolved return type is `()`, e.g. through a type alias.
+fn returns_nothing(no_mangle_pub_export_c_fn: &NoManglePubExportCFn) -> bool {
+    output_ty(no_mangle_pub_export_c_fn).map_or(true, |output| {
+        matches!(
+            syn::parse_str::<syn::Type>(output),
+            Ok(syn::Type::Tuple(type_tuple)) if type_tuple.elems.is_empty()
+        )
+    })
+}
+
+/// The ident of the type pointed to by a mutable pointer, e.g. `Engine` for `Option<Box<Engine>>`.
+fn pointee_ident(ty: &syn::Type) -> Option<String> {
+    match ty {
+        syn::Type::Paren(type_paren) => pointee_ident(&type_paren.elem),
+        syn::Type::Group(type_group) => pointee_ident(&type_group.elem),
+        syn::Type::Ptr(syn::TypePtr {
+            mutability: Some(_),
+            elem,
+            ..
+        }) => path_ident(elem),
+        syn::Type::Path(type_path) => {
+            let segment = type_path.path.segments.last()?;
+            let argument = match &segment.arguments {
+                syn::PathArguments::AngleBracketed(arguments) => match arguments.args.first() {
+                    Some(syn::GenericArgument::Type(argument)) => argument,
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            match segment.ident.to_string().as_str() {
+                "Box" | "NonNull" => path_ident(argument),
+                "Option" => pointee_ident(argument),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn path_ident(ty: &syn::Type) -> Option<String> {
+    match ty {
+        syn::Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// E.g. `http_client` for `HttpClient` and `http_client` for `HTTPClient`.
+fn snake_case(ident: &str) -> String {
+    let chars: Vec<char> = ident.chars().collect();
+    let mut snake_case = String::new();
+    chars.iter().enumerate().for_each(|(index, c)| {
+        if c.is_uppercase() && index > 0 {
+            let previous = chars[index - 1];
+            let next = chars.get(index + 1);
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next.map_or(false, |next| next.is_lowercase()))
+            {
+                snake_case.push('_');
+            }
+        }
+        snake_case.extend(c.to_lowercase());
+    });
+    snake_case
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(source: &str, options: &CppHeaderOptions) -> String {
+        let parsed_files = crate::parse_sources_for_no_mangle_pub_extern_c_fns(
+            vec![("src/lib.rs", source)],
+            &crate::ScanOptions::default(),
+        );
+        generate_cpp_header(&parsed_files, options)
+    }
+
+    #[test]
+    fn suffixes_keyword_params() {
+        let header = header(
+            r#"
+            #[no_mangle]
+            pub extern "C" fn f(template: i32, r#virtual: u8, namespace: *const u8, this: bool) {}
+            "#,
+            &CppHeaderOptions::default(),
+        );
+        assert!(header.contains(
+            "void f(int32_t template_, uint8_t virtual_, const uint8_t *namespace_, bool this_);\n"
+        ));
+    }
+
+    #[test]
+    fn declares_enum_classes_and_unique_ptr_wrappers() {
+        let header = header(
+            r#"
+            /// The mode.
+            #[repr(u8)]
+            pub enum Mode { Fast = 1, Safe }
+            #[repr(C)]
+            pub enum Level { Low, High }
+            pub enum RustEnum { A, B }
+            #[repr(i32)]
+            pub enum Unused { X }
+            pub struct HTTPClient;
+            pub struct Engine;
+            pub struct Leaky;
+            #[no_mangle]
+            pub extern "C" fn engine_new(mode: Mode, level: Level, e: *mut RustEnum) -> *mut Engine {}
+            #[no_mangle]
+            pub extern "C" fn mylib_engine_free(engine: Option<Box<Engine>>) {}
+            #[no_mangle]
+            pub extern "C" fn http_client_free(client: *mut HTTPClient) {}
+            #[no_mangle]
+            pub extern "C" fn leaky_free(leaky: *mut Leaky) -> bool {}
+            "#,
+            &CppHeaderOptions {
+                namespace: "mylib::ffi".to_string(),
+                unique_ptr_wrappers: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            header,
+            r#"#ifndef NO_MANGLE_PUB_EXPORT_C_FNS_HPP
+#define NO_MANGLE_PUB_EXPORT_C_FNS_HPP
+
+#include <cstddef>
+#include <cstdint>
+#include <memory>
+
+namespace mylib {
+namespace ffi {
+
+/**
+ * The mode.
+ */
+enum class Mode : uint8_t {
+    Fast = 1,
+    Safe,
+};
+
+enum class Level {
+    Low,
+    High,
+};
+
+extern "C" {
+
+typedef struct Engine Engine;
+typedef struct RustEnum RustEnum;
+Engine *engine_new(Mode mode, Level level, RustEnum *e);
+void mylib_engine_free(Engine *engine);
+typedef struct HTTPClient HTTPClient;
+void http_client_free(HTTPClient *client);
+typedef struct Leaky Leaky;
+bool leaky_free(Leaky *leaky);
+
+} // extern "C"
+
+/** Frees the `Engine` with `mylib_engine_free`. */
+struct EngineDeleter {
+    void operator()(Engine *ptr) const noexcept { mylib_engine_free(ptr); }
+};
+using EnginePtr = std::unique_ptr<Engine, EngineDeleter>;
+
+/** Frees the `HTTPClient` with `http_client_free`. */
+struct HTTPClientDeleter {
+    void operator()(HTTPClient *ptr) const noexcept { http_client_free(ptr); }
+};
+using HTTPClientPtr = std::unique_ptr<HTTPClient, HTTPClientDeleter>;
+
+} // namespace ffi
+} // namespace mylib
+
+#endif /* NO_MANGLE_PUB_EXPORT_C_FNS_HPP */
+"#
+        );
+    }
+
+    #[test]
+    fn frees_with_deleters_returning_unit_aliases() {
+        let header = header(
+            r#"
+            pub type Unit = ();
+            pub struct Engine;
+            #[no_mangle]
+            pub extern "C" fn engine_free(engine: *mut Engine) -> Unit {}
+            "#,
+            &CppHeaderOptions {
+                unique_ptr_wrappers: true,
+                ..Default::default()
+            },
+        );
+        assert!(header.contains("void engine_free(Engine *engine);\n"));
+        assert!(header
+            .contains("    void operator()(Engine *ptr) const noexcept { engine_free(ptr); }\n"));
+    }
+
+    #[test]
+    fn declares_in_the_global_namespace() {
+        let header = header(
+            r#"
+            pub struct Engine;
+            #[no_mangle]
+            pub extern "C" fn engine_free(engine: *mut Engine) {}
+            "#,
+            &CppHeaderOptions {
+                namespace: String::new(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            header,
+            r#"#ifndef NO_MANGLE_PUB_EXPORT_C_FNS_HPP
+#define NO_MANGLE_PUB_EXPORT_C_FNS_HPP
+
+#include <cstddef>
+#include <cstdint>
+
+extern "C" {
+
+typedef struct Engine Engine;
+void engine_free(Engine *engine);
+
+} // extern "C"
+
+#endif /* NO_MANGLE_PUB_EXPORT_C_FNS_HPP */
+"#
+        );
+    }
+
+    #[test]
+    fn snake_cases_idents() {
+        assert_eq!(snake_case("Engine"), "engine");
+        assert_eq!(snake_case("HttpClient"), "http_client");
+        assert_eq!(snake_case("HTTPClient"), "http_client");
+        assert_eq!(snake_case("Vec3Buffer"), "vec3_buffer");
+        assert_eq!(snake_case("IO"), "io");
+    }
+}
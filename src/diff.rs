@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CfgPredicate, NoManglePubExportCFn, ParsedFile};
+
+/// An exported symbol along with the path to the file where it is defined.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub symbol: String,
+    pub path: PathBuf,
+}
+
+/// The difference between the exports found by two scans. Exports are matched by symbol. An export
+/// is changed when its file, signature or `#[cfg]` predicates differ, while moving within the file isn't a change.
+///
+/// Its [Display](fmt::Display) implementation prints one line per export, prefixed with `+`, `-` or `~`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportsDiff {
+    pub added: Vec<Export>,
+    pub removed: Vec<Export>,
+    pub changed: Vec<Export>,
+}
+
+impl ExportsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for ExportsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = [
+            ("+", &self.added),
+            ("-", &self.removed),
+            ("~", &self.changed),
+        ];
+        for (prefix, exports) in lines {
+            for export in exports {
+                writeln!(
+                    f,
+                    "{} {} ({})",
+                    prefix,
+                    export.symbol,
+                    export.path.display()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What makes two exports under the same symbol different.
+#[derive(PartialEq, Eq)]
+struct ExportSignature<'a> {
+    path: &'a Path,
+    inputs: Vec<(&'a str, &'a str)>,
+    output: Option<&'a str>,
+    cfg: Option<&'a CfgPredicate>,
+    export_cfg: Option<&'a CfgPredicate>,
+    symbol_cfg: Option<&'a CfgPredicate>,
+}
+
+impl<'a> ExportSignature<'a> {
+    fn new(path: &'a Path, no_mangle_pub_export_c_fn: &'a NoManglePubExportCFn) -> Self {
+        Self {
+            path,
+            inputs: no_mangle_pub_export_c_fn
+                .inputs
+                .iter()
+                .map(|fn_arg| (fn_arg.pat.as_str(), fn_arg.ty.as_str()))
+                .collect(),
+            output: no_mangle_pub_export_c_fn.output.as_deref(),
+            cfg: no_mangle_pub_export_c_fn.cfg.as_ref(),
+            export_cfg: no_mangle_pub_export_c_fn.export_cfg.as_ref(),
+            symbol_cfg: no_mangle_pub_export_c_fn.symbol_cfg.as_ref(),
+        }
+    }
+}
+
+// The same symbol may be exported by several functions, e.g. under mutually exclusive `#[cfg]`s
+fn exports_by_symbol(parsed_files: &[ParsedFile]) -> BTreeMap<&str, Vec<ExportSignature<'_>>> {
+    let mut exports: BTreeMap<&str, Vec<ExportSignature>> = BTreeMap::new();
+    parsed_files.iter().for_each(|parsed_file| {
+        parsed_file
+            .no_mangle_pub_export_c_fns
+            .0
+            .iter()
+            .for_each(|no_mangle_pub_export_c_fn| {
+                exports
+                    .entry(no_mangle_pub_export_c_fn.symbol.as_str())
+                    .or_default()
+                    .push(ExportSignature::new(
+                        &parsed_file.path,
+                        no_mangle_pub_export_c_fn,
+                    ))
+            })
+    });
+    exports
+}
+
+/// Compares the exports found by two scans, e.g. before and after an edit. The exports in each
+/// category are sorted by symbol.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{diff_exports, parse_for_no_mangle_pub_extern_c_fns};
+///
+/// let crate_root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+/// let parsed_files = parse_for_no_mangle_pub_extern_c_fns(crate_root.as_str());
+/// let diff = diff_exports(&[], &parsed_files);
+/// assert_eq!(diff.added[0].symbol, "s");
+/// assert!(diff.to_string().starts_with("+ s ("));
+/// assert!(diff_exports(&parsed_files, &parsed_files).is_empty());
+/// ```
+pub fn diff_exports(old: &[ParsedFile], new: &[ParsedFile]) -> ExportsDiff {
+    let (old, new) = (exports_by_symbol(old), exports_by_symbol(new));
+    let export = |symbol: &str, signatures: &[ExportSignature]| Export {
+        symbol: symbol.to_string(),
+        path: signatures
+            .first()
+            .map_or_else(PathBuf::new, |signature| signature.path.to_path_buf()),
+    };
+    let mut diff = ExportsDiff::default();
+    new.iter()
+        .for_each(|(symbol, signatures)| match old.get(symbol) {
+            None => diff.added.push(export(symbol, signatures)),
+            Some(old_signatures) if old_signatures != signatures => {
+                diff.changed.push(export(symbol, signatures))
+            }
+            Some(_) => {}
+        });
+    old.iter()
+        .filter(|(symbol, _)| !new.contains_key(*symbol))
+        .for_each(|(symbol, signatures)| diff.removed.push(export(symbol, signatures)));
+    diff
+}
@@ -0,0 +1,472 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use syn::{ext::IdentExt, spanned::Spanned, visit::Visit};
+
+use crate::cfg;
+use crate::module_tree::ModuleTree;
+use crate::resolve::{FileScopes, TypeResolver};
+use crate::scan::{parse_source_files, SourceFiles};
+use crate::traversal::invalid_glob;
+use crate::{
+    is_no_mangle_pub_export_c_fn, relativize_paths, rust_source_files, CfgSet, FnPartLocations,
+    LineColumnEnds, NoManglePubExportCFn, NoManglePubExportCFns, ParsedFile, ScanOptions,
+};
+
+/// Calls `on_item_fn` for every [syn::ItemFn] along with the path of the enclosing inline modules.
+/// Items disabled by `cfg_set` are skipped.
+struct ModulePathVisitor<'a, F> {
+    module_path: Vec<String>,
+    cfg_set: Option<&'a CfgSet>,
+    on_item_fn: F,
+}
+
+impl<'a, F> ModulePathVisitor<'a, F> {
+    fn new(module_path: Vec<String>, cfg_set: Option<&'a CfgSet>, on_item_fn: F) -> Self {
+        Self {
+            module_path,
+            cfg_set,
+            on_item_fn,
+        }
+    }
+}
+
+impl<'ast, F: FnMut(&[String], &syn::ItemFn)> Visit<'ast> for ModulePathVisitor<'_, F> {
+    fn visit_item(&mut self, node: &'ast syn::Item) {
+        let item_cfgs = cfg::cfg_predicates(cfg::item_attrs(node));
+        if cfg::is_cfg_enabled(self.cfg_set, &item_cfgs) {
+            syn::visit::visit_item(self, node);
+        }
+    }
+
+    fn visit_item_mod(&mut self, node: &'ast syn::ItemMod) {
+        self.module_path.push(node.ident.unraw().to_string());
+        syn::visit::visit_item_mod(self, node);
+        self.module_path.pop();
+    }
+
+    fn visit_item_fn(&mut self, node: &'ast syn::ItemFn) {
+        (self.on_item_fn)(&self.module_path, node)
+    }
+}
+
+/// A function in the original sources that a function found in the expanded source may come from.
+struct OriginalFn {
+    path: PathBuf,
+    module_path: Vec<String>,
+    location: LineColumnEnds,
+    part_locations: FnPartLocations,
+}
+
+/// The original sources of the crate, read once so that the exports and the other functions are
+/// found in the same files.
+struct CrateSources {
+    /// The files selected by [ScanOptions], without the ones pulled in by `include!`.
+    paths: Vec<PathBuf>,
+    /// The contents of all files that could be read, including the ones pulled in by `include!`.
+    sources: HashMap<PathBuf, String>,
+    /// The files that could be parsed.
+    files: Vec<(PathBuf, syn::File)>,
+    module_tree: ModuleTree,
+}
+
+impl CrateSources {
+    /// Reads and parses the files selected by `options` along with the files pulled in by `include!`.
+    /// Files that can't be read or parsed are skipped.
+    fn read(crate_root: &str, options: &ScanOptions) -> Result<Self, globset::Error> {
+        let mut crate_sources = Self {
+            paths: rust_source_files(crate_root, options)?.collect(),
+            sources: HashMap::new(),
+            files: Vec::new(),
+            module_tree: ModuleTree::default(),
+        };
+        let mut read: BTreeSet<PathBuf> = crate_sources.paths.iter().cloned().collect();
+        let mut pending = crate_sources.paths.clone();
+        // Included files may include other files in turn
+        while !pending.is_empty() {
+            pending
+                .drain(..)
+                .for_each(|path| crate_sources.add_file(path, options));
+            pending = crate_sources
+                .module_tree
+                .included()
+                .iter()
+                .filter(|path| read.insert(path.to_path_buf()))
+                .cloned()
+                .collect();
+        }
+        Ok(crate_sources)
+    }
+
+    fn add_file(&mut self, path: PathBuf, options: &ScanOptions) {
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(_) => return,
+        };
+        if let Ok(file) = syn::parse_file(&source) {
+            self.module_tree.add_file(&path, &file, &options.env);
+            self.files.push((path.clone(), file));
+        }
+        self.sources.insert(path, source);
+    }
+
+    /// Scans the files for exports the same way as [parse_for_no_mangle_pub_extern_c_fns_with_options](crate::parse_for_no_mangle_pub_extern_c_fns_with_options),
+    /// except that the paths aren't made relative.
+    fn parse_exports(&self, options: &ScanOptions) -> Vec<ParsedFile> {
+        parse_source_files(
+            self.paths.iter().cloned(),
+            &SourceFiles::InMemory(&self.sources),
+            options,
+        )
+    }
+
+    /// Indexes all functions in files and modules enabled by `options.cfg_set` by ident, regardless of
+    /// whether they are exported. Procedural macros typically keep the ident of the function they transform.
+    fn index_original_fns(&self, options: &ScanOptions) -> HashMap<String, Vec<OriginalFn>> {
+        let cfg_set = options.cfg_set.as_ref();
+        let mut original_fns: HashMap<String, Vec<OriginalFn>> = HashMap::new();
+        self.files
+            .iter()
+            .filter(|(path, file)| {
+                let mut cfgs = self.module_tree.cfgs(path);
+                cfgs.extend(cfg::cfg_predicates(&file.attrs));
+                cfg::is_cfg_enabled(cfg_set, &cfgs)
+            })
+            .for_each(|(path, file)| {
+                let source = &self.sources[path];
+                ModulePathVisitor::new(
+                    self.module_tree.module_path(path),
+                    cfg_set,
+                    |module_path: &[String], node: &syn::ItemFn| {
+                        let mut location = LineColumnEnds::new(&node.span());
+                        let mut part_locations = FnPartLocations::new(node);
+                        if options.utf16_columns {
+                            location.fill_utf16_columns(source);
+                            part_locations.fill_utf16_columns(source);
+                        }
+                        original_fns
+                            .entry(node.sig.ident.unraw().to_string())
+                            .or_default()
+                            .push(OriginalFn {
+                                path: path.clone(),
+                                module_path: module_path.to_vec(),
+                                location,
+                                part_locations,
+                            })
+                    },
+                )
+                .visit_file(file);
+            });
+        original_fns
+    }
+}
+
+fn find_original_fn<'a>(
+    original_fns: &'a HashMap<String, Vec<OriginalFn>>,
+    ident: &str,
+    module_path: &[String],
+) -> Option<&'a OriginalFn> {
+    let candidates = original_fns.get(ident)?;
+    if let [original_fn] = candidates.as_slice() {
+        return Some(original_fn);
+    }
+    let mut same_module = candidates
+        .iter()
+        .filter(|original_fn| original_fn.module_path == module_path);
+    match (same_module.next(), same_module.next()) {
+        (Some(original_fn), None) => Some(original_fn),
+        _ => None,
+    }
+}
+
+/// Parses already expanded source, e.g. the output of [`cargo expand`](https://github.com/dtolnay/cargo-expand),
+/// for `#[no_mangle] pub export "C"` functions. This makes it possible to find functions generated by
+/// procedural macros.
+///
+/// When `crate_root` is given, the functions are mapped back to the original files, which are selected
+/// and scanned according to `options`, e.g. [ScanOptions::cfg_set] should match the configuration the
+/// source was expanded with:
+///
+/// 1. A function exported under the same symbol found by [parse_for_no_mangle_pub_extern_c_fns_with_options](crate::parse_for_no_mangle_pub_extern_c_fns_with_options)
+///    is used, if it is unique. This covers functions written by hand and the ones generated by `macro_rules!` macros.
+/// 2. Otherwise, a function with the same ident in the original sources is used, if it is unique. When there
+///    are several, the one whose module path, following the `mod` declarations, matches the nesting of inline
+///    modules in the expanded source is used.
+///
+/// Functions that can't be mapped are reported with `expanded_path` and their location in the expanded source.
+/// [ParsedFile::type_defs] is left empty, since types can't be mapped back the same way. The result is
+/// sorted by path.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{parse_expanded_source_for_no_mangle_pub_extern_c_fns, ScanOptions};
+///
+/// // The output of `cargo expand`, e.g. read from `std::io::stdin()`
+/// let expanded_source = r#"
+///     mod unused {
+///         #[no_mangle]
+///         pub extern "C" fn s() {}
+///     }
+/// "#;
+/// let crate_root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+/// let parsed_files = parse_expanded_source_for_no_mangle_pub_extern_c_fns(
+///     expanded_source.as_bytes(),
+///     "<stdin>",
+///     Some(crate_root.as_str()),
+///     &ScanOptions::default(),
+/// ).unwrap();
+/// assert!(parsed_files[0].path.ends_with("unused.rs"));
+/// ```
+///
+/// # Errors
+///
+/// Fails if the expanded source can't be read or parsed, or if any of the patterns in [ScanOptions::include]
+/// and [ScanOptions::exclude] isn't a valid glob.
+pub fn parse_expanded_source_for_no_mangle_pub_extern_c_fns<R: Read>(
+    mut expanded_source: R,
+    expanded_path: &str,
+    crate_root: Option<&str>,
+    options: &ScanOptions,
+) -> std::io::Result<Vec<ParsedFile>> {
+    let mut buffer = String::new();
+    expanded_source.read_to_string(&mut buffer)?;
+    let file = syn::parse_file(&buffer)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))?;
+    let crate_sources = crate_root
+        .map(|crate_root| CrateSources::read(crate_root, options))
+        .transpose()
+        .map_err(invalid_glob)?;
+
+    // The expanded source has already been configured, so its `#[cfg]`s are left alone
+    let mut no_mangle_pub_export_c_fns: Vec<(Vec<String>, NoManglePubExportCFn)> = Vec::new();
+    ModulePathVisitor::new(
+        Vec::new(),
+        None,
+        |module_path: &[String], node: &syn::ItemFn| {
+            if is_no_mangle_pub_export_c_fn(node) {
+                no_mangle_pub_export_c_fns.extend(
+                    NoManglePubExportCFn::new(node, None, None, module_path).map(
+                        |no_mangle_pub_export_c_fn| {
+                            (module_path.to_vec(), no_mangle_pub_export_c_fn)
+                        },
+                    ),
+                );
+            }
+        },
+    )
+    .visit_file(&file);
+    // All modules are inline in the expanded source
+    let mut type_resolver = TypeResolver::default();
+    type_resolver.add_file(&[], FileScopes::new(&file.items, None));
+    no_mangle_pub_export_c_fns
+        .iter_mut()
+        .for_each(|(_, no_mangle_pub_export_c_fn)| {
+            type_resolver.resolve_signature(no_mangle_pub_export_c_fn);
+            if options.utf16_columns {
+                no_mangle_pub_export_c_fn
+                    .location
+                    .fill_utf16_columns(&buffer);
+                if let Some(part_locations) = &mut no_mangle_pub_export_c_fn.part_locations {
+                    part_locations.fill_utf16_columns(&buffer);
+                }
+            }
+        });
+
+    let original_fns = crate_sources
+        .as_ref()
+        .map(|crate_sources| crate_sources.index_original_fns(options))
+        .unwrap_or_default();
+    let mut original_exports: HashMap<String, Vec<(PathBuf, NoManglePubExportCFn)>> =
+        HashMap::new();
+    crate_sources
+        .as_ref()
+        .map(|crate_sources| crate_sources.parse_exports(options))
+        .unwrap_or_default()
+        .into_iter()
+        .for_each(|parsed_file| {
+            let path = parsed_file.path;
+            parsed_file
+                .no_mangle_pub_export_c_fns
+                .0
+                .into_iter()
+                .for_each(|no_mangle_pub_export_c_fn| {
+                    original_exports
+                        .entry(no_mangle_pub_export_c_fn.symbol.clone())
+                        .or_default()
+                        .push((path.clone(), no_mangle_pub_export_c_fn))
+                })
+        });
+
+    let mut parsed_files: Vec<ParsedFile> = Vec::new();
+    no_mangle_pub_export_c_fns.into_iter().for_each(
+        |(module_path, mut no_mangle_pub_export_c_fn)| {
+            let path = match original_exports
+                .remove(&no_mangle_pub_export_c_fn.symbol)
+                .as_deref()
+            {
+                Some([(path, original)]) => {
+                    no_mangle_pub_export_c_fn.expanded_from = original.expanded_from.clone();
+                    no_mangle_pub_export_c_fn.location = original.location.clone();
+                    no_mangle_pub_export_c_fn.part_locations = original.part_locations.clone();
+                    path.clone()
+                }
+                _ => match find_original_fn(
+                    &original_fns,
+                    &no_mangle_pub_export_c_fn.ident,
+                    &module_path,
+                ) {
+                    Some(original_fn) => {
+                        no_mangle_pub_export_c_fn.location = original_fn.location.clone();
+                        no_mangle_pub_export_c_fn.part_locations =
+                            Some(original_fn.part_locations.clone());
+                        original_fn.path.clone()
+                    }
+                    None => PathBuf::from(expanded_path),
+                },
+            };
+            match parsed_files
+                .iter_mut()
+                .find(|parsed_file| parsed_file.path == path)
+            {
+                Some(parsed_file) => parsed_file
+                    .no_mangle_pub_export_c_fns
+                    .0
+                    .push(no_mangle_pub_export_c_fn),
+                None => parsed_files.push(ParsedFile {
+                    path,
+                    no_mangle_pub_export_c_fns: NoManglePubExportCFns(vec![
+                        no_mangle_pub_export_c_fn,
+                    ]),
+                    type_defs: Vec::new(),
+                }),
+            }
+        },
+    );
+    if let Some(crate_root) = crate_root {
+        relativize_paths(&mut parsed_files, Path::new(crate_root), options);
+    }
+    parsed_files.sort_by(|lhs, rhs| lhs.path.cmp(&rhs.path));
+    Ok(parsed_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_crate(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let crate_root = tempfile::tempdir().unwrap();
+        files.iter().for_each(|(path, source)| {
+            let path = crate_root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        });
+        crate_root
+    }
+
+    fn paths(expanded_source: &str, crate_root: &Path, options: &ScanOptions) -> Vec<String> {
+        parse_expanded_source_for_no_mangle_pub_extern_c_fns(
+            expanded_source.as_bytes(),
+            "<stdin>",
+            crate_root.to_str(),
+            options,
+        )
+        .unwrap()
+        .iter()
+        .flat_map(|parsed_file| {
+            parsed_file
+                .no_mangle_pub_export_c_fns
+                .0
+                .iter()
+                .map(move |no_mangle_pub_export_c_fn| {
+                    format!(
+                        "{}: {}",
+                        parsed_file.path.display(),
+                        no_mangle_pub_export_c_fn.rust_path
+                    )
+                })
+        })
+        .collect()
+    }
+
+    #[test]
+    fn follows_path_attributes_and_sorts_by_path() {
+        let crate_root = write_crate(&[
+            (
+                "src/lib.rs",
+                "mod other;\n#[path = \"ffi_impl.rs\"]\nmod ffi;\n",
+            ),
+            ("src/other.rs", "pub fn decode() {}\n"),
+            ("src/ffi_impl.rs", "#[export]\npub fn decode() {}\n"),
+        ]);
+        let expanded_source = r#"
+            mod other {
+                #[no_mangle]
+                pub extern "C" fn encode() {}
+                pub fn decode() {}
+            }
+            mod ffi {
+                #[no_mangle]
+                pub extern "C" fn decode() {}
+            }
+        "#;
+        let options = ScanOptions {
+            relative_paths: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(expanded_source, crate_root.path(), &options),
+            vec![
+                "<stdin>: crate::other::encode",
+                "src/ffi_impl.rs: crate::ffi::decode"
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_scan_options() {
+        let crate_root = write_crate(&[
+            (
+                "src/lib.rs",
+                concat!(
+                    "#[cfg(feature = \"gpu\")]\n#[path = \"gpu.rs\"]\nmod backend;\n",
+                    "#[cfg(not(feature = \"gpu\"))]\n#[path = \"cpu.rs\"]\nmod backend;\n",
+                ),
+            ),
+            ("src/gpu.rs", "#[export]\npub fn run() {}\n"),
+            ("src/cpu.rs", "#[export]\npub fn run() {}\n"),
+        ]);
+        let expanded_source = "mod backend { #[no_mangle] pub extern \"C\" fn run() {} }";
+        let mut options = ScanOptions {
+            relative_paths: true,
+            ..Default::default()
+        };
+        // Without a cfg set, the function may come from either file
+        assert_eq!(
+            paths(expanded_source, crate_root.path(), &options),
+            vec!["<stdin>: crate::backend::run"]
+        );
+
+        options.cfg_set = Some(CfgSet::default());
+        assert_eq!(
+            paths(expanded_source, crate_root.path(), &options),
+            vec!["src/cpu.rs: crate::backend::run"]
+        );
+
+        options.exclude = vec!["src/cpu.rs".to_string()];
+        assert_eq!(
+            paths(expanded_source, crate_root.path(), &options),
+            vec!["<stdin>: crate::backend::run"]
+        );
+
+        options.exclude = vec!["[".to_string()];
+        assert!(parse_expanded_source_for_no_mangle_pub_extern_c_fns(
+            expanded_source.as_bytes(),
+            "<stdin>",
+            crate_root.path().to_str(),
+            &options,
+        )
+        .is_err());
+    }
+}
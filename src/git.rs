@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::scan::{parse_source_files, SourceFiles};
+use crate::traversal::{invalid_glob, PathFilter};
+use crate::{relativize_paths, ParsedFile, ScanOptions};
+
+fn git_error(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, message)
+}
+
+/// Lists the blobs under `crate_root/src` at `revision` as pairs of object ids and paths relative to `crate_root`.
+fn ls_tree(crate_root: &str, revision: &str) -> std::io::Result<Vec<(String, PathBuf)>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(crate_root)
+        .args(["ls-tree", "-r", "-z", revision, "--", "src"])
+        .output()?;
+    if !output.status.success() {
+        return Err(git_error(format!(
+            "git ls-tree failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // Each entry is "<mode> SP <type> SP <object> TAB <path>" followed by NUL
+    let blobs = output
+        .stdout
+        .split(|byte| *byte == 0)
+        .filter_map(|entry| {
+            let entry = std::str::from_utf8(entry).ok()?;
+            let (meta, path) = entry.split_once('\t')?;
+            let mut meta = meta.split(' ');
+            let (mode, object) = (meta.next()?, meta.nth(1)?);
+            // Symlinks and submodules have other modes
+            let is_file = mode == "100644" || mode == "100755";
+            (is_file && path.ends_with(".rs")).then(|| (object.to_string(), PathBuf::from(path)))
+        })
+        .collect();
+    Ok(blobs)
+}
+
+/// Reads the blobs requested from `git cat-file --batch`, in order.
+fn read_blobs<R: BufRead>(
+    stdout: &mut R,
+    objects: &[String],
+) -> std::io::Result<Vec<Option<String>>> {
+    let mut contents = Vec::with_capacity(objects.len());
+    for object in objects {
+        // The header is "<object> <type> <size>", or "<object> missing"
+        let mut header = String::new();
+        stdout.read_line(&mut header)?;
+        let size: usize = header
+            .trim_end()
+            .rsplit(' ')
+            .next()
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| git_error(format!("git cat-file failed to read {}", object)))?;
+        // The contents are followed by LF
+        let mut blob = vec![0; size + 1];
+        stdout.read_exact(&mut blob)?;
+        blob.pop();
+        contents.push(String::from_utf8(blob).ok());
+    }
+    Ok(contents)
+}
+
+/// Reads the contents of `objects` in one `git cat-file --batch` process. Blobs that aren't valid UTF-8 are left out.
+fn cat_file(crate_root: &str, objects: &[String]) -> std::io::Result<Vec<Option<String>>> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(crate_root)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+    // Requests are written from another thread so that neither pipe can fill up and block git
+    let contents = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || -> std::io::Result<()> {
+            for object in objects {
+                writeln!(stdin, "{}", object)?;
+            }
+            Ok(())
+        });
+        let contents = read_blobs(&mut stdout, objects);
+        if contents.is_err() {
+            // Once nothing reads its output, git stops reading requests, which would block the writer forever
+            drop(stdout);
+            let _ = child.kill();
+        }
+        let written = writer.join().expect("the writer doesn't panic");
+        contents.and_then(|contents| written.map(|()| contents))
+    });
+    // The process is reaped even if reading failed
+    let status = child.wait();
+    let contents = contents?;
+    status?;
+    Ok(contents)
+}
+
+/// Does the same as [parse_for_no_mangle_pub_extern_c_fns_with_options](crate::parse_for_no_mangle_pub_extern_c_fns_with_options)
+/// for the state of `crate_root/src/**` at `revision`, e.g. a release tag, a branch or a commit hash.
+///
+/// The files are read from the object database of the git repository that contains `crate_root`,
+/// so the worktree is neither checked out nor read. Paths in the output are the same as for the
+/// worktree, which makes the results comparable with [diff_exports](crate::diff_exports).
+/// [ScanOptions::include] and [ScanOptions::exclude] apply, while ignored files aren't in the
+/// repository in the first place. Requires `git` to be available in `PATH`.
+///
+/// # Example
+///
+/// ```no_run
+/// use no_mangle_pub_export_c_fn::{diff_exports, parse_for_no_mangle_pub_extern_c_fns, parse_git_revision_for_no_mangle_pub_extern_c_fns, ScanOptions};
+///
+/// let released = parse_git_revision_for_no_mangle_pub_extern_c_fns(".", "v1.0.0", &ScanOptions::default()).unwrap();
+/// let current = parse_for_no_mangle_pub_extern_c_fns(".");
+/// print!("{}", diff_exports(&released, &current));
+/// ```
+///
+/// # Errors
+///
+/// Fails if `git` can't be run, if `crate_root` isn't in a git repository, if `revision` can't be
+/// resolved, or if a pattern in `options` isn't a valid glob.
+pub fn parse_git_revision_for_no_mangle_pub_extern_c_fns(
+    crate_root: &str,
+    revision: &str,
+    options: &ScanOptions,
+) -> std::io::Result<Vec<ParsedFile>> {
+    let path_filter = PathFilter::new(options).map_err(invalid_glob)?;
+    let (objects, paths): (Vec<String>, Vec<PathBuf>) = ls_tree(crate_root, revision)?
+        .into_iter()
+        .filter(|(_, path)| path_filter.is_match(path))
+        .unzip();
+    let contents = cat_file(crate_root, &objects)?;
+    let sources: HashMap<PathBuf, String> = paths
+        .into_iter()
+        .zip(contents)
+        .filter_map(|(path, contents)| Some((Path::new(crate_root).join(path), contents?)))
+        .collect();
+    let mut parsed_files = parse_source_files(
+        sources.keys().cloned(),
+        &SourceFiles::InMemory(&sources),
+        options,
+    );
+    relativize_paths(&mut parsed_files, Path::new(crate_root), options);
+    Ok(parsed_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(repo: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    fn symbols(parsed_files: &[ParsedFile]) -> Vec<(String, String)> {
+        parsed_files
+            .iter()
+            .flat_map(|parsed_file| {
+                parsed_file.no_mangle_pub_export_c_fns.0.iter().map(
+                    move |no_mangle_pub_export_c_fn| {
+                        (
+                            parsed_file.path.to_string_lossy().into_owned(),
+                            no_mangle_pub_export_c_fn.symbol.clone(),
+                        )
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scans_revisions() {
+        let repo = tempfile::tempdir().unwrap();
+        let src_dir = repo.path().join("src");
+        std::fs::create_dir(&src_dir).unwrap();
+        git(repo.path(), &["init", "-q"]);
+        std::fs::write(
+            src_dir.join("lib.rs"),
+            "mod a;\n#[no_mangle] pub extern \"C\" fn f() {}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            src_dir.join("a.rs"),
+            "#[no_mangle] pub extern \"C\" fn g() {}\n",
+        )
+        .unwrap();
+        git(repo.path(), &["add", "-A"]);
+        git(repo.path(), &["commit", "-q", "-m", "first"]);
+        let first = git(repo.path(), &["rev-parse", "HEAD"]);
+        std::fs::write(
+            src_dir.join("lib.rs"),
+            "#[no_mangle] pub extern \"C\" fn h() {}\n",
+        )
+        .unwrap();
+        std::fs::remove_file(src_dir.join("a.rs")).unwrap();
+        git(repo.path(), &["add", "-A"]);
+        git(repo.path(), &["commit", "-q", "-m", "second"]);
+        // The worktree isn't read
+        std::fs::write(
+            src_dir.join("lib.rs"),
+            "#[no_mangle] pub extern \"C\" fn w() {}\n",
+        )
+        .unwrap();
+
+        let crate_root = repo.path().to_str().unwrap();
+        let options = ScanOptions {
+            relative_paths: true,
+            ..Default::default()
+        };
+        let scan = |revision: &str| {
+            parse_git_revision_for_no_mangle_pub_extern_c_fns(crate_root, revision, &options)
+        };
+        let pair = |path: &str, symbol: &str| (path.to_string(), symbol.to_string());
+        assert_eq!(
+            symbols(&scan(&first).unwrap()),
+            vec![pair("src/a.rs", "g"), pair("src/lib.rs", "f")]
+        );
+        assert_eq!(
+            symbols(&scan("HEAD").unwrap()),
+            vec![pair("src/lib.rs", "h")]
+        );
+        assert_eq!(
+            symbols(&scan("HEAD~1").unwrap()),
+            symbols(&scan(&first).unwrap())
+        );
+
+        let err = scan("no-such-revision").err().unwrap();
+        assert!(
+            err.to_string().starts_with("git ls-tree failed: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn fails_instead_of_blocking_on_missing_objects() {
+        let repo = tempfile::tempdir().unwrap();
+        git(repo.path(), &["init", "-q"]);
+        // Far more requests than fit in a pipe, so that the writer would block if git stopped reading
+        let objects: Vec<String> = (0..100_000).map(|i| format!("{:040x}", i)).collect();
+        let err = cat_file(repo.path().to_str().unwrap(), &objects)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("git cat-file failed to read {}", objects[0])
+        );
+    }
+}
@@ -0,0 +1,594 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::c_header::{
+    c_param_name, declarations, exported_symbols, type_defs_by_ident, DeclaredFn,
+};
+use crate::{NoManglePubExportCFn, ParsedFile};
+
+/// Options for [generate_go_bindings].
+#[derive(Debug, Clone)]
+pub struct GoBindingsOptions {
+    /// The name of the Go package.
+    pub package: String,
+    /// The C header included by the cgo preamble, e.g. the one written by
+    /// [generate_c_header](crate::generate_c_header).
+    pub header: String,
+    /// The flags of the `#cgo LDFLAGS` directive, e.g. `-L${SRCDIR}/lib -lmylib`. The directive is
+    /// left out if empty.
+    pub ldflags: String,
+    /// The same as [CHeaderOptions::callback_typedef_name](crate::CHeaderOptions::callback_typedef_name),
+    /// which has to match the one the header was generated with.
+    pub callback_typedef_name: String,
+    /// The names of the wrappers by symbol, overriding the camel case ones, e.g. to resolve clashes.
+    pub wrapper_names: BTreeMap<String, String>,
+}
+
+impl Default for GoBindingsOptions {
+    fn default() -> Self {
+        Self {
+            package: "ffi".to_string(),
+            header: "bindings.h".to_string(),
+            ldflags: String::new(),
+            callback_typedef_name: "{symbol}_{param}_fn".to_string(),
+            wrapper_names: BTreeMap::new(),
+        }
+    }
+}
+
+/// Generates the source of a Go package that calls all `#[no_mangle] pub export "C"` functions in
+/// the given parsed files through cgo. The cgo preamble includes [GoBindingsOptions::header], which
+/// should declare the functions the way [generate_c_header](crate::generate_c_header) does.
+///
+/// Each function that can be declared in the C header gets a wrapper named after its symbol in
+/// camel case, e.g. `EngineNew` for `engine_new`, or after [GoBindingsOptions::wrapper_names]. Functions
+/// that share a symbol, e.g. under mutually exclusive `#[cfg]`s, get a single wrapper. A comment is
+/// emitted instead of a wrapper for the functions that the C header doesn't declare, for the symbols
+/// that it only declares in `#if` guards, for the symbols that are Go keywords, e.g. `type`, which cgo
+/// can't refer to, and for the names that are already taken by an earlier wrapper or an opaque type,
+/// e.g. `FooBar` for both `foo_bar` and `fooBar`. Integers, floating point numbers, `bool` and
+/// `char` are converted between their Go and C types, e.g. `int32` and `C.int32_t`. Pointers to
+/// [opaque](crate::TypeDef::is_opaque) types are passed as pointers to opaque Go types, e.g. `*Engine`
+/// for `*mut Engine`, `void` pointers as `unsafe.Pointer`, other types as their cgo types, e.g.
+/// `*C.Config`.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{generate_go_bindings, GoBindingsOptions, ParsedFile};
+///
+/// let parsed_files: Vec<ParsedFile> = serde_json::from_str(r#"[{
+///     "path": "src/lib.rs",
+///     "no_mangle_pub_export_c_fns": [{
+///         "ident": "engine_run",
+///         "symbol": "engine_run",
+///         "inputs": [{ "pat": "engine", "ty": "& mut Engine" }, { "pat": "steps", "ty": "u32" }],
+///         "output": "bool",
+///         "location": { "start_line": 1, "start_column": 0, "end_line": 4, "end_column": 1 }
+///     }],
+///     "type_defs": [{ "ident": "Engine", "kind": "Struct", "doc": null }]
+/// }]"#).unwrap();
+/// let options = GoBindingsOptions {
+///     package: "engine".to_string(),
+///     ldflags: "-L${SRCDIR} -lengine".to_string(),
+///     ..Default::default()
+/// };
+/// let bindings = generate_go_bindings(&parsed_files, &options);
+/// assert!(bindings.contains("#cgo LDFLAGS: -L${SRCDIR} -lengine\n#include \"bindings.h\"\n*/\nimport \"C\"\n"));
+/// assert!(bindings.contains("type Engine C.Engine\n"));
+/// assert!(bindings.contains(
+///     "func EngineRun(engine *Engine, steps uint32) bool {\n\
+///      \treturn bool(C.engine_run((*C.Engine)(engine), C.uint32_t(steps)))\n\
+///      }\n"
+/// ));
+/// ```
+pub fn generate_go_bindings(parsed_files: &[ParsedFile], options: &GoBindingsOptions) -> String {
+    let type_defs = type_defs_by_ident(parsed_files);
+    let declarations = declarations(
+        parsed_files,
+        &type_defs,
+        &options.callback_typedef_name,
+        BTreeSet::new(),
+    );
+    let declared_fns: HashMap<*const NoManglePubExportCFn, &DeclaredFn> = declarations
+        .declared_fns
+        .iter()
+        .map(|declared_fn| {
+            (
+                declared_fn.no_mangle_pub_export_c_fn as *const _,
+                declared_fn,
+            )
+        })
+        .collect();
+    let undeclared_fns: HashMap<*const NoManglePubExportCFn, &str> = declarations
+        .undeclared_fns
+        .iter()
+        .map(|(no_mangle_pub_export_c_fn, unsupported)| {
+            (*no_mangle_pub_export_c_fn as *const _, unsupported.as_str())
+        })
+        .collect();
+    // Wrappers share the package scope with the opaque types
+    let mut names: BTreeMap<String, String> = declarations
+        .opaque_types
+        .iter()
+        .map(|ident| (ident.clone(), format!("the opaque type `{}`", ident)))
+        .collect();
+    let mut wrapped_symbols: BTreeSet<&str> = BTreeSet::new();
+    let mut wrappers: Vec<String> = Vec::new();
+    parsed_files
+        .iter()
+        .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+        .for_each(|no_mangle_pub_export_c_fn| {
+            let key = no_mangle_pub_export_c_fn as *const _;
+            let declared_fn = match (declared_fns.get(&key), undeclared_fns.get(&key)) {
+                (Some(declared_fn), _) => declared_fn,
+                (None, Some(unsupported)) => {
+                    return wrappers.push(format!(
+                        "// `{}` is not wrapped: `{}` has no C equivalent\n",
+                        no_mangle_pub_export_c_fn.symbol, unsupported
+                    ))
+                }
+                (None, None) => return,
+            };
+            exported_symbols(no_mangle_pub_export_c_fn)
+                .into_iter()
+                .filter(|(symbol, _)| wrapped_symbols.insert(symbol))
+                .for_each(|(symbol, _)| {
+                    wrappers.push(go_wrapper_or_note(
+                        declared_fn,
+                        symbol,
+                        &declarations.guarded_symbols,
+                        &declarations.opaque_types,
+                        options,
+                        &mut names,
+                    ))
+                });
+        });
+
+    let mut bindings = format!(
+        "// Code generated by no_mangle_pub_export_c_fn. DO NOT EDIT.\n\
+         \n\
+         package {}\n\
+         \n\
+         /*\n",
+        options.package
+    );
+    if !options.ldflags.is_empty() {
+        bindings.push_str(&format!("#cgo LDFLAGS: {}\n", options.ldflags));
+    }
+    bindings.push_str(&format!(
+        "#include \"{}\"\n\
+         */\n\
+         import \"C\"\n",
+        options.header
+    ));
+    if wrappers
+        .iter()
+        .any(|wrapper| wrapper.contains("unsafe.Pointer"))
+    {
+        bindings.push_str("\nimport \"unsafe\"\n");
+    }
+    declarations.opaque_types.iter().for_each(|ident| {
+        bindings.push_str(&format!(
+            "\n// {0} is an opaque handle.\ntype {0} C.{0}\n",
+            ident
+        ))
+    });
+    wrappers.iter().for_each(|wrapper| {
+        bindings.push('\n');
+        bindings.push_str(wrapper);
+    });
+    bindings
+}
+
+/// A type as seen from Go and from cgo, e.g. `int32` and `C.int32_t`.
+struct GoType {
+    go: String,
+    cgo: String,
+}
+
+impl GoType {
+    /// Translates a C type of the header, e.g. `const uint8_t *`.
+    fn new(c_type: &str, opaque_types: &[String]) -> Self {
+        let c_type = c_type.replace("/* nullable */", "");
+        let pointers = c_type.matches('*').count();
+        let base = c_type
+            .replace('*', " ")
+            .split_whitespace()
+            .filter(|word| *word != "const")
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let cgo = match (base.as_str(), pointers) {
+            // cgo has no `*C.void`
+            ("void", 1..) => format!("{}unsafe.Pointer", "*".repeat(pointers - 1)),
+            _ => format!("{}{}", "*".repeat(pointers), cgo_type_of_c_type(&base)),
+        };
+        let go = match (go_type_of_c_type(&base), pointers) {
+            (Some(go), 0) => go.to_string(),
+            (_, 1) if opaque_types.contains(&base) => format!("*{}", base),
+            _ => cgo.clone(),
+        };
+        Self { go, cgo }
+    }
+
+    fn to_cgo(&self, expr: &str) -> String {
+        convert(expr, &self.go, &self.cgo)
+    }
+
+    fn to_go(&self, expr: &str) -> String {
+        convert(expr, &self.cgo, &self.go)
+    }
+}
+
+fn convert(expr: &str, from: &str, to: &str) -> String {
+    match (from == to, to.starts_with('*')) {
+        (true, _) => expr.to_string(),
+        (false, true) => format!("({})({})", to, expr),
+        (false, false) => format!("{}({})", to, expr),
+    }
+}
+
+/// The Go type that C integers, floating point numbers and `bool` are converted to. Types whose size
+/// depends on the platform, e.g. `long`, and `char`, whose signedness does, are left as they are.
+fn go_type_of_c_type(c_type: &str) -> Option<&'static str> {
+    Some(match c_type {
+        "int8_t" | "signed char" => "int8",
+        "int16_t" | "short" => "int16",
+        "int32_t" | "int" => "int32",
+        "int64_t" | "long long" => "int64",
+        "intptr_t" => "int",
+        "uint8_t" | "unsigned char" => "uint8",
+        "uint16_t" | "unsigned short" => "uint16",
+        "uint32_t" | "unsigned int" => "uint32",
+        "uint64_t" | "unsigned long long" => "uint64",
+        "uintptr_t" => "uintptr",
+        "float" => "float32",
+        "double" => "float64",
+        "bool" => "bool",
+        _ => return None,
+    })
+}
+
+/// E.g. `C.uint` for `unsigned int`.
+fn cgo_type_of_c_type(c_type: &str) -> String {
+    let cgo_type = match c_type {
+        "signed char" => "schar",
+        "unsigned char" => "uchar",
+        "unsigned short" => "ushort",
+        "unsigned int" => "uint",
+        "unsigned long" => "ulong",
+        "long long" => "longlong",
+        "unsigned long long" => "ulonglong",
+        other => other,
+    };
+    format!("C.{}", cgo_type)
+}
+
+/// The wrapper of the function under `symbol`, or a comment on why there is none. `names` holds the
+/// names taken in the package along with what they are taken by.
+fn go_wrapper_or_note(
+    declared_fn: &DeclaredFn,
+    symbol: &str,
+    guarded_symbols: &BTreeSet<&str>,
+    opaque_types: &[String],
+    options: &GoBindingsOptions,
+    names: &mut BTreeMap<String, String>,
+) -> String {
+    if guarded_symbols.contains(symbol) {
+        return format!(
+            "// `{}` is not wrapped: its declaration depends on `#[cfg]`s, scan with a cfg set\n",
+            symbol
+        );
+    }
+    if is_go_keyword(symbol) {
+        return format!(
+            "// `{}` is not wrapped: cgo can't refer to a Go keyword\n",
+            symbol
+        );
+    }
+    let name = options
+        .wrapper_names
+        .get(symbol)
+        .cloned()
+        .unwrap_or_else(|| camel_case(symbol));
+    if let Some(taken_by) = names.get(&name) {
+        return format!(
+            "// `{}` is not wrapped: `{}` is taken by {}, rename it with GoBindingsOptions::wrapper_names\n",
+            symbol, name, taken_by
+        );
+    }
+    names.insert(name.clone(), format!("the wrapper of `{}`", symbol));
+    go_wrapper(declared_fn, symbol, &name, opaque_types)
+}
+
+fn go_wrapper(
+    declared_fn: &DeclaredFn,
+    symbol: &str,
+    name: &str,
+    opaque_types: &[String],
+) -> String {
+    let no_mangle_pub_export_c_fn = declared_fn.no_mangle_pub_export_c_fn;
+    let params: Vec<(String, GoType)> = no_mangle_pub_export_c_fn
+        .inputs
+        .iter()
+        .zip(&declared_fn.input_c_types)
+        .enumerate()
+        .map(|(index, (fn_arg, c_type))| {
+            let name = c_param_name(&fn_arg.pat).unwrap_or_else(|| format!("arg{}", index));
+            (go_param_name(name), GoType::new(c_type, opaque_types))
+        })
+        .collect();
+    let output = match declared_fn.output_c_type.as_str() {
+        "void" => None,
+        c_type => Some(GoType::new(c_type, opaque_types)),
+    };
+
+    let mut wrapper = String::new();
+    if let Some(doc) = &no_mangle_pub_export_c_fn.doc {
+        doc.lines().for_each(|line| match line.is_empty() {
+            true => wrapper.push_str("//\n"),
+            false => wrapper.push_str(&format!("// {}\n", line)),
+        });
+    }
+    let go_params: Vec<String> = params
+        .iter()
+        .map(|(name, go_type)| format!("{} {}", name, go_type.go))
+        .collect();
+    wrapper.push_str(&format!("func {}({})", name, go_params.join(", ")));
+    if let Some(output) = &output {
+        wrapper.push_str(&format!(" {}", output.go));
+    }
+    let args: Vec<String> = params
+        .iter()
+        .map(|(name, go_type)| go_type.to_cgo(name))
+        .collect();
+    let call = format!("C.{}({})", symbol, args.join(", "));
+    match &output {
+        Some(output) => wrapper.push_str(&format!(" {{\n\treturn {}\n}}\n", output.to_go(&call))),
+        None => wrapper.push_str(&format!(" {{\n\t{}\n}}\n", call)),
+    }
+    wrapper
+}
+
+fn is_go_keyword(name: &str) -> bool {
+    matches!(
+        name,
+        "break"
+            | "case"
+            | "chan"
+            | "const"
+            | "continue"
+            | "default"
+            | "defer"
+            | "else"
+            | "fallthrough"
+            | "for"
+            | "func"
+            | "go"
+            | "goto"
+            | "if"
+            | "import"
+            | "interface"
+            | "map"
+            | "package"
+            | "range"
+            | "return"
+            | "select"
+            | "struct"
+            | "switch"
+            | "type"
+            | "var"
+    )
+}
+
+/// Parameters named after Go keywords, after the packages in scope or after the types the wrappers
+/// convert to, which they would shadow, are suffixed with `_`.
+fn go_param_name(name: String) -> String {
+    match name.as_str() {
+        "C" | "unsafe" | "int8" | "int16" | "int32" | "int64" | "int" | "uint8" | "uint16"
+        | "uint32" | "uint64" | "uintptr" | "float32" | "float64" | "bool" => format!("{}_", name),
+        name if is_go_keyword(name) => format!("{}_", name),
+        _ => name,
+    }
+}
+
+/// E.g. `EngineNew` for `engine_new`, so that the wrapper is exported from the package. Names that
+/// wouldn't start with an uppercase letter, e.g. `2d_point`, are prefixed with `X`. Since exported
+/// names are capitalized, they can't be Go keywords.
+fn camel_case(symbol: &str) -> String {
+    let camel_case: String = symbol
+        .strip_prefix("r#")
+        .unwrap_or(symbol)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect();
+    match camel_case.chars().next() {
+        Some(first) if first.is_uppercase() => camel_case,
+        _ => format!("X{}", camel_case),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings(source: &str, options: &GoBindingsOptions) -> String {
+        let parsed_files = crate::parse_sources_for_no_mangle_pub_extern_c_fns(
+            vec![("src/lib.rs", source)],
+            &crate::ScanOptions::default(),
+        );
+        generate_go_bindings(&parsed_files, options)
+    }
+
+    /// The Go source after the `import "C"` line.
+    fn declarations(bindings: &str) -> &str {
+        let start = bindings.find("import \"C\"\n").unwrap() + "import \"C\"\n".len();
+        &bindings[start..]
+    }
+
+    #[test]
+    fn reports_clashing_wrapper_names() {
+        let source = r#"
+            pub struct Engine;
+            #[no_mangle]
+            pub extern "C" fn foo_bar() {}
+            #[no_mangle]
+            #[allow(non_snake_case)]
+            pub extern "C" fn fooBar() {}
+            #[no_mangle]
+            pub extern "C" fn engine(engine: *mut Engine) {}
+            "#;
+        assert_eq!(
+            declarations(&bindings(source, &GoBindingsOptions::default())),
+            "
+// Engine is an opaque handle.
+type Engine C.Engine
+
+func FooBar() {
+\tC.foo_bar()
+}
+
+// `fooBar` is not wrapped: `FooBar` is taken by the wrapper of `foo_bar`, rename it with GoBindingsOptions::wrapper_names
+
+// `engine` is not wrapped: `Engine` is taken by the opaque type `Engine`, rename it with GoBindingsOptions::wrapper_names
+"
+        );
+
+        let options = GoBindingsOptions {
+            wrapper_names: [("fooBar", "FooBarCompat"), ("engine", "EngineGet")]
+                .iter()
+                .map(|(symbol, name)| (symbol.to_string(), name.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            declarations(&bindings(source, &options)),
+            "
+// Engine is an opaque handle.
+type Engine C.Engine
+
+func FooBar() {
+\tC.foo_bar()
+}
+
+func FooBarCompat() {
+\tC.fooBar()
+}
+
+func EngineGet(engine *Engine) {
+\tC.engine((*C.Engine)(engine))
+}
+"
+        );
+    }
+
+    #[test]
+    fn wraps_each_symbol_once() {
+        let bindings = bindings(
+            r#"
+            #[cfg(unix)]
+            #[no_mangle]
+            pub extern "C" fn same(x: i32) {}
+            #[cfg(windows)]
+            #[no_mangle]
+            pub extern "C" fn same(x: i32) {}
+            #[cfg(unix)]
+            #[no_mangle]
+            pub extern "C" fn dup(x: i32) {}
+            #[cfg(windows)]
+            #[no_mangle]
+            pub extern "C" fn dup(x: i64) {}
+            #[no_mangle]
+            #[cfg_attr(windows, export_name = "f_win")]
+            pub extern "C" fn f() {}
+            "#,
+            &GoBindingsOptions::default(),
+        );
+        assert_eq!(
+            declarations(&bindings),
+            "
+func Same(x int32) {
+\tC.same(C.int32_t(x))
+}
+
+// `dup` is not wrapped: its declaration depends on `#[cfg]`s, scan with a cfg set
+
+// `f_win` is not wrapped: its declaration depends on `#[cfg]`s, scan with a cfg set
+
+// `f` is not wrapped: its declaration depends on `#[cfg]`s, scan with a cfg set
+"
+        );
+    }
+
+    #[test]
+    fn skips_functions_without_c_declaration() {
+        let bindings = bindings(
+            r#"
+            #[no_mangle]
+            pub extern "C" fn s(x: &str, y: i128) -> u128 {}
+            #[no_mangle]
+            pub extern "C" fn t(x: &str) {}
+            #[no_mangle]
+            pub extern "C" fn u(y: u64) -> u64 {}
+            "#,
+            &GoBindingsOptions::default(),
+        );
+        assert_eq!(
+            declarations(&bindings),
+            "
+// `s` is not wrapped: `u128` has no C equivalent
+
+// `t` is not wrapped: `& str` has no C equivalent
+
+func U(y uint64) uint64 {
+\treturn uint64(C.u(C.uint64_t(y)))
+}
+"
+        );
+    }
+
+    #[test]
+    fn escapes_keywords() {
+        let bindings = bindings(
+            r#"
+            #[no_mangle]
+            pub extern "C" fn r#type() {}
+            #[no_mangle]
+            pub extern "C" fn r#match(r#type: i32, func: u8, int32: i32, C: bool, class: u8) -> i32 {}
+            #[no_mangle]
+            #[export_name = "range"]
+            pub extern "C" fn range_() {}
+            "#,
+            &GoBindingsOptions::default(),
+        );
+        assert_eq!(
+            declarations(&bindings),
+            "
+// `type` is not wrapped: cgo can't refer to a Go keyword
+
+func Match(type_ int32, func_ uint8, int32_ int32, C_ bool, class_ uint8) int32 {
+\treturn int32(C.match(C.int32_t(type_), C.uint8_t(func_), C.int32_t(int32_), C.bool(C_), C.uint8_t(class_)))
+}
+
+// `range` is not wrapped: cgo can't refer to a Go keyword
+"
+        );
+    }
+
+    #[test]
+    fn camel_cases_symbols() {
+        assert_eq!(camel_case("engine_new"), "EngineNew");
+        assert_eq!(camel_case("engineNew"), "EngineNew");
+        assert_eq!(camel_case("__engine__new_"), "EngineNew");
+        assert_eq!(camel_case("r#type"), "Type");
+        assert_eq!(camel_case("mylib.engine$new"), "MylibEngineNew");
+        assert_eq!(camel_case("_2d_point"), "X2dPoint");
+        assert_eq!(camel_case("_"), "X");
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::scan::{parse_source_files, SourceFiles};
+use crate::{NoManglePubExportCFns, ParsedFile, ScanOptions};
+
+/// Parses a single Rust source file held in memory, e.g. the unsaved contents of an editor buffer,
+/// for `#[no_mangle] pub export "C"` functions. `path` is only used to fill [ParsedFile::path].
+///
+/// The file is scanned on its own: only the `macro_rules!` macros defined in it are expanded, only
+/// the types and `use` declarations in it are used to resolve types, and only its own `#![cfg]`
+/// predicates are taken into account. If they are disabled by
+/// `options.cfg_set`, no functions are reported.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{parse_source_for_no_mangle_pub_extern_c_fns, ScanOptions};
+///
+/// let source = r#"
+///     #[no_mangle]
+///     pub extern "C" fn s() {}
+/// "#;
+/// let parsed_file = parse_source_for_no_mangle_pub_extern_c_fns(source, "unused.rs", &ScanOptions::default()).unwrap();
+/// assert_eq!(parsed_file.no_mangle_pub_export_c_fns.0[0].symbol, "s");
+/// ```
+///
+/// # Errors
+///
+/// Fails if the source can't be parsed.
+pub fn parse_source_for_no_mangle_pub_extern_c_fns(
+    source: &str,
+    path: &str,
+    options: &ScanOptions,
+) -> std::io::Result<ParsedFile> {
+    let path = PathBuf::from(path);
+    let sources = HashMap::from([(path.clone(), source.to_string())]);
+    // The pre-filter is off so that syntax errors are always reported, and the cache is off so
+    // that the cache of the whole crate isn't replaced by the entry of this file
+    let options = ScanOptions {
+        prefilter: false,
+        cache_dir: None,
+        ..options.clone()
+    };
+    match parse_source_files([path.clone()], &SourceFiles::InMemory(&sources), &options).pop() {
+        Some(parsed_file) => Ok(parsed_file),
+        // The file is either unparsable or disabled by its `#![cfg]` predicates
+        None => syn::parse_file(source)
+            .map(|_| ParsedFile {
+                path,
+                no_mangle_pub_export_c_fns: NoManglePubExportCFns::default(),
+                type_defs: Vec::new(),
+            })
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())),
+    }
+}
+
+/// Does the same as [parse_source_for_no_mangle_pub_extern_c_fns] but reads the source from `reader`,
+/// e.g. [std::io::stdin].
+///
+/// # Errors
+///
+/// Fails if the source can't be read or parsed.
+pub fn parse_reader_for_no_mangle_pub_extern_c_fns<R: Read>(
+    mut reader: R,
+    path: &str,
+    options: &ScanOptions,
+) -> std::io::Result<ParsedFile> {
+    let mut source = String::new();
+    reader.read_to_string(&mut source)?;
+    parse_source_for_no_mangle_pub_extern_c_fns(&source, path, options)
+}
+
+/// Does the same as [parse_for_no_mangle_pub_extern_c_fns_with_options](crate::parse_for_no_mangle_pub_extern_c_fns_with_options)
+/// for a virtual filesystem, i.e. pairs of paths and contents, so that a whole crate can be scanned
+/// without touching the disk.
+///
+/// The paths are used as is, both in the output and to relate files through out-of-line `mod`
+/// declarations, so they should follow the layout of a crate, e.g. `src/lib.rs` and `src/a.rs`.
+/// Files that can't be parsed are skipped.
+///
+/// # Example
+///
+/// ```
+/// use std::path::Path;
+/// use no_mangle_pub_export_c_fn::{parse_sources_for_no_mangle_pub_extern_c_fns, CfgSet, ScanOptions};
+///
+/// let sources = vec![
+///     ("src/lib.rs", "#[cfg(feature = \"gpu\")] mod gpu;"),
+///     ("src/gpu.rs", "#[no_mangle] pub extern \"C\" fn kernel() {}"),
+/// ];
+/// let options = ScanOptions {
+///     cfg_set: Some(CfgSet::default()),
+///     ..Default::default()
+/// };
+/// let parsed_files = parse_sources_for_no_mangle_pub_extern_c_fns(sources, &options);
+/// assert_eq!(parsed_files.len(), 1);
+/// assert_eq!(parsed_files[0].path, Path::new("src/lib.rs"));
+/// ```
+pub fn parse_sources_for_no_mangle_pub_extern_c_fns<I, P, S>(
+    sources: I,
+    options: &ScanOptions,
+) -> Vec<ParsedFile>
+where
+    I: IntoIterator<Item = (P, S)>,
+    P: Into<PathBuf>,
+    S: Into<String>,
+{
+    let sources: HashMap<PathBuf, String> = sources
+        .into_iter()
+        .map(|(path, source)| (path.into(), source.into()))
+        .collect();
+    parse_source_files(
+        sources.keys().cloned(),
+        &SourceFiles::InMemory(&sources),
+        options,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CfgSet;
+
+    fn rust_paths(parsed_file: &ParsedFile) -> Vec<&str> {
+        parsed_file
+            .no_mangle_pub_export_c_fns
+            .0
+            .iter()
+            .map(|no_mangle_pub_export_c_fn| no_mangle_pub_export_c_fn.rust_path.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn parses_a_single_source() {
+        let source = r#"
+            macro_rules! export { ($f:ident) => { #[no_mangle] pub extern "C" fn $f() {} }; }
+            type Status = i32;
+            #[no_mangle]
+            /* 😀 */ pub extern "C" fn größe() -> Status { 0 }
+            export!(exported);
+        "#;
+        let options = ScanOptions {
+            utf16_columns: true,
+            ..Default::default()
+        };
+        let parsed_file =
+            parse_source_for_no_mangle_pub_extern_c_fns(source, "src/ffi/image.rs", &options)
+                .unwrap();
+        assert_eq!(parsed_file.path, PathBuf::from("src/ffi/image.rs"));
+        assert_eq!(
+            rust_paths(&parsed_file),
+            vec!["crate::ffi::image::größe", "crate::ffi::image::exported"]
+        );
+        let no_mangle_pub_export_c_fn = &parsed_file.no_mangle_pub_export_c_fns.0[0];
+        assert_eq!(
+            no_mangle_pub_export_c_fn.resolved_output.as_deref(),
+            Some("i32")
+        );
+        let signature = &no_mangle_pub_export_c_fn
+            .part_locations
+            .as_ref()
+            .unwrap()
+            .signature;
+        assert_eq!(
+            (signature.end_column, signature.end_utf16_column),
+            (55, Some(56))
+        );
+        assert_eq!(
+            parsed_file.no_mangle_pub_export_c_fns.0[1]
+                .expanded_from
+                .as_deref(),
+            Some("export")
+        );
+    }
+
+    #[test]
+    fn reports_disabled_and_unparsable_sources() {
+        let source = "#![cfg(feature = \"ffi\")]\n#[no_mangle] pub extern \"C\" fn f() {}\n";
+        let options = ScanOptions {
+            cfg_set: Some(CfgSet::default()),
+            ..Default::default()
+        };
+        let parsed_file =
+            parse_source_for_no_mangle_pub_extern_c_fns(source, "src/lib.rs", &options).unwrap();
+        assert_eq!(parsed_file.path, PathBuf::from("src/lib.rs"));
+        assert!(parsed_file.no_mangle_pub_export_c_fns.0.is_empty());
+
+        // Even without anything the pre-filter looks for
+        let err = parse_source_for_no_mangle_pub_extern_c_fns(
+            "fn f( {}",
+            "src/lib.rs",
+            &ScanOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn parses_a_reader() {
+        let source = "#[no_mangle] pub extern \"C\" fn f() {}\n";
+        let parsed_file = parse_reader_for_no_mangle_pub_extern_c_fns(
+            source.as_bytes(),
+            "src/lib.rs",
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rust_paths(&parsed_file), vec!["crate::f"]);
+
+        let err = parse_reader_for_no_mangle_pub_extern_c_fns(
+            &[0xff_u8][..],
+            "src/lib.rs",
+            &ScanOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn parses_a_virtual_crate() {
+        let sources = vec![
+            (
+                "src/lib.rs",
+                concat!(
+                    "macro_rules! export { ($f:ident) => { #[no_mangle] pub extern \"C\" fn $f() {} }; }\n",
+                    "#[path = \"ffi_impl.rs\"]\nmod ffi;\n",
+                    "#[cfg(feature = \"gpu\")]\nmod gpu;\n",
+                ),
+            ),
+            ("src/ffi_impl.rs", "export!(decode);\n"),
+            ("src/gpu.rs", "#[no_mangle] pub extern \"C\" fn kernel() {}\n"),
+            ("src/broken.rs", "#[no_mangle] fn f( {}\n"),
+        ];
+        let options = ScanOptions {
+            cfg_set: Some(CfgSet::default()),
+            ..Default::default()
+        };
+        let parsed_files = parse_sources_for_no_mangle_pub_extern_c_fns(sources, &options);
+        let paths: Vec<&std::path::Path> = parsed_files
+            .iter()
+            .map(|parsed_file| parsed_file.path.as_path())
+            .collect();
+        assert_eq!(paths, vec!["src/ffi_impl.rs", "src/lib.rs"]);
+        assert_eq!(rust_paths(&parsed_files[0]), vec!["crate::ffi::decode"]);
+    }
+}
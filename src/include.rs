@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+
+fn is_macro(mac: &syn::Macro, name: &str) -> bool {
+    // Also `std::include!` and the like
+    mac.path
+        .segments
+        .last()
+        .map_or(false, |segment| segment.ident == name)
+}
+
+/// Evaluates a string literal, possibly built with `concat!` and `env!`, the way the compiler would.
+/// Environment variables are taken from `env` rather than from the environment of the process.
+fn eval_str(expr: &syn::Expr, env: &BTreeMap<String, String>) -> Option<String> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(lit_str),
+            ..
+        }) => Some(lit_str.value()),
+        syn::Expr::Macro(syn::ExprMacro { mac, .. }) if is_macro(mac, "concat") => macro_args(mac)?
+            .iter()
+            .map(|arg| eval_str(arg, env))
+            .collect(),
+        syn::Expr::Macro(syn::ExprMacro { mac, .. }) if is_macro(mac, "env") => {
+            // The optional second argument is the error message
+            let name = eval_str(macro_args(mac)?.first()?, env)?;
+            env.get(&name).cloned()
+        }
+        syn::Expr::Group(syn::ExprGroup { expr, .. })
+        | syn::Expr::Paren(syn::ExprParen { expr, .. }) => eval_str(expr, env),
+        _ => None,
+    }
+}
+
+fn macro_args(mac: &syn::Macro) -> Option<Punctuated<syn::Expr, syn::Token![,]>> {
+    Punctuated::<syn::Expr, syn::Token![,]>::parse_terminated
+        .parse2(mac.tokens.clone())
+        .ok()
+}
+
+/// Resolves the file pulled in by an `include!` invocation in the file at `path`, e.g.
+/// `include!(concat!(env!("OUT_DIR"), "/ffi.rs"))`. As with the compiler, relative paths are
+/// resolved against the directory of the including file. Returns [None] for other macros and for
+/// arguments that can't be evaluated.
+pub(crate) fn include_path(
+    mac: &syn::Macro,
+    path: &Path,
+    env: &BTreeMap<String, String>,
+) -> Option<PathBuf> {
+    if !is_macro(mac, "include") {
+        return None;
+    }
+    let args = macro_args(mac)?;
+    let included = PathBuf::from(eval_str(args.first()?, env)?);
+    Some(if included.is_absolute() {
+        included
+    } else {
+        path.parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(included)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env() -> BTreeMap<String, String> {
+        [("OUT_DIR", "/target/out"), ("CARGO_PKG_NAME", "ffi")]
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn mac(source: &str) -> syn::Macro {
+        syn::parse_str(source).unwrap()
+    }
+
+    #[test]
+    fn evaluates_strings() {
+        let eval = |source: &str| eval_str(&syn::parse_str(source).unwrap(), &env());
+        assert_eq!(eval(r#""ffi.rs""#), Some("ffi.rs".to_string()));
+        assert_eq!(eval(r#"env!("OUT_DIR")"#), Some("/target/out".to_string()));
+        assert_eq!(
+            eval(r#"std::env!("OUT_DIR", "OUT_DIR isn't set")"#),
+            Some("/target/out".to_string())
+        );
+        assert_eq!(
+            eval(r#"concat!(env!("OUT_DIR"), "/", (concat!(env!("CARGO_PKG_NAME"), ".rs")))"#),
+            Some("/target/out/ffi.rs".to_string())
+        );
+        // Not in `env`, even if set for the process
+        assert_eq!(eval(r#"env!("PATH")"#), None);
+        assert_eq!(eval(r#"concat!(env!("MISSING"), "/ffi.rs")"#), None);
+        assert_eq!(eval(r#"concat!("ffi", 1)"#), None);
+        assert_eq!(eval(r#"format!("{}.rs", "ffi")"#), None);
+    }
+
+    #[test]
+    fn resolves_include_paths() {
+        let path = Path::new("src/ffi/mod.rs");
+        assert_eq!(
+            include_path(
+                &mac(r#"include!(concat!(env!("OUT_DIR"), "/ffi.rs"))"#),
+                path,
+                &env()
+            ),
+            Some(PathBuf::from("/target/out/ffi.rs"))
+        );
+        assert_eq!(
+            include_path(&mac(r#"core::include!("generated/ffi.rs")"#), path, &env()),
+            Some(PathBuf::from("src/ffi/generated/ffi.rs"))
+        );
+        assert_eq!(
+            include_path(
+                &mac(r#"include!(concat!(env!("MISSING"), "/ffi.rs"))"#),
+                path,
+                &env()
+            ),
+            None
+        );
+        assert_eq!(
+            include_path(&mac(r#"include_str!("ffi.rs")"#), path, &env()),
+            None
+        );
+        assert_eq!(include_path(&mac("include!()"), path, &env()), None);
+    }
+}
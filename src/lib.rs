@@ -30,36 +30,96 @@
 //!
 //! ```text
 //! [
-//! ParsedFile {
-//!     path: "...\\no_mangle_pub_export_c_fn\\src\\lib.rs",
-//!     no_mangle_pub_export_c_fns: NoManglePubExportCFns {
-//!         no_mangle_pub_export_c_fn_vec: [],
+//!     ParsedFile {
+//!         path: "...\\no_mangle_pub_export_c_fn\\src\\lib.rs",
+//!         no_mangle_pub_export_c_fns: NoManglePubExportCFns(
+//!             [],
+//!         ),
+//!         type_defs: [],
 //!     },
-//! },
-//! ParsedFile {
-//!     path: "...\\no_mangle_pub_export_c_fn\\src\\main.rs",
-//!     no_mangle_pub_export_c_fns: NoManglePubExportCFns {
-//!         no_mangle_pub_export_c_fn_vec: [],
+//!     ParsedFile {
+//!         path: "...\\no_mangle_pub_export_c_fn\\src\\main.rs",
+//!         no_mangle_pub_export_c_fns: NoManglePubExportCFns(
+//!             [],
+//!         ),
+//!         type_defs: [],
 //!     },
-//! },
-//! ParsedFile {
-//!     path: "...\\no_mangle_pub_export_c_fn\\src\\unused.rs",
-//!     no_mangle_pub_export_c_fns: NoManglePubExportCFns {
-//!         no_mangle_pub_export_c_fn_vec: [
-//!             NoManglePubExportCFnEnds {
-//!                 start_line: 1,
-//!                 start_column: 0,
-//!                 end_line: 4,
-//!                 end_column: 1,
-//!             },
-//!         ],
+//!     ParsedFile {
+//!         path: "...\\no_mangle_pub_export_c_fn\\src\\unused.rs",
+//!         no_mangle_pub_export_c_fns: NoManglePubExportCFns(
+//!             [
+//!                 NoManglePubExportCFn {
+//!                     ident: "s",
+//!                     symbol: "s",
+//!                     module_path: [
+//!                         "unused",
+//!                     ],
+//!                     rust_path: "crate::unused::s",
+//!                     inputs: [],
+//!                     output: None,
+//!                     resolved_output: None,
+//!                     doc: None,
+//!                     cfg: None,
+//!                     export_cfg: None,
+//!                     symbol_cfg: None,
+//!                     fallback_symbol: None,
+//!                     expanded_from: None,
+//!                     location: LineColumnEnds {
+//!                         start_line: 1,
+//!                         start_column: 0,
+//!                         end_line: 4,
+//!                         end_column: 1,
+//!                         start_byte: 0,
+//!                         end_byte: 50,
+//!                         start_utf16_column: None,
+//!                         end_utf16_column: None,
+//!                     },
+//!                     part_locations: Some(
+//!                         FnPartLocations {
+//!                             attrs: [
+//!                                 LineColumnEnds {
+//!                                     start_line: 1,
+//!                                     start_column: 0,
+//!                                     end_line: 1,
+//!                                     end_column: 12,
+//!                                     start_byte: 0,
+//!                                     end_byte: 12,
+//!                                     start_utf16_column: None,
+//!                                     end_utf16_column: None,
+//!                                 },
+//!                             ],
+//!                             signature: LineColumnEnds {
+//!                                 start_line: 2,
+//!                                 start_column: 15,
+//!                                 end_line: 2,
+//!                                 end_column: 21,
+//!                                 start_byte: 28,
+//!                                 end_byte: 34,
+//!                                 start_utf16_column: None,
+//!                                 end_utf16_column: None,
+//!                             },
+//!                             body: LineColumnEnds {
+//!                                 start_line: 2,
+//!                                 start_column: 22,
+//!                                 end_line: 4,
+//!                                 end_column: 1,
+//!                                 start_byte: 35,
+//!                                 end_byte: 50,
+//!                                 start_utf16_column: None,
+//!                                 end_utf16_column: None,
+//!                             },
+//!                         },
+//!                     ),
+//!                 },
+//!             ],
+//!         ),
+//!         type_defs: [],
 //!     },
-//! },
 //! ]
 //! ```
 //!
 //! # Note
-//! The paths will be absolute. The prefixes have been deleted intentionally.
+//! The paths will be absolute unless [ScanOptions::relative_paths] is set. The prefixes have been deleted intentionally.
 //!
 //! # Integration with serde
 //!
@@ -121,38 +181,391 @@
 //! * [What are no mangle pub export C functions?](https://docs.rust-embedded.org/book/interoperability/rust-with-c.html#no_mangle)
 //! * Will **this** crate support other [calling conventions](https://doc.rust-lang.org/nomicon/ffi.html#foreign-calling-conventions)? No but some other crate might.
 
-use std::io::Read;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 
 use proc_macro2::Span;
+use quote::ToTokens;
 use serde::{Deserialize, Serialize};
-use syn::{spanned::Spanned, visit::Visit, Visibility};
-use walkdir::WalkDir;
+use syn::{ext::IdentExt, spanned::Spanned, visit::Visit, Visibility};
+
+use macro_expansion::MacroRulesMap;
+use resolve::TypeResolver;
+use scan::parse_rust_source_files;
+use traversal::rust_source_files;
+
+#[cfg(feature = "archive")]
+mod archive;
+mod build_script;
+mod c_header;
+mod cache;
+mod cfg;
+mod cpp_header;
+mod diff;
+mod expanded;
+mod git;
+mod go_bindings;
+mod in_memory;
+mod include;
+mod linker;
+mod macro_expansion;
+mod module_tree;
+mod prefilter;
+mod resolve;
+mod scan;
+mod stream;
+mod traversal;
+mod types;
+#[cfg(feature = "watch")]
+mod watch;
+
+#[cfg(feature = "archive")]
+pub use archive::parse_crate_archive_for_no_mangle_pub_extern_c_fns;
+pub use build_script::{write_build_script_outputs, BuildScriptOutputs};
+pub use c_header::{generate_c_header, CHeaderOptions};
+pub use cfg::{CfgPredicate, CfgSet};
+pub use cpp_header::{generate_cpp_header, CppHeaderOptions};
+pub use diff::{diff_exports, Export, ExportsDiff};
+pub use expanded::parse_expanded_source_for_no_mangle_pub_extern_c_fns;
+pub use git::parse_git_revision_for_no_mangle_pub_extern_c_fns;
+pub use go_bindings::{generate_go_bindings, GoBindingsOptions};
+pub use in_memory::{
+    parse_reader_for_no_mangle_pub_extern_c_fns, parse_source_for_no_mangle_pub_extern_c_fns,
+    parse_sources_for_no_mangle_pub_extern_c_fns,
+};
+pub use linker::{generate_module_definition, generate_version_script};
+pub use stream::{
+    parse_for_no_mangle_pub_extern_c_fns_iter, parse_for_no_mangle_pub_extern_c_fns_with_sink,
+    ParsedFilesIter,
+};
+pub use types::{EnumVariant, TypeDef, TypeDefKind};
+#[cfg(feature = "watch")]
+pub use watch::watch_build_script_outputs;
 
 /// The location information of an individual `#[no_mangle] pub export "C"` function.
 ///
 /// Read more about
 /// `#[no_mangle] pub export "C"` functions in Rust [here](https://docs.rust-embedded.org/book/interoperability/rust-with-c.html#no_mangle).
-#[derive(Serialize, Deserialize, Debug)]
+///
+/// Lines are 1-based, while columns count chars from 0. Byte offsets are counted from the start of
+/// the file, which makes it possible to slice the source text with [LineColumnEnds::source_text].
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LineColumnEnds {
     pub start_line: usize,
     pub start_column: usize,
     pub end_line: usize,
     pub end_column: usize,
+    #[serde(default)]
+    pub start_byte: usize,
+    #[serde(default)]
+    pub end_byte: usize,
+    /// The columns in UTF-16 code units, as used by the Language Server Protocol. Only set when
+    /// [ScanOptions::utf16_columns] is enabled.
+    #[serde(default)]
+    pub start_utf16_column: Option<usize>,
+    #[serde(default)]
+    pub end_utf16_column: Option<usize>,
 }
 
 impl LineColumnEnds {
     fn new(span: &Span) -> Self {
+        Self::between(span, span)
+    }
+
+    /// From the start of `start` to the end of `end`.
+    fn between(start: &Span, end: &Span) -> Self {
+        Self {
+            start_line: start.start().line,
+            start_column: start.start().column,
+            end_line: end.end().line,
+            end_column: end.end().column,
+            start_byte: start.byte_range().start,
+            end_byte: end.byte_range().end,
+            start_utf16_column: None,
+            end_utf16_column: None,
+        }
+    }
+
+    /// Sets the UTF-16 columns from the byte offsets.
+    pub(crate) fn fill_utf16_columns(&mut self, source: &str) {
+        let utf16_column = |byte: usize| {
+            let line = source.get(..byte)?;
+            let line_start = line.rfind('\n').map_or(0, |newline| newline + 1);
+            Some(line[line_start..].encode_utf16().count())
+        };
+        self.start_utf16_column = utf16_column(self.start_byte);
+        self.end_utf16_column = utf16_column(self.end_byte);
+    }
+
+    /// Slices the text at this location out of the source of the file, e.g. the whole function
+    /// including its attributes. For functions generated by `macro_rules!` macros, it is the text of
+    /// the macro invocation. Returns [None] if the source doesn't match the location.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use no_mangle_pub_export_c_fn::{parse_source_for_no_mangle_pub_extern_c_fns, ScanOptions};
+    ///
+    /// let source = "// Größe\n#[no_mangle]\npub extern \"C\" fn s() {}\n";
+    /// let parsed_file = parse_source_for_no_mangle_pub_extern_c_fns(source, "unused.rs", &ScanOptions::default()).unwrap();
+    /// let location = &parsed_file.no_mangle_pub_export_c_fns.0[0].location;
+    /// assert_eq!(location.source_text(source), Some("#[no_mangle]\npub extern \"C\" fn s() {}"));
+    /// ```
+    pub fn source_text<'a>(&self, source: &'a str) -> Option<&'a str> {
+        source.get(self.start_byte..self.end_byte)
+    }
+}
+
+/// The locations of the parts of a `#[no_mangle] pub export "C"` function, e.g. to underline just
+/// the signature or to jump to the `#[no_mangle]` attribute.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FnPartLocations {
+    /// One location per attribute in source order, doc comments included.
+    pub attrs: Vec<LineColumnEnds>,
+    /// From `fn` through the return type, or through the `where` clause if there is one.
+    pub signature: LineColumnEnds,
+    /// The block, braces included.
+    pub body: LineColumnEnds,
+}
+
+impl FnPartLocations {
+    pub(crate) fn new(node: &syn::ItemFn) -> Self {
         Self {
-            start_line: span.start().line,
-            start_column: span.start().column,
-            end_line: span.end().line,
-            end_column: span.end().column,
+            attrs: node
+                .attrs
+                .iter()
+                .map(|attr| LineColumnEnds::new(&attr.span()))
+                .collect(),
+            signature: LineColumnEnds::between(&node.sig.fn_token.span, &node.sig.span()),
+            body: LineColumnEnds::new(&node.block.brace_token.span),
         }
     }
+
+    fn fill_utf16_columns(&mut self, source: &str) {
+        self.attrs
+            .iter_mut()
+            .chain([&mut self.signature, &mut self.body])
+            .for_each(|location| location.fill_utf16_columns(source));
+    }
+}
+
+/// An argument of a `#[no_mangle] pub export "C"` function. Both the pattern and the type are stored
+/// as token strings so that they survive serialization and can be parsed back with [syn::parse_str].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FnArg {
+    pub pat: String,
+    pub ty: String,
+    /// `ty` with type aliases replaced by the aliased types and the other types defined in the crate
+    /// by their canonical paths, e.g. `crate :: ffi :: Status` for `Status` imported with `use`.
+    /// Paths that can't be resolved, e.g. to other crates, are kept. [None] if types weren't resolved.
+    #[serde(default)]
+    pub resolved_ty: Option<String>,
+}
+
+/// The information about an individual `#[no_mangle] pub export "C"` function: its name, the symbol
+/// under which it is exported, its signature and its location.
+///
+/// Read more about
+/// `#[no_mangle] pub export "C"` functions in Rust [here](https://docs.rust-embedded.org/book/interoperability/rust-with-c.html#no_mangle).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoManglePubExportCFn {
+    /// The identifier of the function in Rust source.
+    pub ident: String,
+    /// The name of the exported symbol. It differs from `ident` only when `#[export_name = "..."]` is
+    /// present, possibly in a `#[cfg_attr(...)]`, see [NoManglePubExportCFn::symbol_cfg].
+    pub symbol: String,
+    /// The modules enclosing the function, e.g. `["ffi", "image"]`, following the `mod` declarations
+    /// leading to its file and the inline modules in the file. Files that aren't declared as modules,
+    /// e.g. crate roots, get a module path guessed from their location relative to `src`.
+    #[serde(default)]
+    pub module_path: Vec<String>,
+    /// The fully qualified path of the function, e.g. `crate::ffi::image::decode`.
+    #[serde(default)]
+    pub rust_path: String,
+    pub inputs: Vec<FnArg>,
+    /// The return type as a token string or [None] for `()`.
+    pub output: Option<String>,
+    /// `output` resolved the same way as [FnArg::resolved_ty].
+    #[serde(default)]
+    pub resolved_output: Option<String>,
+    /// The doc comment, i.e. the `///` comments and `#[doc = "..."]` attributes, with the common
+    /// indentation removed, or [None] if the function isn't documented.
+    pub doc: Option<String>,
+    /// The conjunction of all `#[cfg]` predicates on the function, on the enclosing modules and on the
+    /// `mod` declarations leading to its file, or [None] if the function is unconditional.
+    pub cfg: Option<CfgPredicate>,
+    /// The condition under which `#[no_mangle]` applies when it comes from `#[cfg_attr(...)]`,
+    /// e.g. `feature = "ffi"` for `#[cfg_attr(feature = "ffi", no_mangle)]`, or [None] if it is unconditional.
+    pub export_cfg: Option<CfgPredicate>,
+    /// The condition under which `symbol` applies when it comes from `#[cfg_attr(...)]`, e.g. `windows`
+    /// for `#[cfg_attr(windows, export_name = "f_win")]`, or [None] if it is unconditional or has been
+    /// decided by [ScanOptions::cfg_set]. When the condition is false, the function is exported as
+    /// [NoManglePubExportCFn::fallback_symbol] instead.
+    #[serde(default)]
+    pub symbol_cfg: Option<CfgPredicate>,
+    /// The symbol when [NoManglePubExportCFn::symbol_cfg] is false, i.e. the unraw `ident`. [None] if
+    /// `symbol_cfg` is.
+    #[serde(default)]
+    pub fallback_symbol: Option<String>,
+    /// The name of the `macro_rules!` macro whose invocation produced the function, if any.
+    /// In that case, `location` points at the outermost invocation.
+    pub expanded_from: Option<String>,
+    pub location: LineColumnEnds,
+    /// The locations of the attributes, the signature and the body, or [None] for functions
+    /// generated by `macro_rules!` macros, whose parts aren't written out at `location`.
+    pub part_locations: Option<FnPartLocations>,
+}
+
+impl NoManglePubExportCFn {
+    /// Returns [None] if none of the `#[no_mangle]` attributes of the function is enabled by `cfg_set`.
+    fn new(
+        node: &syn::ItemFn,
+        cfg_set: Option<&CfgSet>,
+        cfg: Option<CfgPredicate>,
+        module_path: &[String],
+    ) -> Option<Self> {
+        let attrs = cfg::enabled_attrs(&node.attrs, cfg_set);
+        let no_mangle_cfgs: Vec<Option<CfgPredicate>> = attrs
+            .iter()
+            .filter(|conditional_meta| conditional_meta.meta.path().is_ident("no_mangle"))
+            .map(|conditional_meta| conditional_meta.cfg.clone())
+            .collect();
+        let export_cfg = match no_mangle_cfgs.iter().any(Option::is_none) {
+            true => None,
+            false => {
+                let mut no_mangle_cfgs: Vec<CfgPredicate> =
+                    no_mangle_cfgs.into_iter().flatten().collect();
+                match no_mangle_cfgs.len() {
+                    0 => return None,
+                    1 => no_mangle_cfgs.pop(),
+                    _ => Some(CfgPredicate::Any(no_mangle_cfgs)),
+                }
+            }
+        };
+        let ident = node.sig.ident.to_string();
+        // The symbol of `r#type` is `type`
+        let unraw_ident = node.sig.ident.unraw().to_string();
+        let (symbol, symbol_cfg, fallback_symbol) = match export_name(&attrs) {
+            // Without a cfg set, the condition of a `#[cfg_attr(..., export_name = "...")]` is open
+            Some((export_name, Some(cfg))) if cfg_set.is_none() => {
+                (export_name, Some(cfg), Some(unraw_ident))
+            }
+            Some((export_name, _)) => (export_name, None, None),
+            None => (unraw_ident, None, None),
+        };
+        Some(Self {
+            symbol,
+            module_path: module_path.to_vec(),
+            rust_path: rust_path(module_path, &ident),
+            ident,
+            inputs: node
+                .sig
+                .inputs
+                .iter()
+                .filter_map(|fn_arg| match fn_arg {
+                    syn::FnArg::Typed(pat_type) => Some(FnArg {
+                        pat: pat_type.pat.to_token_stream().to_string(),
+                        ty: pat_type.ty.to_token_stream().to_string(),
+                        resolved_ty: None,
+                    }),
+                    // Free functions can't have receivers
+                    syn::FnArg::Receiver(_) => None,
+                })
+                .collect(),
+            output: match &node.sig.output {
+                syn::ReturnType::Default => None,
+                syn::ReturnType::Type(_, ty) => Some(ty.to_token_stream().to_string()),
+            },
+            resolved_output: None,
+            doc: doc_comment(&attrs),
+            cfg,
+            export_cfg,
+            symbol_cfg,
+            fallback_symbol,
+            expanded_from: None,
+            location: LineColumnEnds::new(&node.span()),
+            part_locations: Some(FnPartLocations::new(node)),
+        })
+    }
 }
 
-/// The [newtype](https://doc.rust-lang.org/rust-by-example/generics/new_types.html) of [Vec]<[LineColumnEnds]>.
-/// When accessed from the result of [parse_for_no_mangle_pub_extern_c_fns]\(_\), contains location information
+/// The value of the `#[export_name = "..."]` attribute and its condition, preferring unconditional
+/// attributes over the ones that come from `#[cfg_attr(...)]`.
+fn export_name(attrs: &[cfg::ConditionalMeta]) -> Option<(String, Option<CfgPredicate>)> {
+    let mut export_names: Vec<(String, Option<CfgPredicate>)> = attrs
+        .iter()
+        .filter_map(|conditional_meta| match &conditional_meta.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                path,
+                lit: syn::Lit::Str(lit_str),
+                ..
+            }) if path.is_ident("export_name") => {
+                Some((lit_str.value(), conditional_meta.cfg.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    let index = export_names
+        .iter()
+        .position(|(_, cfg)| cfg.is_none())
+        .unwrap_or(0);
+    (!export_names.is_empty()).then(|| export_names.swap_remove(index))
+}
+
+/// E.g. `crate::ffi::decode` for `["ffi"]` and `decode`.
+pub(crate) fn rust_path(module_path: &[String], ident: &str) -> String {
+    std::iter::once("crate")
+        .chain(module_path.iter().map(String::as_str))
+        .chain(std::iter::once(ident))
+        .collect::<Vec<&str>>()
+        .join("::")
+}
+
+/// Joins the `#[doc = "..."]` attributes, which is what `///` and `/** */` comments turn into. As with
+/// rustdoc, the common indentation and the leading `*` of block comments are removed.
+pub(crate) fn doc_comment(attrs: &[cfg::ConditionalMeta]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter_map(|conditional_meta| match &conditional_meta.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                path,
+                lit: syn::Lit::Str(lit_str),
+                ..
+            }) if path.is_ident("doc") => Some(lit_str.value()),
+            _ => None,
+        })
+        .flat_map(|doc| {
+            // Only block comments span several lines, `///` comments may well start with `*`
+            let is_starred = doc.contains('\n')
+                && doc
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .all(|line| line.trim_start().starts_with('*'));
+            doc.split('\n')
+                .map(|line| match is_starred {
+                    true => line.trim_start().trim_start_matches('*').trim_end(),
+                    false => line.trim_end(),
+                })
+                .map(str::to_string)
+                .collect::<Vec<String>>()
+        })
+        .collect();
+    let indent = lines
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()?;
+    let doc = lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or_default())
+        .collect::<Vec<&str>>()
+        .join("\n");
+    Some(doc.trim_matches('\n').to_string())
+}
+
+/// The [newtype](https://doc.rust-lang.org/rust-by-example/generics/new_types.html) of [Vec]<[NoManglePubExportCFn]>.
+/// When accessed from the result of [parse_for_no_mangle_pub_extern_c_fns]\(_\), contains information
 /// about `#[no_mangle] pub export "C"` functions in a particular file.
 ///
 /// Read more about
@@ -161,11 +574,45 @@ impl LineColumnEnds {
 /// # Implementation at the time of writing
 ///
 /// Currently, the implementation relies on [syn::visit] module and its [syn::visit::Visit] trait.
-#[derive(Serialize, Deserialize, Default, Debug)]
-pub struct NoManglePubExportCFns(pub Vec<LineColumnEnds>);
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct NoManglePubExportCFns(pub Vec<NoManglePubExportCFn>);
+
+impl NoManglePubExportCFns {
+    /// Sets the UTF-16 columns of the locations from the source of the file.
+    pub(crate) fn fill_utf16_columns(&mut self, source: &str) {
+        self.0.iter_mut().for_each(|no_mangle_pub_export_c_fn| {
+            no_mangle_pub_export_c_fn
+                .location
+                .fill_utf16_columns(source);
+            if let Some(part_locations) = &mut no_mangle_pub_export_c_fn.part_locations {
+                part_locations.fill_utf16_columns(source);
+            }
+        });
+    }
+
+    /// Resolves the types in the signatures of the functions.
+    pub(crate) fn resolve_types(&mut self, type_resolver: &TypeResolver) {
+        self.0.iter_mut().for_each(|no_mangle_pub_export_c_fn| {
+            type_resolver.resolve_signature(no_mangle_pub_export_c_fn)
+        });
+    }
+}
 
 /// Checks if the given [syn::ItemFn] is a `#[no_mangle] pub export "C"` function.
 ///
+/// `#[no_mangle]` produced by `#[cfg_attr(...)]` counts regardless of the condition.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::is_no_mangle_pub_export_c_fn;
+///
+/// let item_fn: syn::ItemFn = syn::parse_str(
+///     r#"#[cfg_attr(feature = "ffi", no_mangle)] pub extern "C" fn f() {}"#,
+/// ).unwrap();
+/// assert!(is_no_mangle_pub_export_c_fn(&item_fn));
+/// ```
+///
 /// Read more about `#[no_mangle] pub export "C"` functions in Rust
 ///     [here](https://docs.rust-embedded.org/book/interoperability/rust-with-c.html#no_mangle).
 pub fn is_no_mangle_pub_export_c_fn(node: &syn::ItemFn) -> bool {
@@ -173,7 +620,14 @@ pub fn is_no_mangle_pub_export_c_fn(node: &syn::ItemFn) -> bool {
     let is_no_mangle = node
         .attrs
         .iter()
-        .any(|attr| attr.path.is_ident("no_mangle"));
+        .any(|attr| attr.path.is_ident("no_mangle"))
+        || cfg::expand_cfg_attrs(
+            node.attrs
+                .iter()
+                .filter(|attr| attr.path.is_ident("cfg_attr")),
+        )
+        .iter()
+        .any(|conditional_meta| conditional_meta.meta.path().is_ident("no_mangle"));
     let is_extern_c = matches!(node
             .sig
             .abi
@@ -186,23 +640,272 @@ pub fn is_no_mangle_pub_export_c_fn(node: &syn::ItemFn) -> bool {
     is_public && is_no_mangle && is_extern_c
 }
 
+/// The visitor behind [NoManglePubExportCFns] that keeps track of the enclosing `#[cfg]` predicates.
+struct NoManglePubExportCFnsVisitor<'a> {
+    cfg_set: Option<&'a CfgSet>,
+    cfgs: Vec<CfgPredicate>,
+    /// The module path of the file followed by the enclosing inline modules.
+    module_path: Vec<String>,
+    macro_rules_map: Option<&'a MacroRulesMap>,
+    /// The name and the span of the outermost macro invocation being expanded
+    expansion: Option<(String, Span)>,
+    expansion_depth: usize,
+    no_mangle_pub_export_c_fns: NoManglePubExportCFns,
+    type_defs: Vec<TypeDef>,
+}
+
+impl<'a> NoManglePubExportCFnsVisitor<'a> {
+    /// The same as the default [recursion limit](https://doc.rust-lang.org/reference/attributes/limits.html#the-recursion_limit-attribute).
+    const MAX_EXPANSION_DEPTH: usize = 128;
+
+    fn new(cfg_set: Option<&'a CfgSet>, cfgs: Vec<CfgPredicate>) -> Self {
+        Self {
+            cfg_set,
+            cfgs,
+            module_path: Vec::new(),
+            macro_rules_map: None,
+            expansion: None,
+            expansion_depth: 0,
+            no_mangle_pub_export_c_fns: NoManglePubExportCFns::default(),
+            type_defs: Vec::new(),
+        }
+    }
+
+    fn add_type_def(
+        &mut self,
+        ident: &syn::Ident,
+        kind: TypeDefKind,
+        attrs: &[syn::Attribute],
+        variants: Vec<EnumVariant>,
+    ) {
+        self.type_defs.push(TypeDef::new(
+            ident,
+            kind,
+            attrs,
+            &self.module_path,
+            variants,
+            self.cfg_set,
+        ));
+    }
+}
+
 // https://docs.rs/syn/latest/syn/visit/index.html
-impl<'ast> Visit<'ast> for NoManglePubExportCFns {
+impl<'ast, 'a> Visit<'ast> for NoManglePubExportCFnsVisitor<'a> {
+    fn visit_item(&mut self, node: &'ast syn::Item) {
+        let item_cfgs = cfg::cfg_predicates(cfg::item_attrs(node));
+        if !cfg::is_cfg_enabled(self.cfg_set, &item_cfgs) {
+            return;
+        }
+        let len = self.cfgs.len();
+        self.cfgs.extend(item_cfgs);
+        syn::visit::visit_item(self, node);
+        self.cfgs.truncate(len);
+    }
+
     fn visit_item_fn(&mut self, node: &'ast syn::ItemFn) {
         if is_no_mangle_pub_export_c_fn(node) {
-            self.0.push(LineColumnEnds::new(&node.span()))
+            let expansion = &self.expansion;
+            self.no_mangle_pub_export_c_fns.0.extend(
+                NoManglePubExportCFn::new(
+                    node,
+                    self.cfg_set,
+                    CfgPredicate::all(self.cfgs.clone()),
+                    &self.module_path,
+                )
+                .map(|mut no_mangle_pub_export_c_fn| {
+                    if let Some((name, span)) = expansion {
+                        no_mangle_pub_export_c_fn.expanded_from = Some(name.clone());
+                        no_mangle_pub_export_c_fn.location = LineColumnEnds::new(span);
+                        no_mangle_pub_export_c_fn.part_locations = None;
+                    }
+                    no_mangle_pub_export_c_fn
+                }),
+            )
         };
     }
+
+    fn visit_item_mod(&mut self, node: &'ast syn::ItemMod) {
+        self.module_path.push(node.ident.unraw().to_string());
+        syn::visit::visit_item_mod(self, node);
+        self.module_path.pop();
+    }
+
+    fn visit_item_struct(&mut self, node: &'ast syn::ItemStruct) {
+        self.add_type_def(&node.ident, TypeDefKind::Struct, &node.attrs, Vec::new());
+    }
+
+    fn visit_item_enum(&mut self, node: &'ast syn::ItemEnum) {
+        self.add_type_def(
+            &node.ident,
+            TypeDefKind::Enum,
+            &node.attrs,
+            types::enum_variants(node, self.cfg_set),
+        );
+    }
+
+    fn visit_item_union(&mut self, node: &'ast syn::ItemUnion) {
+        self.add_type_def(&node.ident, TypeDefKind::Union, &node.attrs, Vec::new());
+    }
+
+    fn visit_item_type(&mut self, node: &'ast syn::ItemType) {
+        self.add_type_def(&node.ident, TypeDefKind::TypeAlias, &node.attrs, Vec::new());
+    }
+
+    fn visit_item_macro(&mut self, node: &'ast syn::ItemMacro) {
+        let macro_rules_map = match self.macro_rules_map {
+            // Definitions (`macro_rules! name { ... }`) have an ident, invocations don't
+            Some(macro_rules_map) if node.ident.is_none() => macro_rules_map,
+            _ => return,
+        };
+        if self.expansion_depth >= Self::MAX_EXPANSION_DEPTH {
+            return;
+        }
+        if let Some((name, items)) = macro_rules_map.expand(&node.mac) {
+            let outer_expansion = self.expansion.clone();
+            self.expansion = outer_expansion.clone().or(Some((name, node.span())));
+            self.expansion_depth += 1;
+            items.iter().for_each(|item| self.visit_item(item));
+            self.expansion_depth -= 1;
+            self.expansion = outer_expansion;
+        }
+    }
 }
 
-/// The struct that contains the path to the parsed file and a collection of location information about
+// Visiting with the newtype itself records every function along with its `#[cfg]` predicates
+impl<'ast> Visit<'ast> for NoManglePubExportCFns {
+    fn visit_file(&mut self, node: &'ast syn::File) {
+        let mut visitor = NoManglePubExportCFnsVisitor::new(None, cfg::cfg_predicates(&node.attrs));
+        visitor.visit_file(node);
+        self.0.append(&mut visitor.no_mangle_pub_export_c_fns.0);
+    }
+
+    fn visit_item(&mut self, node: &'ast syn::Item) {
+        let mut visitor = NoManglePubExportCFnsVisitor::new(None, Vec::new());
+        visitor.visit_item(node);
+        self.0.append(&mut visitor.no_mangle_pub_export_c_fns.0);
+    }
+
+    fn visit_item_fn(&mut self, node: &'ast syn::ItemFn) {
+        let mut visitor = NoManglePubExportCFnsVisitor::new(None, Vec::new());
+        visitor.visit_item_fn(node);
+        self.0.append(&mut visitor.no_mangle_pub_export_c_fns.0);
+    }
+}
+
+/// The struct that contains the path to the parsed file and a collection of information about
 /// `#[no_mangle] pub export "C"` functions in that file.
 ///
 /// Read more about `#[no_mangle] pub export "C"` functions in Rust [here](https://docs.rust-embedded.org/book/interoperability/rust-with-c.html#no_mangle).
-#[derive(Serialize, Deserialize, Debug)]
+///
+/// Serializing fails if the path isn't valid UTF-8.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ParsedFile {
-    pub path: String,
+    pub path: PathBuf,
     pub no_mangle_pub_export_c_fns: NoManglePubExportCFns,
+    /// The types defined in the file, e.g. to document the types referenced by the exports in
+    /// generated headers.
+    #[serde(default)]
+    pub type_defs: Vec<TypeDef>,
+}
+
+/// Options for [parse_for_no_mangle_pub_extern_c_fns_with_options].
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// The active configuration. When [Some], functions, modules and files whose `#[cfg]` predicates
+    /// evaluate to false are pruned. Either way, the predicates are recorded in [NoManglePubExportCFn::cfg].
+    pub cfg_set: Option<CfgSet>,
+    /// Whether invocations of crate-local `macro_rules!` macros at item position should be expanded.
+    /// Enabled by default.
+    pub expand_macro_rules: bool,
+    /// The number of threads parsing the files. Defaults to the available parallelism. The output
+    /// is sorted by path regardless of the number of threads.
+    pub threads: usize,
+    /// Whether files are scanned for tokens such as `no_mangle` before being parsed, so that files
+    /// which can't contain exports or types aren't fully parsed. Enabled by default. Disabling it makes every
+    /// file go through the parser, e.g. to audit the pre-filter.
+    pub prefilter: bool,
+    /// The directory where the results are cached between scans, e.g. `target/no_mangle_pub_export_c_fn`.
+    /// Cached results are reused for files whose content hasn't changed, unless the cache format, the
+    /// options or the `mod` declarations and `macro_rules!` definitions in other files have changed.
+    /// Disabled by default.
+    pub cache_dir: Option<PathBuf>,
+    /// Whether paths are reported relative to the crate root with components separated by forward
+    /// slashes, e.g. `src/a/b.rs`, so that the output doesn't depend on the location of the crate or
+    /// on the platform. Disabled by default, in which case the paths start with the given crate root.
+    pub relative_paths: bool,
+    /// Glob patterns for the files to scan, relative to the crate root, e.g. `src/ffi/**`. As in
+    /// `.gitignore`, `*` doesn't match `/`, while `**` does. When empty, all Rust source files in
+    /// `src/**` are scanned. Patterns that aren't valid globs make the scan fail.
+    pub include: Vec<String>,
+    /// Glob patterns for the files to skip, e.g. `src/**/fixtures/**` or `**/*.orig.rs`. They take
+    /// precedence over [ScanOptions::include].
+    pub exclude: Vec<String>,
+    /// Whether files ignored by `.gitignore` and `.ignore` files, including the ones in the parent
+    /// directories of the crate, are skipped. `.gitignore` files only count within a git repository.
+    /// Disabled by default.
+    pub respect_ignore_files: bool,
+    /// Whether symbolic links are followed, as the compiler does. Otherwise, they are skipped.
+    /// Disabled by default, so that links to files outside of the crate aren't scanned unless asked for.
+    pub follow_symlinks: bool,
+    /// The environment variables available to `env!` when resolving `include!` invocations, e.g.
+    /// `OUT_DIR` for `include!(concat!(env!("OUT_DIR"), "/ffi.rs"))`. The included files are scanned
+    /// as well and reported under their own paths. Invocations that can't be resolved are ignored.
+    pub env: BTreeMap<String, String>,
+    /// Whether [LineColumnEnds::start_utf16_column] and [LineColumnEnds::end_utf16_column] are set,
+    /// e.g. for editors. Disabled by default, since the sources have to be kept until the functions are found.
+    pub utf16_columns: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            cfg_set: None,
+            expand_macro_rules: true,
+            threads: std::thread::available_parallelism().map_or(1, usize::from),
+            prefilter: true,
+            cache_dir: None,
+            relative_paths: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            respect_ignore_files: false,
+            follow_symlinks: false,
+            env: BTreeMap::new(),
+            utf16_columns: false,
+        }
+    }
+}
+
+/// Rewrites the path relative to `crate_root` with forward slashes, if `options.relative_paths` is set.
+pub(crate) fn relativize_path(
+    parsed_file: &mut ParsedFile,
+    crate_root: &Path,
+    options: &ScanOptions,
+) {
+    if !options.relative_paths {
+        return;
+    }
+    if let Ok(relative_path) = parsed_file.path.strip_prefix(crate_root) {
+        // Joined as OsString, so that non-UTF-8 components are preserved
+        let mut path = OsString::new();
+        relative_path.iter().enumerate().for_each(|(i, component)| {
+            if i > 0 {
+                path.push("/");
+            }
+            path.push(component);
+        });
+        parsed_file.path = PathBuf::from(path);
+    }
+}
+
+/// Does the same as [relativize_path] for every parsed file.
+pub(crate) fn relativize_paths(
+    parsed_files: &mut [ParsedFile],
+    crate_root: &Path,
+    options: &ScanOptions,
+) {
+    parsed_files
+        .iter_mut()
+        .for_each(|parsed_file| relativize_path(parsed_file, crate_root, options));
 }
 
 /// Traverses all directories in `crate_root/src/**` filtering Rust source files, parsing them and returning a
@@ -214,11 +917,16 @@ pub struct ParsedFile {
 ///
 /// ```
 /// extern crate serde_json;
-/// use no_mangle_pub_export_c_fn::{parse_for_no_mangle_pub_extern_c_fns, ParsedFile};
+/// use no_mangle_pub_export_c_fn::{parse_for_no_mangle_pub_extern_c_fns_with_options, ParsedFile, ScanOptions};
 ///
 /// fn main() -> serde_json::Result<()> {
 ///     let crate_root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
-///     let parsed_files: Vec<ParsedFile> = parse_for_no_mangle_pub_extern_c_fns(crate_root.as_str());
+///     let options = ScanOptions {
+///         relative_paths: true,
+///         ..Default::default()
+///     };
+///     let parsed_files: Vec<ParsedFile> =
+///         parse_for_no_mangle_pub_extern_c_fns_with_options(crate_root.as_str(), &options).unwrap();
 ///     println!("{}", serde_json::to_string(&parsed_files)?);
 ///     Ok(())
 /// }
@@ -233,56 +941,95 @@ pub struct ParsedFile {
 /// }
 /// ```
 ///
-/// # Output on Windows
+/// # Output
+///
+/// The same on every platform, since the paths are relative to the crate root and separated by
+/// forward slashes. The other files have been left out.
 ///
 /// ```text
-/// [{"path":"...\\no_mangle_pub_export_c_fn\\src\\lib.rs","no_mangle_pub_export_c_fns":[]},{"path":"...\\no_mangle_pub_export_c_fn\\src\\main.rs","no_mangle_pub_export_c_fns":[]},{"path":"..\\no_mangle_pub_export_c_fn\\src\\unused.rs","no_mangle_pub_export_c_fns":[{"start_line":1,"start_column":0,"end_line":4,"end_column":1}]}]
+/// [...,{"path":"src/main.rs","no_mangle_pub_export_c_fns":[],"type_defs":[]},...,{"path":"src/unused.rs","no_mangle_pub_export_c_fns":[{"ident":"s","symbol":"s","module_path":["unused"],"rust_path":"crate::unused::s","inputs":[],"output":null,"resolved_output":null,"doc":null,"cfg":null,"export_cfg":null,"symbol_cfg":null,"fallback_symbol":null,"expanded_from":null,"location":{"start_line":1,"start_column":0,"end_line":4,"end_column":1,"start_byte":0,"end_byte":50,"start_utf16_column":null,"end_utf16_column":null},"part_locations":{"attrs":[{"start_line":1,"start_column":0,"end_line":1,"end_column":12,"start_byte":0,"end_byte":12,"start_utf16_column":null,"end_utf16_column":null}],"signature":{"start_line":2,"start_column":15,"end_line":2,"end_column":21,"start_byte":28,"end_byte":34,"start_utf16_column":null,"end_utf16_column":null},"body":{"start_line":2,"start_column":22,"end_line":4,"end_column":1,"start_byte":35,"end_byte":50,"start_utf16_column":null,"end_utf16_column":null}}}],"type_defs":[]}]
 /// ```
 ///
 /// # Note
 ///
-/// The paths will be absolute. The prefixes have been deleted intentionally.
+/// Without [ScanOptions::relative_paths], as with this function, the paths start with `crate_root`
+/// and use the separators of the platform.
 pub fn parse_for_no_mangle_pub_extern_c_fns(crate_root: &str) -> Vec<ParsedFile> {
-    // With prior information, the buffer could be preallocated
-    let mut buffer = String::new();
-
-    // All errors are skipped. In the hindsight, the solution with ? would be better
-    WalkDir::new(format!("{}{}src", crate_root, std::path::MAIN_SEPARATOR))
-        .into_iter()
-        .flatten()
-        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".rs"))
-        // BufReader is unnecessary bc the files are read only once
-        // https://doc.rust-lang.org/std/io/struct.BufReader.html
-        .filter_map(|rust_file| {
-            let path = rust_file.path();
-            std::fs::File::open(path)
-                .map(|file: std::fs::File| (path.to_string_lossy().into_owned(), file))
-                .ok()
-        })
-        .filter_map(|(path, mut file): (String, std::fs::File)| {
-            file.read_to_string(&mut buffer)
-                .map(|_byted_read| {
-                    let file = syn::parse_file(&buffer);
-                    buffer.truncate(0);
-                    (path, file)
-                })
-                .ok()
-        })
-        .filter_map(|(path, res_file): (String, syn::Result<syn::File>)| {
-            res_file.ok().map(|file| (path, file))
-        })
-        .fold(
-            Vec::<ParsedFile>::new(),
-            |mut parsed_files: Vec<ParsedFile>, (path, file): (String, syn::File)| {
-                parsed_files.push(ParsedFile {
-                    path,
-                    no_mangle_pub_export_c_fns: {
-                        let mut no_mangle_pub_export_c_fns = NoManglePubExportCFns::default();
-                        no_mangle_pub_export_c_fns.visit_file(&file);
-                        no_mangle_pub_export_c_fns
-                    },
-                });
-                parsed_files
-            },
+    parse_for_no_mangle_pub_extern_c_fns_with_options(crate_root, &ScanOptions::default())
+        .expect("the default options have no globs")
+}
+
+/// Does the same as [parse_for_no_mangle_pub_extern_c_fns] but accepts [ScanOptions].
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{parse_for_no_mangle_pub_extern_c_fns_with_options, CfgSet, ScanOptions};
+///
+/// let crate_root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+/// let mut cfg_set = CfgSet::default();
+/// cfg_set.insert_feature("gpu");
+/// let options = ScanOptions {
+///     cfg_set: Some(cfg_set),
+///     ..Default::default()
+/// };
+/// let parsed_files = parse_for_no_mangle_pub_extern_c_fns_with_options(crate_root.as_str(), &options).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// Fails if any of the patterns in [ScanOptions::include] and [ScanOptions::exclude] isn't a valid glob.
+pub fn parse_for_no_mangle_pub_extern_c_fns_with_options(
+    crate_root: &str,
+    options: &ScanOptions,
+) -> Result<Vec<ParsedFile>, globset::Error> {
+    let mut parsed_files =
+        parse_rust_source_files(rust_source_files(crate_root, options)?, options);
+    relativize_paths(&mut parsed_files, Path::new(crate_root), options);
+    Ok(parsed_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_paths_relative_to_the_crate_root() {
+        let crate_root = tempfile::tempdir().unwrap();
+        let src_dir = crate_root.path().join("src");
+        std::fs::create_dir_all(src_dir.join("a")).unwrap();
+        std::fs::write(src_dir.join("lib.rs"), "mod a;\n").unwrap();
+        std::fs::write(src_dir.join("a/mod.rs"), "mod b;\n").unwrap();
+        std::fs::write(
+            src_dir.join("a/b.rs"),
+            "#[no_mangle] pub extern \"C\" fn f() {}\n",
         )
+        .unwrap();
+        let crate_root_str = crate_root.path().to_str().unwrap();
+
+        let options = ScanOptions {
+            relative_paths: true,
+            ..Default::default()
+        };
+        let parsed_files =
+            parse_for_no_mangle_pub_extern_c_fns_with_options(crate_root_str, &options).unwrap();
+        let paths: Vec<&Path> = parsed_files
+            .iter()
+            .map(|parsed_file| parsed_file.path.as_path())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("src/a/b.rs"),
+                Path::new("src/a/mod.rs"),
+                Path::new("src/lib.rs")
+            ]
+        );
+        assert!(serde_json::to_string(&parsed_files)
+            .unwrap()
+            .starts_with("[{\"path\":\"src/a/b.rs\","));
+
+        let parsed_files = parse_for_no_mangle_pub_extern_c_fns(crate_root_str);
+        assert_eq!(parsed_files[0].path, src_dir.join("a").join("b.rs"));
+    }
 }
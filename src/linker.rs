@@ -0,0 +1,116 @@
+use std::collections::BTreeSet;
+
+use crate::ParsedFile;
+
+/// Each symbol once, in the order of first appearance, since functions behind mutually exclusive
+/// `#[cfg]`s may share one. Both symbols of a conditional `export_name` are included.
+fn symbols(parsed_files: &[ParsedFile]) -> impl Iterator<Item = &str> {
+    let mut seen = BTreeSet::new();
+    parsed_files
+        .iter()
+        .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+        .flat_map(|no_mangle_pub_export_c_fn| {
+            std::iter::once(no_mangle_pub_export_c_fn.symbol.as_str())
+                .chain(no_mangle_pub_export_c_fn.fallback_symbol.as_deref())
+        })
+        .filter(move |symbol| seen.insert(*symbol))
+}
+
+/// Generates a [GNU ld version script](https://sourceware.org/binutils/docs/ld/VERSION.html) that keeps
+/// the symbols of all `#[no_mangle] pub export "C"` functions global and makes everything else local.
+///
+/// The symbols of functions behind `#[cfg]`s are listed regardless of the target, and so are both
+/// symbols of `#[cfg_attr(..., export_name = "...")]`. Scan with [ScanOptions::cfg_set](crate::ScanOptions::cfg_set)
+/// to only list the symbols of a specific target.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{generate_version_script, parse_for_no_mangle_pub_extern_c_fns};
+///
+/// let crate_root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+/// let parsed_files = parse_for_no_mangle_pub_extern_c_fns(crate_root.as_str());
+/// assert_eq!(
+///     generate_version_script(&parsed_files),
+///     "{\n  global:\n    s;\n  local: *;\n};\n"
+/// );
+/// ```
+pub fn generate_version_script(parsed_files: &[ParsedFile]) -> String {
+    let mut version_script = String::from("{\n  global:\n");
+    symbols(parsed_files).for_each(|symbol| {
+        version_script.push_str(&format!("    {};\n", symbol));
+    });
+    version_script.push_str("  local: *;\n};\n");
+    version_script
+}
+
+/// Generates a [module-definition (.def) file](https://docs.microsoft.com/en-us/cpp/build/reference/module-definition-dot-def-files)
+/// that exports the symbols of all `#[no_mangle] pub export "C"` functions. When `library` is given,
+/// a `LIBRARY` statement is emitted as well.
+///
+/// The symbols are listed the same way as by [generate_version_script]. Since `link.exe` fails on
+/// exports that aren't defined, scan with [ScanOptions::cfg_set](crate::ScanOptions::cfg_set) if some
+/// are conditional.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{generate_module_definition, parse_for_no_mangle_pub_extern_c_fns};
+///
+/// let crate_root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+/// let parsed_files = parse_for_no_mangle_pub_extern_c_fns(crate_root.as_str());
+/// assert_eq!(
+///     generate_module_definition(&parsed_files, Some("mylib")),
+///     "LIBRARY mylib\nEXPORTS\n    s\n"
+/// );
+/// ```
+pub fn generate_module_definition(parsed_files: &[ParsedFile], library: Option<&str>) -> String {
+    let mut module_definition = library
+        .map(|library| format!("LIBRARY {}\n", library))
+        .unwrap_or_default();
+    module_definition.push_str("EXPORTS\n");
+    symbols(parsed_files).for_each(|symbol| {
+        module_definition.push_str(&format!("    {}\n", symbol));
+    });
+    module_definition
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed_files() -> Vec<ParsedFile> {
+        crate::parse_sources_for_no_mangle_pub_extern_c_fns(
+            vec![(
+                "src/lib.rs",
+                r#"
+                #[no_mangle]
+                pub extern "C" fn first() {}
+                #[cfg(unix)]
+                #[no_mangle]
+                pub extern "C" fn dup(x: i32) {}
+                #[cfg(windows)]
+                #[no_mangle]
+                pub extern "C" fn dup(x: i64) {}
+                #[no_mangle]
+                #[cfg_attr(windows, export_name = "last_win")]
+                pub extern "C" fn last() {}
+                "#,
+            )],
+            &crate::ScanOptions::default(),
+        )
+    }
+
+    #[test]
+    fn lists_cfg_exclusive_twins_once() {
+        let parsed_files = parsed_files();
+        assert_eq!(
+            generate_version_script(&parsed_files),
+            "{\n  global:\n    first;\n    dup;\n    last_win;\n    last;\n  local: *;\n};\n"
+        );
+        assert_eq!(
+            generate_module_definition(&parsed_files, None),
+            "EXPORTS\n    first\n    dup\n    last_win\n    last\n"
+        );
+    }
+}
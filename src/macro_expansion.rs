@@ -0,0 +1,764 @@
+//! A limited implementation of [`macro_rules!`](https://doc.rust-lang.org/reference/macros-by-example.html)
+//! expansion, sufficient for the common case of macros that stamp out functions.
+//!
+//! Matching is done by backtracking over the token trees of the invocation. Fragments other than
+//! `tt`, `ident`, `lifetime`, `literal` and `block` are matched by parsing the longest prefix
+//! with [syn]. Hygiene is not modeled.
+
+use std::collections::{HashMap, HashSet};
+
+use proc_macro2::{Delimiter, Group, Ident, Span, TokenStream, TokenTree};
+use serde::{Deserialize, Serialize};
+use syn::parse::{Parse, ParseStream, Parser};
+
+use crate::cfg::{self, CfgPredicate};
+use crate::CfgSet;
+
+/// The maximum number of alternative matches, and of forks of a repetition, explored while matching
+/// a single rule. Protects against combinatorial explosion on ambiguous matchers; repetitions with
+/// a single live option at each step aren't limited by it.
+const MAX_ALTERNATIVES: usize = 1024;
+
+struct Repetition<T> {
+    nodes: Vec<T>,
+    separator: Option<TokenTree>,
+    /// One of `*`, `+` or `?`
+    op: char,
+}
+
+enum Matcher {
+    Token(TokenTree),
+    Group(Delimiter, Vec<Matcher>),
+    Fragment(String, String),
+    Repetition(Repetition<Matcher>),
+}
+
+enum Transcriber {
+    Token(TokenTree),
+    Group(Delimiter, Vec<Transcriber>),
+    Var(String),
+    Repetition(Repetition<Transcriber>),
+}
+
+struct Rule {
+    matcher: Vec<Matcher>,
+    transcriber: Vec<Transcriber>,
+}
+
+#[derive(Clone)]
+enum Binding {
+    /// The matched tokens and the fragment specifier
+    Fragment(Vec<TokenTree>, String),
+    Seq(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+fn is_punct(token: Option<&TokenTree>, ch: char) -> bool {
+    matches!(token, Some(TokenTree::Punct(punct)) if punct.as_char() == ch)
+}
+
+fn is_repetition_op(token: Option<&TokenTree>) -> Option<char> {
+    ['*', '+', '?']
+        .iter()
+        .copied()
+        .find(|op| is_punct(token, *op))
+}
+
+/// Parses `$(...) sep? op` which starts at `tokens[i]`, i.e. right after `$`. Returns the index past it.
+fn parse_repetition<T>(
+    tokens: &[TokenTree],
+    i: usize,
+    parse: fn(TokenStream) -> Option<Vec<T>>,
+) -> Option<(Repetition<T>, usize)> {
+    let nodes = match tokens.get(i) {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+            parse(group.stream())?
+        }
+        _ => return None,
+    };
+    match is_repetition_op(tokens.get(i + 1)) {
+        Some(op) => Some((
+            Repetition {
+                nodes,
+                separator: None,
+                op,
+            },
+            i + 2,
+        )),
+        None => {
+            let op = is_repetition_op(tokens.get(i + 2)).filter(|op| *op != '?')?;
+            Some((
+                Repetition {
+                    nodes,
+                    separator: tokens.get(i + 1).cloned(),
+                    op,
+                },
+                i + 3,
+            ))
+        }
+    }
+}
+
+fn parse_matcher(stream: TokenStream) -> Option<Vec<Matcher>> {
+    let tokens: Vec<TokenTree> = stream.into_iter().collect();
+    let mut matcher = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match (&tokens[i], tokens.get(i + 1)) {
+            (TokenTree::Punct(punct), Some(TokenTree::Ident(name))) if punct.as_char() == '$' => {
+                match tokens.get(i + 3) {
+                    Some(TokenTree::Ident(kind)) if is_punct(tokens.get(i + 2), ':') => {
+                        matcher.push(Matcher::Fragment(name.to_string(), kind.to_string()));
+                        i += 4;
+                    }
+                    _ => return None,
+                }
+            }
+            (TokenTree::Punct(punct), Some(TokenTree::Group(_))) if punct.as_char() == '$' => {
+                let (repetition, next) = parse_repetition(&tokens, i + 1, parse_matcher)?;
+                matcher.push(Matcher::Repetition(repetition));
+                i = next;
+            }
+            (TokenTree::Group(group), _) => {
+                matcher.push(Matcher::Group(
+                    group.delimiter(),
+                    parse_matcher(group.stream())?,
+                ));
+                i += 1;
+            }
+            (token, _) => {
+                matcher.push(Matcher::Token(token.clone()));
+                i += 1;
+            }
+        }
+    }
+    Some(matcher)
+}
+
+fn parse_transcriber(stream: TokenStream) -> Option<Vec<Transcriber>> {
+    let tokens: Vec<TokenTree> = stream.into_iter().collect();
+    let mut transcriber = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match (&tokens[i], tokens.get(i + 1)) {
+            (TokenTree::Punct(punct), Some(TokenTree::Ident(name))) if punct.as_char() == '$' => {
+                transcriber.push(Transcriber::Var(name.to_string()));
+                i += 2;
+            }
+            (TokenTree::Punct(punct), Some(TokenTree::Group(_))) if punct.as_char() == '$' => {
+                let (repetition, next) = parse_repetition(&tokens, i + 1, parse_transcriber)?;
+                transcriber.push(Transcriber::Repetition(repetition));
+                i = next;
+            }
+            (TokenTree::Group(group), _) => {
+                transcriber.push(Transcriber::Group(
+                    group.delimiter(),
+                    parse_transcriber(group.stream())?,
+                ));
+                i += 1;
+            }
+            (token, _) => {
+                transcriber.push(Transcriber::Token(token.clone()));
+                i += 1;
+            }
+        }
+    }
+    Some(transcriber)
+}
+
+/// Parses the body of `macro_rules! name { (matcher) => { transcriber }; ... }`.
+fn parse_rules(stream: TokenStream) -> Option<Vec<Rule>> {
+    let tokens: Vec<TokenTree> = stream.into_iter().collect();
+    let mut rules = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match (&tokens[i], tokens.get(i + 3)) {
+            (TokenTree::Group(matcher), Some(TokenTree::Group(transcriber)))
+                if is_punct(tokens.get(i + 1), '=') && is_punct(tokens.get(i + 2), '>') =>
+            {
+                rules.push(Rule {
+                    matcher: parse_matcher(matcher.stream())?,
+                    transcriber: parse_transcriber(transcriber.stream())?,
+                });
+                i += 4;
+            }
+            _ => return None,
+        }
+        if is_punct(tokens.get(i), ';') {
+            i += 1;
+        }
+    }
+    Some(rules)
+}
+
+fn is_same_token(expected: &TokenTree, actual: &TokenTree) -> bool {
+    match (expected, actual) {
+        (TokenTree::Ident(expected), TokenTree::Ident(actual)) => expected == actual,
+        (TokenTree::Punct(expected), TokenTree::Punct(actual)) => {
+            expected.as_char() == actual.as_char()
+        }
+        (TokenTree::Literal(expected), TokenTree::Literal(actual)) => {
+            expected.to_string() == actual.to_string()
+        }
+        _ => false,
+    }
+}
+
+/// Returns the end of the longest prefix of `tokens[pos..]` that parses as `T`.
+fn parse_prefix<T: Parse>(tokens: &[TokenTree], pos: usize) -> Option<usize> {
+    let stream: TokenStream = tokens[pos..].iter().cloned().collect();
+    let rest = (|input: ParseStream| {
+        input.parse::<T>()?;
+        input.parse::<TokenStream>()
+    })
+    .parse2(stream)
+    .ok()?;
+    Some(tokens.len() - rest.into_iter().count())
+}
+
+/// Returns the end of the fragment of the given kind starting at `tokens[pos]`.
+fn match_fragment(kind: &str, tokens: &[TokenTree], pos: usize) -> Option<usize> {
+    match kind {
+        "tt" => tokens.get(pos).map(|_| pos + 1),
+        "ident" => match tokens.get(pos) {
+            Some(TokenTree::Ident(ident)) if ident != "_" => Some(pos + 1),
+            _ => None,
+        },
+        "lifetime" => match tokens.get(pos + 1) {
+            Some(TokenTree::Ident(_)) if is_punct(tokens.get(pos), '\'') => Some(pos + 2),
+            _ => None,
+        },
+        "literal" => match (tokens.get(pos), tokens.get(pos + 1)) {
+            (Some(TokenTree::Literal(_)), _) => Some(pos + 1),
+            (Some(TokenTree::Punct(punct)), Some(TokenTree::Literal(_)))
+                if punct.as_char() == '-' =>
+            {
+                Some(pos + 2)
+            }
+            _ => None,
+        },
+        "block" => match tokens.get(pos) {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => Some(pos + 1),
+            _ => None,
+        },
+        "vis" => parse_prefix::<syn::Visibility>(tokens, pos),
+        "ty" => parse_prefix::<syn::Type>(tokens, pos),
+        "expr" | "expr_2021" => parse_prefix::<syn::Expr>(tokens, pos),
+        "path" => parse_prefix::<syn::Path>(tokens, pos),
+        "pat" | "pat_param" => parse_prefix::<syn::Pat>(tokens, pos),
+        "item" => parse_prefix::<syn::Item>(tokens, pos),
+        "stmt" => parse_prefix::<syn::Stmt>(tokens, pos),
+        "meta" => parse_prefix::<syn::Meta>(tokens, pos),
+        _ => None,
+    }
+}
+
+fn matcher_names(nodes: &[Matcher], names: &mut Vec<String>) {
+    nodes.iter().for_each(|node| match node {
+        Matcher::Token(_) => {}
+        Matcher::Group(_, nodes) => matcher_names(nodes, names),
+        Matcher::Fragment(name, _) => names.push(name.clone()),
+        Matcher::Repetition(repetition) => matcher_names(&repetition.nodes, names),
+    });
+}
+
+/// Returns all possible ends of matching `nodes` against `tokens[pos..]` along with the bindings.
+/// With `to_end`, only matches that consume all of `tokens` are returned.
+fn match_nodes(
+    nodes: &[Matcher],
+    tokens: &[TokenTree],
+    pos: usize,
+    to_end: bool,
+) -> Vec<(usize, Bindings)> {
+    let (node, rest) = match nodes.split_first() {
+        Some(split) => split,
+        None if to_end && pos != tokens.len() => return Vec::new(),
+        None => return vec![(pos, Bindings::new())],
+    };
+    if let Matcher::Repetition(repetition) = node {
+        return match_repetition(repetition, rest, tokens, pos, to_end);
+    }
+    let mut alternatives = Vec::new();
+    for (pos, bindings) in match_node(node, tokens, pos) {
+        for (end, mut rest_bindings) in match_nodes(rest, tokens, pos, to_end) {
+            rest_bindings.extend(bindings.clone());
+            alternatives.push((end, rest_bindings));
+            if alternatives.len() >= MAX_ALTERNATIVES {
+                return alternatives;
+            }
+        }
+    }
+    alternatives
+}
+
+fn match_node(node: &Matcher, tokens: &[TokenTree], pos: usize) -> Vec<(usize, Bindings)> {
+    match node {
+        Matcher::Token(expected) => match tokens.get(pos) {
+            Some(actual) if is_same_token(expected, actual) => vec![(pos + 1, Bindings::new())],
+            _ => Vec::new(),
+        },
+        Matcher::Group(delimiter, nodes) => match tokens.get(pos) {
+            Some(TokenTree::Group(group)) if group.delimiter() == *delimiter => {
+                let inner: Vec<TokenTree> = group.stream().into_iter().collect();
+                match_nodes(nodes, &inner, 0, true)
+                    .into_iter()
+                    .map(|(_, bindings)| (pos + 1, bindings))
+                    .collect()
+            }
+            _ => Vec::new(),
+        },
+        Matcher::Fragment(name, kind) => match_fragment(kind, tokens, pos)
+            .map(|end| {
+                let mut bindings = Bindings::new();
+                bindings.insert(
+                    name.clone(),
+                    Binding::Fragment(tokens[pos..end].to_vec(), kind.clone()),
+                );
+                vec![(end, bindings)]
+            })
+            .unwrap_or_default(),
+        Matcher::Repetition(repetition) => match_repetition(repetition, &[], tokens, pos, false),
+    }
+}
+
+/// Matches `repetition` followed by `rest`. As long as either stopping or another iteration is the
+/// only option, the repetition proceeds in place; only a choice between several live options
+/// forks the iterations matched so far.
+fn match_repetition(
+    repetition: &Repetition<Matcher>,
+    rest: &[Matcher],
+    tokens: &[TokenTree],
+    pos: usize,
+    to_end: bool,
+) -> Vec<(usize, Bindings)> {
+    let mut names = Vec::new();
+    matcher_names(&repetition.nodes, &mut names);
+
+    let mut alternatives = Vec::new();
+    let mut forks = 0;
+    let mut frontier: Vec<(usize, Vec<Bindings>)> = vec![(pos, Vec::new())];
+    while let Some((mut pos, mut iterations)) = frontier.pop() {
+        loop {
+            if alternatives.len() >= MAX_ALTERNATIVES {
+                return alternatives;
+            }
+            if repetition.op != '+' || !iterations.is_empty() {
+                let stops = match_nodes(rest, tokens, pos, to_end);
+                if !stops.is_empty() {
+                    let mut bindings = Bindings::new();
+                    names.iter().for_each(|name| {
+                        let seq = iterations
+                            .iter()
+                            .filter_map(|iteration| iteration.get(name).cloned())
+                            .collect();
+                        bindings.insert(name.clone(), Binding::Seq(seq));
+                    });
+                    alternatives.extend(stops.into_iter().map(|(end, mut rest_bindings)| {
+                        rest_bindings.extend(bindings.clone());
+                        (end, rest_bindings)
+                    }));
+                }
+            }
+            if repetition.op == '?' && !iterations.is_empty() {
+                break;
+            }
+            let start = match (&repetition.separator, iterations.is_empty()) {
+                (Some(separator), false) => match tokens.get(pos) {
+                    Some(token) if is_same_token(separator, token) => pos + 1,
+                    _ => break,
+                },
+                _ => pos,
+            };
+            // An iteration that consumes nothing would repeat forever
+            let mut next: Vec<(usize, Bindings)> =
+                match_nodes(&repetition.nodes, tokens, start, false)
+                    .into_iter()
+                    .filter(|(end, _)| *end != pos)
+                    .collect();
+            let (end, bindings) = match next.pop() {
+                Some(iteration) => iteration,
+                None => break,
+            };
+            for (end, bindings) in next {
+                forks += 1;
+                if forks >= MAX_ALTERNATIVES {
+                    return alternatives;
+                }
+                let mut iterations = iterations.clone();
+                iterations.push(bindings);
+                frontier.push((end, iterations));
+            }
+            iterations.push(bindings);
+            pos = end;
+        }
+    }
+    alternatives
+}
+
+fn lookup<'a>(bindings: &'a Bindings, name: &str, indices: &[usize]) -> Option<&'a Binding> {
+    let mut binding = bindings.get(name)?;
+    for index in indices {
+        match binding {
+            Binding::Seq(seq) => binding = seq.get(*index)?,
+            Binding::Fragment(..) => break,
+        }
+    }
+    Some(binding)
+}
+
+fn transcriber_names(nodes: &[Transcriber], names: &mut HashSet<String>) {
+    nodes.iter().for_each(|node| match node {
+        Transcriber::Token(_) => {}
+        Transcriber::Group(_, nodes) => transcriber_names(nodes, names),
+        Transcriber::Var(name) => {
+            names.insert(name.clone());
+        }
+        Transcriber::Repetition(repetition) => transcriber_names(&repetition.nodes, names),
+    });
+}
+
+fn transcribe(
+    nodes: &[Transcriber],
+    bindings: &Bindings,
+    indices: &[usize],
+    output: &mut TokenStream,
+) -> Option<()> {
+    for node in nodes {
+        match node {
+            Transcriber::Token(token) => output.extend(std::iter::once(token.clone())),
+            Transcriber::Group(delimiter, nodes) => {
+                let mut stream = TokenStream::new();
+                transcribe(nodes, bindings, indices, &mut stream)?;
+                output.extend(std::iter::once(TokenTree::Group(Group::new(
+                    *delimiter, stream,
+                ))));
+            }
+            Transcriber::Var(name) => match lookup(bindings, name, indices) {
+                // Expressions and types are substituted as opaque groups to preserve precedence
+                Some(Binding::Fragment(tokens, kind))
+                    if matches!(kind.as_str(), "expr" | "expr_2021" | "ty") =>
+                {
+                    output.extend(std::iter::once(TokenTree::Group(Group::new(
+                        Delimiter::None,
+                        tokens.iter().cloned().collect(),
+                    ))));
+                }
+                Some(Binding::Fragment(tokens, _)) => output.extend(tokens.iter().cloned()),
+                Some(Binding::Seq(_)) => return None,
+                None if name == "crate" => output.extend(std::iter::once(TokenTree::Ident(
+                    Ident::new("crate", Span::call_site()),
+                ))),
+                None => return None,
+            },
+            Transcriber::Repetition(repetition) => {
+                let mut names = HashSet::new();
+                transcriber_names(&repetition.nodes, &mut names);
+                let count =
+                    names
+                        .iter()
+                        .find_map(|name| match lookup(bindings, name, indices) {
+                            Some(Binding::Seq(seq)) => Some(seq.len()),
+                            _ => None,
+                        })?;
+                let mut indices = indices.to_vec();
+                for i in 0..count {
+                    if let (Some(separator), true) = (&repetition.separator, i != 0) {
+                        output.extend(std::iter::once(separator.clone()));
+                    }
+                    indices.push(i);
+                    transcribe(&repetition.nodes, bindings, &indices, output)?;
+                    indices.pop();
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// A `macro_rules!` definition whose body is kept as source text. Unlike token streams, it can be
+/// sent to other threads.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct MacroRulesDefinition {
+    name: String,
+    body: String,
+}
+
+/// Collects `macro_rules!` definitions from the items, including the ones in inline modules.
+/// Definitions disabled by `cfg_set` are skipped.
+pub(crate) fn macro_rules_definitions(
+    items: &[syn::Item],
+    cfg_set: Option<&CfgSet>,
+    definitions: &mut Vec<MacroRulesDefinition>,
+) {
+    items
+        .iter()
+        .filter(|item| {
+            let cfgs: Vec<CfgPredicate> = cfg::cfg_predicates(cfg::item_attrs(item));
+            cfg::is_cfg_enabled(cfg_set, &cfgs)
+        })
+        .for_each(|item| match item {
+            syn::Item::Macro(syn::ItemMacro {
+                ident: Some(ident),
+                mac,
+                ..
+            }) if mac.path.is_ident("macro_rules") => definitions.push(MacroRulesDefinition {
+                name: ident.to_string(),
+                body: mac.tokens.to_string(),
+            }),
+            syn::Item::Mod(syn::ItemMod {
+                content: Some((_, items)),
+                ..
+            }) => macro_rules_definitions(items, cfg_set, definitions),
+            _ => {}
+        });
+}
+
+/// The crate-local `macro_rules!` macros, by name. The same name may be defined several times,
+/// e.g. in different modules, in which case the definitions are tried in order.
+pub(crate) struct MacroRulesMap {
+    macros: HashMap<String, Vec<Vec<Rule>>>,
+}
+
+impl MacroRulesMap {
+    pub(crate) fn new(definitions: &[MacroRulesDefinition]) -> Self {
+        let mut macros: HashMap<String, Vec<Vec<Rule>>> = HashMap::new();
+        definitions
+            .iter()
+            .filter_map(|definition| {
+                let body: TokenStream = definition.body.parse().ok()?;
+                parse_rules(body).map(|rules| (definition.name.clone(), rules))
+            })
+            .for_each(|(name, rules)| macros.entry(name).or_default().push(rules));
+        Self { macros }
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.macros.contains_key(name)
+    }
+
+    /// Expands the invocation of a known macro into items. Returns the name of the macro along with
+    /// the items or [None] if the macro is unknown, no rule matches, or the expansion isn't a
+    /// sequence of items.
+    ///
+    /// As with rustc, the first rule whose matcher matches the invocation is used, even if its
+    /// expansion turns out not to be a sequence of items.
+    pub(crate) fn expand(&self, mac: &syn::Macro) -> Option<(String, Vec<syn::Item>)> {
+        let name = mac.path.segments.last()?.ident.to_string();
+        let tokens: Vec<TokenTree> = mac.tokens.clone().into_iter().collect();
+        self.macros.get(&name)?.iter().find_map(|rules| {
+            let (rule, bindings) = rules.iter().find_map(|rule| {
+                match_nodes(&rule.matcher, &tokens, 0, true)
+                    .into_iter()
+                    .next()
+                    .map(|(_, bindings)| (rule, bindings))
+            })?;
+            let mut expansion = TokenStream::new();
+            transcribe(&rule.transcriber, &bindings, &[], &mut expansion)?;
+            syn::parse2::<syn::File>(expansion)
+                .ok()
+                .map(|file| (name.clone(), file.items))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_sources_for_no_mangle_pub_extern_c_fns, ScanOptions};
+
+    /// Expands `invocation` with the macros defined in `definitions` and prints the items back.
+    fn expand(definitions: &str, invocation: &str) -> Option<Vec<String>> {
+        let file = syn::parse_file(definitions).unwrap();
+        let mut macro_rules = Vec::new();
+        macro_rules_definitions(&file.items, None, &mut macro_rules);
+        let item_macro: syn::ItemMacro = syn::parse_str(invocation).unwrap();
+        MacroRulesMap::new(&macro_rules)
+            .expand(&item_macro.mac)
+            .map(|(_, items)| {
+                items
+                    .iter()
+                    .map(|item| quote::ToTokens::to_token_stream(item).to_string())
+                    .collect()
+            })
+    }
+
+    fn symbols(sources: Vec<(&str, &str)>) -> Vec<String> {
+        let mut symbols: Vec<String> =
+            parse_sources_for_no_mangle_pub_extern_c_fns(sources, &ScanOptions::default())
+                .iter()
+                .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+                .map(|no_mangle_pub_export_c_fn| no_mangle_pub_export_c_fn.symbol.clone())
+                .collect();
+        symbols.sort();
+        symbols
+    }
+
+    #[test]
+    fn commits_to_the_first_matching_rule() {
+        let definitions = "macro_rules! m {\n\
+                               ($name:ident) => { let $name = 1; };\n\
+                               ($name:ident) => { fn $name() {} };\n\
+                               ($name:literal) => { fn literal() {} };\n\
+                           }";
+        assert_eq!(expand(definitions, "m!(f);"), None);
+        assert_eq!(
+            expand(definitions, "m!(\"f\");"),
+            Some(vec!["fn literal () { }".to_string()])
+        );
+        assert_eq!(expand(definitions, "m!(f g);"), None);
+        assert_eq!(expand(definitions, "unknown!(f);"), None);
+    }
+
+    #[test]
+    fn expands_repetitions_with_separators() {
+        let definitions = "macro_rules! star { ($($f:ident),*) => { $(fn $f() {})* }; }\n\
+                           macro_rules! plus { ($($f:ident);+ $(;)?) => { $(fn $f() {})+ }; }\n\
+                           macro_rules! opt { ($f:ident $(-> $ret:ty)?) => { fn $f() $(-> $ret)? {} }; }";
+        assert_eq!(expand(definitions, "star!();"), Some(Vec::new()));
+        assert_eq!(
+            expand(definitions, "star!(a, b, c);"),
+            Some(
+                vec!["fn a () { }", "fn b () { }", "fn c () { }"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            )
+        );
+        assert_eq!(expand(definitions, "star!(a, b,);"), None);
+        assert_eq!(expand(definitions, "star!(a b);"), None);
+        assert_eq!(expand(definitions, "plus!();"), None);
+        assert_eq!(
+            expand(definitions, "plus!(a; b;);").map(|items| items.len()),
+            Some(2)
+        );
+        assert_eq!(
+            expand(definitions, "opt!(a);"),
+            Some(vec!["fn a () { }".to_string()])
+        );
+        assert_eq!(
+            expand(definitions, "opt!(a -> u8);"),
+            Some(vec!["fn a () -> u8 { }".to_string()])
+        );
+        assert_eq!(expand(definitions, "opt!(a -> u8 -> u8);"), None);
+    }
+
+    #[test]
+    fn expands_nested_repetitions() {
+        let definitions = "macro_rules! mods {\n\
+                               ($($m:ident { $($f:ident($($arg:ident: $ty:ty),*)),* })*) => {\n\
+                                   $(mod $m { $(pub fn $f($($arg: $ty),*) {})* })*\n\
+                               };\n\
+                           }";
+        assert_eq!(
+            expand(
+                definitions,
+                "mods!(a { f(x: u8, y: *const u8), g() } b {});"
+            ),
+            Some(vec![
+                "mod a { pub fn f (x : u8 , y : * const u8) { } pub fn g () { } }".to_string(),
+                "mod b { }".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn matches_fragment_kinds() {
+        let definitions = "macro_rules! m {\n\
+                               ($v:vis fn $f:ident<$l:lifetime>($p:pat: $t:ty) -> $r:path $b:block) => {\n\
+                                   $v fn $f<$l>($p: $t) -> $r $b\n\
+                               };\n\
+                               ($e:expr, $lit:literal, $($tt:tt)*) => { const C: i32 = $e * $lit; $($tt)* };\n\
+                               (#[$meta:meta] $i:item) => { #[$meta] $i };\n\
+                           }";
+        assert_eq!(
+            expand(
+                definitions,
+                "m!(pub(crate) fn f<'a>(x: &'a u8) -> std::primitive::u8 { *x });"
+            ),
+            Some(vec![
+                "pub (crate) fn f < 'a > (x : & 'a u8) -> std :: primitive :: u8 { * x }"
+                    .to_string()
+            ])
+        );
+        // Expressions are substituted as a whole
+        assert_eq!(
+            expand(definitions, "m!(1 + 2, -3, struct S;);"),
+            Some(vec![
+                "const C : i32 = 1 + 2 * - 3 ;".to_string(),
+                "struct S ;".to_string()
+            ])
+        );
+        assert_eq!(
+            expand(definitions, "m!(#[inline] fn f() {});"),
+            Some(vec!["# [inline] fn f () { }".to_string()])
+        );
+        assert_eq!(expand(definitions, "m!(fn f<'a>(x: u8) -> u8);"), None);
+    }
+
+    #[test]
+    fn matches_long_repetitions() {
+        let definitions = "macro_rules! m { ($($f:ident)*) => { $(fn $f() {})* }; }";
+        let idents: Vec<String> = (0..MAX_ALTERNATIVES + 100)
+            .map(|i| format!("f{}", i))
+            .collect();
+        let invocation = format!("m!({});", idents.join(" "));
+        assert_eq!(
+            expand(definitions, &invocation).map(|items| items.len()),
+            Some(MAX_ALTERNATIVES + 100)
+        );
+
+        let definitions =
+            "macro_rules! m { ($($f:ident),* ; $last:ident) => { $(fn $f() {})* fn $last() {} }; }";
+        let invocation = format!("m!({} ; last);", idents.join(", "));
+        assert_eq!(
+            expand(definitions, &invocation).map(|items| items.len()),
+            Some(MAX_ALTERNATIVES + 101)
+        );
+    }
+
+    #[test]
+    fn bounds_ambiguous_repetitions() {
+        let definitions =
+            "macro_rules! m { ($($($a:tt)*)* ;) => {}; ($($t:tt)*) => { fn fallback() {} }; }";
+        let invocation = format!("m!({});", vec!["x"; 40].join(" "));
+        assert_eq!(
+            expand(definitions, &invocation),
+            Some(vec!["fn fallback () { }".to_string()])
+        );
+    }
+
+    #[test]
+    fn expands_recursively_up_to_the_recursion_limit() {
+        let definitions = "macro_rules! exports {\n\
+                               () => {};\n\
+                               ($f:ident $($rest:ident)*) => {\n\
+                                   #[no_mangle] pub extern \"C\" fn $f() {}\n\
+                                   exports!($($rest)*);\n\
+                               };\n\
+                           }\n\
+                           macro_rules! forever { () => { forever!(); }; }\n";
+        let source = format!("{}exports!(a b c);\nforever!();\n", definitions);
+        assert_eq!(symbols(vec![("src/lib.rs", &source)]), vec!["a", "b", "c"]);
+
+        let idents: Vec<String> = (0..130).map(|i| format!("f{:03}", i)).collect();
+        let source = format!("{}exports!({});\n", definitions, idents.join(" "));
+        assert_eq!(
+            symbols(vec![("src/lib.rs", &source)]),
+            idents[..128].to_vec()
+        );
+    }
+
+    #[test]
+    fn expands_macros_defined_in_other_files() {
+        let sources = vec![
+            ("src/lib.rs", "#[macro_use] mod macros;\nmod ffi;\n"),
+            (
+                "src/macros.rs",
+                "macro_rules! export { ($f:ident) => { #[no_mangle] pub extern \"C\" fn $f() {} }; }\n",
+            ),
+            ("src/ffi.rs", "export!(init);\nexport!(shutdown);\n"),
+        ];
+        assert_eq!(symbols(sources), vec!["init", "shutdown"]);
+    }
+}
@@ -1,7 +1,158 @@
-use no_mangle_pub_export_c_fn::{parse_for_no_mangle_pub_extern_c_fns, ParsedFile};
+use no_mangle_pub_export_c_fn::{
+    parse_for_no_mangle_pub_extern_c_fns, BuildScriptOutputs, ParsedFile,
+};
+
+const USAGE: &str = "\
+Usage:
+    no_mangle_pub_export_c_fn [CRATE_ROOT]
+        Prints the exports of the crate.
+    no_mangle_pub_export_c_fn watch [CRATE_ROOT] [OPTIONS]
+        Rewrites the outputs whenever the sources change and prints the added (+), removed (-)
+        and changed (~) exports. Requires the `watch` feature.
+
+CRATE_ROOT defaults to CARGO_MANIFEST_DIR or to the current directory.
+
+Options:
+    --out-dir DIR               The directory of the outputs, the current directory by default
+    --c-header FILE             Writes a C header
+    --cpp-header FILE           Writes a C++ header
+    --go-bindings FILE          Writes Go bindings
+    --version-script FILE       Writes a linker version script
+    --module-definition FILE    Writes a module-definition file
+";
+
+fn default_crate_root() -> String {
+    std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".to_string())
+}
+
+/// Parses the arguments of the `watch` subcommand into the crate root and the outputs.
+fn parse_watch_args(args: &[String]) -> Result<(String, BuildScriptOutputs), String> {
+    let mut crate_root = None;
+    let mut outputs = BuildScriptOutputs {
+        out_dir: Some(".".into()),
+        ..Default::default()
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            match crate_root.replace(arg.clone()) {
+                None => continue,
+                Some(_) => return Err(format!("unexpected argument `{}`", arg)),
+            }
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("`{}` requires a value", arg))?;
+        let output = match arg.as_str() {
+            "--out-dir" => &mut outputs.out_dir,
+            "--c-header" => &mut outputs.c_header,
+            "--cpp-header" => &mut outputs.cpp_header,
+            "--go-bindings" => &mut outputs.go_bindings,
+            "--version-script" => &mut outputs.version_script,
+            "--module-definition" => &mut outputs.module_definition,
+            _ => return Err(format!("unknown option `{}`", arg)),
+        };
+        *output = Some(value.into());
+    }
+    outputs.scan_options.relative_paths = true;
+    Ok((crate_root.unwrap_or_else(default_crate_root), outputs))
+}
+
+#[cfg(feature = "watch")]
+fn watch(crate_root: &str, outputs: &BuildScriptOutputs) -> std::io::Result<()> {
+    use std::io::Write;
+
+    eprintln!(
+        "Watching {}",
+        std::path::Path::new(crate_root).join("src").display()
+    );
+    no_mangle_pub_export_c_fn::watch_build_script_outputs(crate_root, outputs, |_, diff| {
+        print!("{}", diff);
+        // Shows the changes right away when the output is piped
+        let _ = std::io::stdout().flush();
+        std::ops::ControlFlow::Continue(())
+    })
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch(_crate_root: &str, _outputs: &BuildScriptOutputs) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "the `watch` subcommand requires the `watch` feature",
+    ))
+}
 
 fn main() {
-    let crate_root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
-    let parsed_files: Vec<ParsedFile> = parse_for_no_mangle_pub_extern_c_fns(crate_root.as_str());
-    println!("{:#?}", parsed_files);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("-h" | "--help") => print!("{}", USAGE),
+        Some("watch") => {
+            let result = parse_watch_args(&args[1..])
+                .map_err(|err| format!("{}\n\n{}", err, USAGE))
+                .and_then(|(crate_root, outputs)| {
+                    watch(&crate_root, &outputs).map_err(|err| err.to_string())
+                });
+            if let Err(err) = result {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        crate_root => {
+            let crate_root = crate_root.map_or_else(default_crate_root, str::to_string);
+            let parsed_files: Vec<ParsedFile> =
+                parse_for_no_mangle_pub_extern_c_fns(crate_root.as_str());
+            println!("{:#?}", parsed_files);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_watch_args() {
+        let (crate_root, outputs) = parse_watch_args(&args(&[
+            "path/to/crate",
+            "--out-dir",
+            "include",
+            "--c-header",
+            "bindings.h",
+            "--version-script",
+            "exports.map",
+        ]))
+        .unwrap();
+        assert_eq!(crate_root, "path/to/crate");
+        assert_eq!(outputs.out_dir, Some("include".into()));
+        assert_eq!(outputs.c_header, Some("bindings.h".into()));
+        assert_eq!(outputs.version_script, Some("exports.map".into()));
+        assert_eq!(outputs.cpp_header, None);
+        assert!(outputs.scan_options.relative_paths);
+
+        let (_, outputs) = parse_watch_args(&args(&["--cpp-header", "bindings.hpp"])).unwrap();
+        assert_eq!(outputs.out_dir, Some(".".into()));
+        assert_eq!(outputs.cpp_header, Some("bindings.hpp".into()));
+    }
+
+    #[test]
+    fn rejects_invalid_watch_args() {
+        assert_eq!(
+            parse_watch_args(&args(&["--c-header"])).err().unwrap(),
+            "`--c-header` requires a value"
+        );
+        assert_eq!(
+            parse_watch_args(&args(&["--header", "bindings.h"]))
+                .err()
+                .unwrap(),
+            "unknown option `--header`"
+        );
+        assert_eq!(
+            parse_watch_args(&args(&["a", "b"])).err().unwrap(),
+            "unexpected argument `b`"
+        );
+    }
 }
@@ -0,0 +1,338 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use syn::ext::IdentExt;
+
+use crate::cfg::{cfg_predicates, CfgPredicate};
+use crate::include::include_path;
+
+/// An out-of-line `mod name;` declaration or an `include!` invocation that pulls a file into the module tree.
+#[derive(Serialize, Deserialize, Clone)]
+struct ModDecl {
+    /// The file containing the declaration.
+    parent: PathBuf,
+    /// The `#[cfg]` predicates of the declaration itself and of the enclosing inline modules.
+    cfgs: Vec<CfgPredicate>,
+    /// The module path of the file relative to the one of `parent`, i.e. the enclosing inline modules
+    /// followed by the name of the declared module. Included files have no name of their own.
+    module_path: Vec<String>,
+}
+
+/// Relations between Rust source files established by out-of-line `mod` declarations and by
+/// `include!` invocations at item position.
+///
+/// Files are related by path, following the [rules](https://doc.rust-lang.org/reference/items/modules.html#module-source-filenames)
+/// for module source filenames, including `#[path]` attributes.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub(crate) struct ModuleTree {
+    mod_decls: BTreeMap<PathBuf, ModDecl>,
+    /// The files pulled in by `include!`, which may be outside of `src`, e.g. in `OUT_DIR`.
+    included: BTreeSet<PathBuf>,
+}
+
+fn path_attr(attrs: &[syn::Attribute]) -> Option<String> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("path"))
+        .find_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::NameValue(syn::MetaNameValue {
+                lit: syn::Lit::Str(lit_str),
+                ..
+            })) => Some(lit_str.value()),
+            _ => None,
+        })
+}
+
+// `#[path]` attributes may go up with `..`, while the walk yields paths without them. Symlinks aren't
+// resolved, as with the paths of the walk.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    path.components().for_each(|component| match component {
+        Component::CurDir => {}
+        Component::ParentDir
+            if matches!(
+                normalized.components().next_back(),
+                Some(Component::Normal(_))
+            ) =>
+        {
+            normalized.pop();
+        }
+        component => normalized.push(component),
+    });
+    normalized
+}
+
+// Crate roots and `mod.rs` files own their directory, other files own the directory named after them
+fn is_mod_rs(path: &Path) -> bool {
+    let file_name = path.file_name().and_then(|file_name| file_name.to_str());
+    let parent_name = |path: Option<&Path>| {
+        path.and_then(Path::file_name)
+            .and_then(|file_name| file_name.to_str())
+            .map(str::to_string)
+    };
+    matches!(file_name, Some("lib.rs") | Some("main.rs") | Some("mod.rs"))
+        || (parent_name(path.parent()).as_deref() == Some("bin")
+            && parent_name(path.parent().and_then(Path::parent)).as_deref() == Some("src"))
+}
+
+/// Guesses the module path of a file from its location relative to `src`, e.g. `["a", "b"]` for both
+/// `src/a/b.rs` and `src/a/b/mod.rs`. Crate roots, including binaries in `src/bin`, have an empty module path.
+pub(crate) fn file_module_path(src_dir: &Path, path: &Path) -> Vec<String> {
+    let relative = path
+        .strip_prefix(src_dir)
+        .unwrap_or(path)
+        .with_extension("");
+    let mut segments: Vec<String> = relative
+        .components()
+        .filter_map(|component| component.as_os_str().to_str())
+        .map(str::to_string)
+        .collect();
+    if segments.first().map(String::as_str) == Some("bin") {
+        // `src/bin/name.rs` or `src/bin/name/...`
+        segments.drain(..segments.len().min(2));
+    }
+    if segments.last().map(String::as_str) == Some("mod") {
+        segments.pop();
+    }
+    if segments.len() == 1 && matches!(segments[0].as_str(), "lib" | "main") {
+        segments.clear();
+    }
+    segments
+}
+
+/// Guesses the module path of a file that no `mod` declaration leads to, e.g. a crate root, relative
+/// to the closest `src` directory it is in.
+pub(crate) fn guess_module_path(path: &Path) -> Vec<String> {
+    let src_dir = path
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| {
+            ancestor
+                .file_name()
+                .map_or(false, |file_name| file_name == "src")
+        })
+        .unwrap_or_else(|| Path::new(""));
+    file_module_path(src_dir, path)
+}
+
+/// The file whose items are being added to the module tree.
+struct AddedFile<'a> {
+    path: &'a Path,
+    file_dir: &'a Path,
+    env: &'a BTreeMap<String, String>,
+}
+
+impl ModuleTree {
+    /// Adds the out-of-line `mod` declarations and the `include!` invocations of the file. `env` is
+    /// used to evaluate `env!` in the paths of included files.
+    pub(crate) fn add_file(
+        &mut self,
+        path: &Path,
+        file: &syn::File,
+        env: &BTreeMap<String, String>,
+    ) {
+        let file_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let dir = if is_mod_rs(path) {
+            file_dir.to_path_buf()
+        } else {
+            file_dir.join(path.file_stem().unwrap_or_default())
+        };
+        let added_file = AddedFile {
+            path,
+            file_dir,
+            env,
+        };
+        self.add_items(&added_file, &dir, true, &[], &[], &file.items);
+    }
+
+    pub(crate) fn merge(&mut self, other: ModuleTree) {
+        self.mod_decls.extend(other.mod_decls);
+        self.included.extend(other.included);
+    }
+
+    /// The files pulled in by `include!`.
+    pub(crate) fn included(&self) -> &BTreeSet<PathBuf> {
+        &self.included
+    }
+
+    fn add_items(
+        &mut self,
+        added_file: &AddedFile,
+        dir: &Path,
+        is_top_level: bool,
+        cfgs: &[CfgPredicate],
+        module_path: &[String],
+        items: &[syn::Item],
+    ) {
+        let path = added_file.path;
+        items.iter().for_each(|item| {
+            let item_mod = match item {
+                syn::Item::Mod(item_mod) => item_mod,
+                syn::Item::Macro(item_macro) => {
+                    if let Some(included) = include_path(&item_macro.mac, path, added_file.env) {
+                        let mut include_cfgs = cfgs.to_vec();
+                        include_cfgs.extend(cfg_predicates(&item_macro.attrs));
+                        self.add_mod_decl(included.clone(), path, &include_cfgs, module_path);
+                        self.included.insert(normalize(&included));
+                    }
+                    return;
+                }
+                _ => return,
+            };
+            let name = item_mod.ident.unraw().to_string();
+            let mut mod_cfgs = cfgs.to_vec();
+            mod_cfgs.extend(cfg_predicates(&item_mod.attrs));
+            let mut mod_path = module_path.to_vec();
+            mod_path.push(name.clone());
+            match (&item_mod.content, path_attr(&item_mod.attrs)) {
+                (Some((_, items)), path_attr) => {
+                    let dir = dir.join(path_attr.unwrap_or(name));
+                    self.add_items(added_file, &dir, false, &mod_cfgs, &mod_path, items);
+                }
+                (None, Some(path_attr)) => {
+                    let base = if is_top_level {
+                        added_file.file_dir
+                    } else {
+                        dir
+                    };
+                    self.add_mod_decl(base.join(path_attr), path, &mod_cfgs, &mod_path);
+                }
+                (None, None) => {
+                    let mod_rs = dir.join(&name).join("mod.rs");
+                    self.add_mod_decl(dir.join(format!("{}.rs", name)), path, &mod_cfgs, &mod_path);
+                    self.add_mod_decl(mod_rs, path, &mod_cfgs, &mod_path);
+                }
+            }
+        });
+    }
+
+    fn add_mod_decl(
+        &mut self,
+        child: PathBuf,
+        parent: &Path,
+        cfgs: &[CfgPredicate],
+        module_path: &[String],
+    ) {
+        self.mod_decls.insert(
+            normalize(&child),
+            ModDecl {
+                parent: parent.to_path_buf(),
+                cfgs: cfgs.to_vec(),
+                module_path: module_path.to_vec(),
+            },
+        );
+    }
+
+    /// The `#[cfg]` predicates of the `mod` declarations leading to the file, outermost first.
+    pub(crate) fn cfgs(&self, path: &Path) -> Vec<CfgPredicate> {
+        let mut chain: Vec<&[CfgPredicate]> = Vec::new();
+        let mut current = path;
+        // The limit protects against cycles created by `#[path]` attributes
+        while let Some(mod_decl) = self.mod_decls.get(current) {
+            if chain.len() > self.mod_decls.len() {
+                break;
+            }
+            chain.push(&mod_decl.cfgs);
+            current = &mod_decl.parent;
+        }
+        chain.into_iter().rev().flatten().cloned().collect()
+    }
+
+    /// The module path of the file, e.g. `["a", "b"]` for `src/a/b.rs` declared with `mod b;` in
+    /// `src/a.rs`. The file at the top of the chain of `mod` declarations, normally a crate root,
+    /// gets a module path guessed from its location.
+    pub(crate) fn module_path(&self, path: &Path) -> Vec<String> {
+        let mut chain: Vec<&[String]> = Vec::new();
+        let mut current = path;
+        // The limit protects against cycles created by `#[path]` attributes
+        while let Some(mod_decl) = self.mod_decls.get(current) {
+            if chain.len() > self.mod_decls.len() {
+                break;
+            }
+            chain.push(&mod_decl.module_path);
+            current = &mod_decl.parent;
+        }
+        let mut module_path = guess_module_path(current);
+        module_path.extend(chain.into_iter().rev().flatten().cloned());
+        module_path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module_tree(files: &[(&str, &str)]) -> ModuleTree {
+        let mut module_tree = ModuleTree::default();
+        files.iter().for_each(|(path, source)| {
+            let file = syn::parse_file(source).unwrap();
+            module_tree.add_file(Path::new(path), &file, &BTreeMap::new());
+        });
+        module_tree
+    }
+
+    fn module_path(module_tree: &ModuleTree, path: &str) -> String {
+        module_tree.module_path(Path::new(path)).join("::")
+    }
+
+    #[test]
+    fn follows_mod_declarations() {
+        let module_tree = module_tree(&[
+            ("src/lib.rs", "mod a; mod b; mod r#type;"),
+            ("src/a.rs", "mod c; mod inline { mod d; }"),
+            ("src/b/mod.rs", "mod e;"),
+        ]);
+        assert_eq!(module_path(&module_tree, "src/lib.rs"), "");
+        assert_eq!(module_path(&module_tree, "src/a.rs"), "a");
+        assert_eq!(module_path(&module_tree, "src/a/c.rs"), "a::c");
+        assert_eq!(module_path(&module_tree, "src/a/c/mod.rs"), "a::c");
+        assert_eq!(
+            module_path(&module_tree, "src/a/inline/d.rs"),
+            "a::inline::d"
+        );
+        assert_eq!(module_path(&module_tree, "src/b/mod.rs"), "b");
+        assert_eq!(module_path(&module_tree, "src/b/e.rs"), "b::e");
+        assert_eq!(module_path(&module_tree, "src/type.rs"), "type");
+        // Not declared anywhere
+        assert_eq!(module_path(&module_tree, "src/bin/tool/x.rs"), "x");
+    }
+
+    #[test]
+    fn follows_path_attributes() {
+        let module_tree = module_tree(&[
+            ("src/lib.rs", r#"#[path = "../shared/ffi.rs"] mod ffi;"#),
+            (
+                "src/a.rs",
+                r#"#[path = "../b_impl.rs"] mod b; mod inline { #[path = "../../c.rs"] mod c; }"#,
+            ),
+        ]);
+        // Relative to the directory of the file for top level declarations...
+        assert_eq!(module_path(&module_tree, "shared/ffi.rs"), "ffi");
+        assert_eq!(module_path(&module_tree, "b_impl.rs"), "a::b");
+        // ...and to the directory of the inline module otherwise
+        assert_eq!(module_path(&module_tree, "src/c.rs"), "a::inline::c");
+        assert!(module_tree.mod_decls.keys().all(|path| path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))));
+    }
+
+    #[test]
+    fn scans_path_attributes_going_up() {
+        let parsed_files = crate::parse_sources_for_no_mangle_pub_extern_c_fns(
+            vec![
+                ("src/lib.rs", "mod a;"),
+                ("src/a/mod.rs", r#"#[path = "../b.rs"] mod b;"#),
+                ("src/b.rs", r#"#[no_mangle] pub extern "C" fn f() {}"#),
+            ],
+            &crate::ScanOptions::default(),
+        );
+        let f = &parsed_files
+            .iter()
+            .find(|parsed_file| parsed_file.path == Path::new("src/b.rs"))
+            .unwrap()
+            .no_mangle_pub_export_c_fns
+            .0[0];
+        assert_eq!(f.rust_path, "crate::a::b::f");
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::BTreeSet;
+
+/// A token relevant to [prefilter]. Comments, literals and lifetimes are skipped.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Ident(&'a str),
+    Punct(u8),
+}
+
+// Non-ASCII identifiers are rare, so every non-ASCII byte is treated as a part of an identifier
+fn is_ident_start(byte: u8) -> bool {
+    byte.is_ascii_alphabetic() || byte == b'_' || !byte.is_ascii()
+}
+
+fn is_ident_continue(byte: u8) -> bool {
+    is_ident_start(byte) || byte.is_ascii_digit()
+}
+
+/// A lexer that is only precise enough to tell identifiers and punctuation apart from comments and literals.
+struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self { source, pos: 0 }
+    }
+
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.source.as_bytes().get(self.pos + offset).copied()
+    }
+
+    fn skip_while(&mut self, predicate: impl Fn(u8) -> bool) {
+        while self.peek(0).map_or(false, &predicate) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_block_comment(&mut self) {
+        let mut depth = 0usize;
+        while let Some(byte) = self.peek(0) {
+            match (byte, self.peek(1)) {
+                (b'/', Some(b'*')) => {
+                    depth += 1;
+                    self.pos += 2;
+                }
+                (b'*', Some(b'/')) => {
+                    depth -= 1;
+                    self.pos += 2;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    // Starts at the opening quote
+    fn skip_string(&mut self) {
+        self.pos += 1;
+        while let Some(byte) = self.peek(0) {
+            self.pos += if byte == b'\\' { 2 } else { 1 };
+            if byte == b'"' {
+                return;
+            }
+        }
+    }
+
+    // Starts right after the `r` prefix
+    fn skip_raw_string(&mut self) {
+        let start = self.pos;
+        self.skip_while(|byte| byte == b'#');
+        let hashes = self.pos - start;
+        if self.peek(0) != Some(b'"') {
+            return;
+        }
+        self.pos += 1;
+        while let Some(byte) = self.peek(0) {
+            self.pos += 1;
+            if byte == b'"' && (0..hashes).all(|offset| self.peek(offset) == Some(b'#')) {
+                self.pos += hashes;
+                return;
+            }
+        }
+    }
+
+    // Starts at the quote, which either opens a char literal or starts a lifetime or a label
+    fn skip_char_or_lifetime(&mut self) {
+        self.pos += 1;
+        if self.peek(0) == Some(b'\\') {
+            self.pos += 2;
+            self.skip_while(|byte| byte != b'\'');
+            self.pos += 1;
+            return;
+        }
+        let char_len = self.source[self.pos..]
+            .chars()
+            .next()
+            .map_or(0, char::len_utf8);
+        if self.peek(char_len) == Some(b'\'') {
+            self.pos += char_len + 1;
+        } else {
+            self.skip_while(is_ident_continue);
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            let byte = self.peek(0)?;
+            match (byte, self.peek(1)) {
+                (byte, _) if byte.is_ascii_whitespace() => self.pos += 1,
+                (b'/', Some(b'/')) => self.skip_while(|byte| byte != b'\n'),
+                (b'/', Some(b'*')) => self.skip_block_comment(),
+                (b'"', _) => self.skip_string(),
+                (b'\'', _) => self.skip_char_or_lifetime(),
+                // Numbers, including suffixes, e.g. `1u8` or `0x_ff`
+                (byte, _) if byte.is_ascii_digit() => self.skip_while(is_ident_continue),
+                (byte, _) if is_ident_start(byte) => {
+                    let start = self.pos;
+                    self.skip_while(is_ident_continue);
+                    let ident = &self.source[start..self.pos];
+                    match (ident, self.peek(0), self.peek(1)) {
+                        // Raw identifiers, e.g. `r#type`
+                        ("r", Some(b'#'), Some(next)) if is_ident_start(next) => {
+                            self.pos += 1;
+                            let start = self.pos;
+                            self.skip_while(is_ident_continue);
+                            return Some(Token::Ident(&self.source[start..self.pos]));
+                        }
+                        ("r" | "br" | "cr", Some(b'"' | b'#'), _) => self.skip_raw_string(),
+                        ("b" | "c", Some(b'"'), _) => self.skip_string(),
+                        _ => return Some(Token::Ident(ident)),
+                    }
+                }
+                (byte, _) => {
+                    self.pos += 1;
+                    return Some(Token::Punct(byte));
+                }
+            }
+        }
+    }
+}
+
+/// What [prefilter] found out about a file without parsing it.
+#[derive(Debug, Default)]
+pub(crate) struct PrefilterSummary {
+    /// Whether the file has to be parsed regardless of the other files, i.e. whether it mentions
+    /// `no_mangle` or `export_name`, defines or re-exports types, declares out-of-line modules, defines
+    /// `macro_rules!` macros, includes other files or has `#![cfg]` attributes.
+    pub(crate) must_parse: bool,
+    /// The names of the macros the file invokes. The file has to be parsed if any of them is a
+    /// crate-local `macro_rules!` macro.
+    pub(crate) invoked_macros: BTreeSet<String>,
+}
+
+/// The index of the token following the visibility that starts with the `pub` at `tokens[i]`, e.g.
+/// `pub(in crate::a)`.
+fn after_visibility(tokens: &[Token], i: usize) -> usize {
+    let mut end = i + 1;
+    if tokens.get(end) != Some(&Token::Punct(b'(')) {
+        return end;
+    }
+    let mut depth = 0usize;
+    for token in &tokens[end..] {
+        end += 1;
+        match token {
+            Token::Punct(b'(') => depth += 1,
+            Token::Punct(b')') => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    end
+}
+
+/// Scans the tokens of the source to tell whether it may contain `#[no_mangle] pub export "C"` functions
+/// or types, or affect the scan of other files. False positives are fine, false negatives are not.
+pub(crate) fn prefilter(source: &str) -> PrefilterSummary {
+    let tokens: Vec<Token> = Lexer::new(source).collect();
+    let mut summary = PrefilterSummary::default();
+    for (i, token) in tokens.iter().enumerate() {
+        let next = |offset: usize| tokens.get(i + offset).copied();
+        summary.must_parse = match (*token, next(1), next(2), next(3)) {
+            (Token::Ident("no_mangle" | "export_name"), ..) => true,
+            (Token::Ident("struct" | "enum" | "union" | "type"), Some(Token::Ident(_)), ..) => true,
+            // Re-exports, e.g. `pub use` and `pub(in crate::a) use`, are needed to resolve types in other files
+            (Token::Ident("pub"), ..) => {
+                tokens.get(after_visibility(&tokens, i)) == Some(&Token::Ident("use"))
+            }
+            (Token::Ident("mod"), Some(Token::Ident(_)), Some(Token::Punct(b';')), _) => true,
+            (Token::Ident("macro_rules" | "include"), Some(Token::Punct(b'!')), ..) => true,
+            (
+                Token::Punct(b'#'),
+                Some(Token::Punct(b'!')),
+                Some(Token::Punct(b'[')),
+                Some(Token::Ident("cfg" | "cfg_attr")),
+            ) => true,
+            (Token::Ident(name), Some(Token::Punct(b'!')), next, _)
+                if next != Some(Token::Punct(b'=')) =>
+            {
+                summary.invoked_macros.insert(name.to_string());
+                false
+            }
+            _ => false,
+        };
+        if summary.must_parse {
+            break;
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_sources_for_no_mangle_pub_extern_c_fns, ParsedFile, ScanOptions};
+
+    fn scan(sources: &[(&str, &str)], prefilter: bool) -> Vec<ParsedFile> {
+        let options = ScanOptions {
+            prefilter,
+            ..Default::default()
+        };
+        parse_sources_for_no_mangle_pub_extern_c_fns(sources.to_vec(), &options)
+    }
+
+    fn assert_equivalent(sources: &[(&str, &str)]) -> Vec<ParsedFile> {
+        let parsed_files = scan(sources, true);
+        assert_eq!(
+            serde_json::to_string(&parsed_files).unwrap(),
+            serde_json::to_string(&scan(sources, false)).unwrap(),
+        );
+        parsed_files
+    }
+
+    fn symbols(parsed_files: &[ParsedFile]) -> Vec<&str> {
+        let mut symbols: Vec<&str> = parsed_files
+            .iter()
+            .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+            .map(|no_mangle_pub_export_c_fn| no_mangle_pub_export_c_fn.symbol.as_str())
+            .collect();
+        symbols.sort_unstable();
+        symbols
+    }
+
+    #[test]
+    fn skips_literals_and_comments() {
+        let export = "\n#[no_mangle] pub extern \"C\" fn f() {}\n";
+        let prefixes = [
+            "const S: &str = r#\"\" \"#;",
+            "const S: &str = r##\"\"# \"##;",
+            "const S: &[u8] = br\"\\\";",
+            "const S: &[u8] = b\"\\\"\";",
+            "const S: &str = \"\\\\\";",
+            "const C: u8 = b'\"';",
+            "const C: char = '\"';",
+            "const C: char = '\\'';",
+            "const C: char = '\\u{22}';",
+            "fn g<'a>(x: &'a str) -> char { 'b: loop { break 'b '\"'; } }",
+            "/* outer /* inner */ \" */",
+            "/** doc /* \" */ */",
+            "// \"",
+            "const R: u8 = r#as;",
+        ];
+        prefixes.iter().for_each(|prefix| {
+            let source = format!("{}{}", prefix, export);
+            assert!(prefilter(&source).must_parse, "{}", source);
+            let parsed_files = assert_equivalent(&[("src/lib.rs", &source)]);
+            assert_eq!(symbols(&parsed_files), vec!["f"], "{}", source);
+        });
+    }
+
+    #[test]
+    fn skips_files_without_exports() {
+        let sources = [
+            "fn f() -> &'static str { \"no_mangle\" }",
+            "// #[no_mangle]\nfn f() {}",
+            "/* /* */ export_name */ fn f() {}",
+            "const S: &str = r#\"#[no_mangle] pub extern \"C\" fn f() {}\"#;",
+            "use a::B;\nuse crate::c::*;",
+            "pub(crate) fn f() {}\npub(in crate::a) const C: u8 = 1;",
+            "println!(\"{}\", 1);",
+        ];
+        sources.iter().for_each(|source| {
+            assert!(!prefilter(source).must_parse, "{}", source);
+        });
+        assert_eq!(
+            prefilter("println!(\"{}\", 1); vec![]; x != y;").invoked_macros,
+            ["println", "vec"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        );
+    }
+
+    #[test]
+    fn keeps_re_exports_with_every_visibility() {
+        let visibilities = [
+            "pub",
+            "pub(crate)",
+            "pub(self)",
+            "pub(super)",
+            "pub(in crate)",
+            "pub(in crate::a)",
+            "pub(in super::super)",
+            "pub ( in crate :: a )",
+        ];
+        visibilities.iter().for_each(|visibility| {
+            let source = format!("{} use crate::e::Real as G;", visibility);
+            assert!(prefilter(&source).must_parse, "{}", source);
+
+            let sources = [
+                ("src/lib.rs", "mod a;\nmod e;\n"),
+                ("src/e.rs", "pub struct Real(f64);\n"),
+                ("src/a.rs", "mod f;\nmod g;\n"),
+                ("src/a/g.rs", source.as_str()),
+                (
+                    "src/a/f.rs",
+                    "use super::g::G;\n#[no_mangle] pub extern \"C\" fn f(g: *const G) {}\n",
+                ),
+            ];
+            let parsed_files = assert_equivalent(&sources);
+            let resolved_ty = parsed_files
+                .iter()
+                .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+                .find(|no_mangle_pub_export_c_fn| no_mangle_pub_export_c_fn.symbol == "f")
+                .and_then(|no_mangle_pub_export_c_fn| {
+                    no_mangle_pub_export_c_fn.inputs[0].resolved_ty.clone()
+                });
+            assert_eq!(
+                resolved_ty.as_deref(),
+                Some("* const crate :: e :: Real"),
+                "{}",
+                visibility
+            );
+        });
+    }
+}
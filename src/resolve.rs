@@ -0,0 +1,508 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use quote::ToTokens;
+use serde::{Deserialize, Serialize};
+use syn::ext::IdentExt;
+
+use crate::cfg::{self, CfgPredicate, CfgSet};
+use crate::{rust_path, NoManglePubExportCFn};
+
+/// A single import of a `use` declaration, e.g. `use a::{b, c as d, e::*};` has three of them.
+#[derive(Serialize, Deserialize, Clone)]
+enum Import {
+    /// The name under which the item is imported and the path of the item.
+    Named(String, Vec<String>),
+    /// The path of the module whose items are imported.
+    Glob(Vec<String>),
+}
+
+fn flatten_use_tree(use_tree: &syn::UseTree, prefix: &mut Vec<String>, imports: &mut Vec<Import>) {
+    match use_tree {
+        syn::UseTree::Path(use_path) => {
+            prefix.push(use_path.ident.unraw().to_string());
+            flatten_use_tree(&use_path.tree, prefix, imports);
+            prefix.pop();
+        }
+        syn::UseTree::Name(use_name) => {
+            let name = use_name.ident.unraw().to_string();
+            match name.as_str() {
+                // `use a::{self, b};` imports `a` itself
+                "self" => imports.extend(
+                    prefix
+                        .last()
+                        .map(|module| Import::Named(module.clone(), prefix.clone())),
+                ),
+                _ => imports.push(Import::Named(name.clone(), [&prefix[..], &[name]].concat())),
+            }
+        }
+        syn::UseTree::Rename(use_rename) => {
+            let rename = use_rename.rename.unraw().to_string();
+            let name = use_rename.ident.unraw().to_string();
+            let path = match name.as_str() {
+                "self" => prefix.clone(),
+                _ => [&prefix[..], &[name]].concat(),
+            };
+            // `use a::Trait as _;` only brings the methods of the trait into scope
+            if rename != "_" {
+                imports.push(Import::Named(rename, path));
+            }
+        }
+        syn::UseTree::Glob(_) => imports.push(Import::Glob(prefix.clone())),
+        syn::UseTree::Group(use_group) => use_group
+            .items
+            .iter()
+            .for_each(|use_tree| flatten_use_tree(use_tree, prefix, imports)),
+    }
+}
+
+/// The types defined in a module and its imports, i.e. what is needed to resolve the types in it.
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct ModuleScope {
+    /// Type aliases map to the aliased type as a token string, other types to [None]. Generic type
+    /// aliases aren't expanded, so they map to [None] as well.
+    types: BTreeMap<String, Option<String>>,
+    imports: Vec<Import>,
+}
+
+/// Module paths joined with `::`, since map keys have to be strings to be serialized as JSON.
+fn module_key(module_path: &[String]) -> String {
+    module_path.join("::")
+}
+
+/// The scopes of the modules defined in a file, i.e. the file itself and its inline modules, by module
+/// path relative to the file.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub(crate) struct FileScopes(Vec<(Vec<String>, ModuleScope)>);
+
+impl FileScopes {
+    /// Items disabled by `cfg_set` are left out.
+    pub(crate) fn new(items: &[syn::Item], cfg_set: Option<&CfgSet>) -> Self {
+        let mut file_scopes = Self::default();
+        file_scopes.add_module(&mut Vec::new(), items, cfg_set);
+        file_scopes
+    }
+
+    fn add_module(
+        &mut self,
+        module_path: &mut Vec<String>,
+        items: &[syn::Item],
+        cfg_set: Option<&CfgSet>,
+    ) {
+        let mut scope = ModuleScope::default();
+        items
+            .iter()
+            .filter(|item| {
+                let cfgs: Vec<CfgPredicate> = cfg::cfg_predicates(cfg::item_attrs(item));
+                cfg::is_cfg_enabled(cfg_set, &cfgs)
+            })
+            .for_each(|item| match item {
+                syn::Item::Struct(syn::ItemStruct { ident, .. })
+                | syn::Item::Enum(syn::ItemEnum { ident, .. })
+                | syn::Item::Union(syn::ItemUnion { ident, .. }) => {
+                    scope.types.insert(ident.unraw().to_string(), None);
+                }
+                syn::Item::Type(item_type) => {
+                    let aliased = item_type
+                        .generics
+                        .params
+                        .is_empty()
+                        .then(|| item_type.ty.to_token_stream().to_string());
+                    scope
+                        .types
+                        .insert(item_type.ident.unraw().to_string(), aliased);
+                }
+                // Paths starting with `::` refer to other crates
+                syn::Item::Use(item_use) if item_use.leading_colon.is_none() => {
+                    flatten_use_tree(&item_use.tree, &mut Vec::new(), &mut scope.imports)
+                }
+                syn::Item::Mod(syn::ItemMod {
+                    ident,
+                    content: Some((_, items)),
+                    ..
+                }) => {
+                    module_path.push(ident.unraw().to_string());
+                    self.add_module(module_path, items, cfg_set);
+                    module_path.pop();
+                }
+                _ => {}
+            });
+        self.0.push((module_path.clone(), scope));
+    }
+}
+
+/// What a path resolves to.
+enum Resolution {
+    Module(Vec<String>),
+    /// The module path of the definition and the ident of the type.
+    Type(Vec<String>, String),
+}
+
+/// Resolves the types in signatures through `use` declarations, including `pub use` re-exports, and
+/// type aliases within the crate.
+///
+/// Paths are resolved the way the 2018 edition does, i.e. relative to the current module unless they
+/// start with `crate`, `self` or `super`. Paths to other crates, e.g. `libc::c_int`, can't be resolved
+/// and are kept as is. Items generated by macros aren't known.
+#[derive(Serialize, Default)]
+pub(crate) struct TypeResolver {
+    modules: BTreeMap<String, ModuleScope>,
+}
+
+impl TypeResolver {
+    /// Cycles of type aliases end here.
+    const MAX_DEPTH: usize = 32;
+
+    /// Adds the modules defined in the file whose module path is `module_path`.
+    pub(crate) fn add_file(&mut self, module_path: &[String], file_scopes: FileScopes) {
+        file_scopes
+            .0
+            .into_iter()
+            .for_each(|(relative_module_path, scope)| {
+                let module = self
+                    .modules
+                    .entry(module_key(&[module_path, &relative_module_path].concat()))
+                    .or_default();
+                module.types.extend(scope.types);
+                module.imports.extend(scope.imports);
+            });
+    }
+
+    /// Sets [FnArg::resolved_ty](crate::FnArg::resolved_ty) and [NoManglePubExportCFn::resolved_output]
+    /// for the signature of a function in the module given by [NoManglePubExportCFn::module_path].
+    pub(crate) fn resolve_signature(&self, no_mangle_pub_export_c_fn: &mut NoManglePubExportCFn) {
+        let module_path = &no_mangle_pub_export_c_fn.module_path;
+        no_mangle_pub_export_c_fn
+            .inputs
+            .iter_mut()
+            .for_each(|fn_arg| fn_arg.resolved_ty = self.resolve(module_path, &fn_arg.ty));
+        no_mangle_pub_export_c_fn.resolved_output = no_mangle_pub_export_c_fn
+            .output
+            .as_ref()
+            .and_then(|output| self.resolve(module_path, output));
+    }
+
+    fn resolve(&self, module_path: &[String], ty: &str) -> Option<String> {
+        let mut ty: syn::Type = syn::parse_str(ty).ok()?;
+        self.resolve_type(module_path, &mut ty, 0);
+        Some(ty.to_token_stream().to_string())
+    }
+
+    fn resolve_type(&self, module_path: &[String], ty: &mut syn::Type, depth: usize) {
+        match ty {
+            syn::Type::Paren(type_paren) => {
+                self.resolve_type(module_path, &mut type_paren.elem, depth)
+            }
+            syn::Type::Group(type_group) => {
+                self.resolve_type(module_path, &mut type_group.elem, depth)
+            }
+            syn::Type::Ptr(type_ptr) => self.resolve_type(module_path, &mut type_ptr.elem, depth),
+            syn::Type::Reference(type_reference) => {
+                self.resolve_type(module_path, &mut type_reference.elem, depth)
+            }
+            syn::Type::Array(type_array) => {
+                self.resolve_type(module_path, &mut type_array.elem, depth)
+            }
+            syn::Type::Slice(type_slice) => {
+                self.resolve_type(module_path, &mut type_slice.elem, depth)
+            }
+            syn::Type::Tuple(type_tuple) => type_tuple
+                .elems
+                .iter_mut()
+                .for_each(|elem| self.resolve_type(module_path, elem, depth)),
+            syn::Type::BareFn(type_bare_fn) => {
+                type_bare_fn.inputs.iter_mut().for_each(|bare_fn_arg| {
+                    self.resolve_type(module_path, &mut bare_fn_arg.ty, depth)
+                });
+                if let syn::ReturnType::Type(_, output) = &mut type_bare_fn.output {
+                    self.resolve_type(module_path, output, depth);
+                }
+            }
+            syn::Type::Path(type_path) if type_path.qself.is_none() => {
+                type_path.path.segments.iter_mut().for_each(|segment| {
+                    if let syn::PathArguments::AngleBracketed(arguments) = &mut segment.arguments {
+                        arguments.args.iter_mut().for_each(|argument| {
+                            if let syn::GenericArgument::Type(argument) = argument {
+                                self.resolve_type(module_path, argument, depth)
+                            }
+                        });
+                    }
+                });
+                if type_path.path.leading_colon.is_some() {
+                    return;
+                }
+                let segments: Vec<String> = type_path
+                    .path
+                    .segments
+                    .iter()
+                    .map(|segment| segment.ident.unraw().to_string())
+                    .collect();
+                let (definition_module_path, ident) =
+                    match self.resolve_path(module_path, &segments, &mut BTreeSet::new()) {
+                        Some(Resolution::Type(definition_module_path, ident)) => {
+                            (definition_module_path, ident)
+                        }
+                        _ => return,
+                    };
+                let aliased = self
+                    .modules
+                    .get(&module_key(&definition_module_path))
+                    .and_then(|scope| scope.types.get(&ident))
+                    .cloned()
+                    .flatten()
+                    .filter(|_| depth < Self::MAX_DEPTH);
+                match aliased.and_then(|aliased| syn::parse_str::<syn::Type>(&aliased).ok()) {
+                    Some(mut aliased) => {
+                        // The aliased type is resolved where the alias is defined
+                        self.resolve_type(&definition_module_path, &mut aliased, depth + 1);
+                        *ty = aliased;
+                    }
+                    None => {
+                        let arguments = type_path
+                            .path
+                            .segments
+                            .last()
+                            .map(|segment| segment.arguments.clone());
+                        if let Ok(mut path) =
+                            syn::parse_str::<syn::Path>(&rust_path(&definition_module_path, &ident))
+                        {
+                            if let Some((segment, arguments)) =
+                                path.segments.last_mut().zip(arguments)
+                            {
+                                segment.arguments = arguments;
+                            }
+                            type_path.path = path;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `visited` holds the module keys and the names that have been looked up, which ends cycles of
+    /// imports and keeps chains of glob imports from being walked more than once.
+    fn resolve_path(
+        &self,
+        module_path: &[String],
+        segments: &[String],
+        visited: &mut BTreeSet<(String, String)>,
+    ) -> Option<Resolution> {
+        let (first, rest) = segments.split_first()?;
+        let mut resolution = match first.as_str() {
+            "crate" => Resolution::Module(Vec::new()),
+            "self" => Resolution::Module(module_path.to_vec()),
+            "super" => Resolution::Module(module_path.split_last()?.1.to_vec()),
+            name => self.lookup(module_path, name, visited)?,
+        };
+        for segment in rest {
+            resolution = match (resolution, segment.as_str()) {
+                (Resolution::Module(module_path), "super") => {
+                    Resolution::Module(module_path.split_last()?.1.to_vec())
+                }
+                (Resolution::Module(module_path), name) => {
+                    self.lookup(&module_path, name, visited)?
+                }
+                // Associated types aren't resolved
+                (Resolution::Type(..), _) => return None,
+            };
+        }
+        Some(resolution)
+    }
+
+    /// Looks `name` up among the types, the child modules, the imports and the glob imports of the
+    /// module, in that order.
+    fn lookup(
+        &self,
+        module_path: &[String],
+        name: &str,
+        visited: &mut BTreeSet<(String, String)>,
+    ) -> Option<Resolution> {
+        if !visited.insert((module_key(module_path), name.to_string())) {
+            return None;
+        }
+        let scope = self.modules.get(&module_key(module_path))?;
+        if scope.types.contains_key(name) {
+            return Some(Resolution::Type(module_path.to_vec(), name.to_string()));
+        }
+        let child_module_path = [module_path, &[name.to_string()]].concat();
+        if self.modules.contains_key(&module_key(&child_module_path)) {
+            return Some(Resolution::Module(child_module_path));
+        }
+        let imported = scope.imports.iter().find_map(|import| match import {
+            Import::Named(imported_name, path) if imported_name == name => Some(path),
+            _ => None,
+        });
+        if let Some(path) = imported {
+            return self.resolve_path(module_path, path, visited);
+        }
+        scope
+            .imports
+            .iter()
+            .filter_map(|import| match import {
+                Import::Glob(path) => Some(path),
+                Import::Named(..) => None,
+            })
+            .find_map(
+                |path| match self.resolve_path(module_path, path, visited)? {
+                    Resolution::Module(glob_module_path) => {
+                        self.lookup(&glob_module_path, name, visited)
+                    }
+                    Resolution::Type(..) => None,
+                },
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cfg::CfgSet;
+    use crate::ScanOptions;
+
+    /// The resolved signatures by symbol, with the inputs and the output separated by `->`.
+    fn resolved_signatures(sources: &[(&str, &str)], options: &ScanOptions) -> Vec<String> {
+        let mut signatures: Vec<String> =
+            crate::parse_sources_for_no_mangle_pub_extern_c_fns(sources.to_vec(), options)
+                .iter()
+                .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0.iter())
+                .map(|no_mangle_pub_export_c_fn| {
+                    let inputs: Vec<&str> = no_mangle_pub_export_c_fn
+                        .inputs
+                        .iter()
+                        .map(|fn_arg| fn_arg.resolved_ty.as_deref().unwrap())
+                        .collect();
+                    format!(
+                        "{}({}) -> {}",
+                        no_mangle_pub_export_c_fn.symbol,
+                        inputs.join(", "),
+                        no_mangle_pub_export_c_fn
+                            .resolved_output
+                            .as_deref()
+                            .unwrap_or("()")
+                    )
+                })
+                .collect();
+        signatures.sort();
+        signatures
+    }
+
+    #[test]
+    fn resolves_imports_and_aliases() {
+        let signatures = resolved_signatures(
+            &[
+                (
+                    "src/lib.rs",
+                    r#"
+                    mod ffi;
+                    pub mod types;
+                    pub use types::Engine as PublicEngine;
+                    "#,
+                ),
+                (
+                    "src/types.rs",
+                    r#"
+                    pub struct Engine;
+                    pub type Len = Size;
+                    type Size = usize;
+                    pub type Buffer = *const Byte;
+                    pub struct Byte;
+                    pub mod status {
+                        pub enum Status { Ok }
+                    }
+                    "#,
+                ),
+                (
+                    "src/ffi.rs",
+                    r#"
+                    use crate::types::{self, status::*, Buffer as Buf};
+                    use super::PublicEngine;
+                    use libc::c_int;
+
+                    #[no_mangle]
+                    pub extern "C" fn a(engine: *mut PublicEngine, len: types::Len) -> Status {}
+                    #[no_mangle]
+                    pub extern "C" fn b(buffer: Buf, n: c_int, m: ::libc::c_int) -> Option<&'static types::Engine> {}
+                    #[no_mangle]
+                    pub extern "C" fn c(cb: extern "C" fn(Buf) -> crate::types::Len, pair: [self::Unknown; 2]) {}
+                    "#,
+                ),
+            ],
+            &ScanOptions::default(),
+        );
+        assert_eq!(
+            signatures,
+            [
+                "a(* mut crate :: types :: Engine, usize) -> crate :: types :: status :: Status",
+                "b(* const crate :: types :: Byte, c_int, :: libc :: c_int) -> Option < & 'static crate :: types :: Engine >",
+                "c(extern \"C\" fn (* const crate :: types :: Byte) -> usize, [self :: Unknown ; 2]) -> ()",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_inline_modules_and_super() {
+        let signatures = resolved_signatures(
+            &[(
+                "src/lib.rs",
+                r#"
+                pub struct Handle;
+                mod outer {
+                    pub type Id = u64;
+                    pub mod inner {
+                        use super::super::Handle;
+                        #[no_mangle]
+                        pub extern "C" fn f(handle: *const Handle, id: super::Id) {}
+                    }
+                }
+                "#,
+            )],
+            &ScanOptions::default(),
+        );
+        assert_eq!(signatures, ["f(* const crate :: Handle, u64) -> ()"]);
+    }
+
+    #[test]
+    fn stops_at_cycles() {
+        let signatures = resolved_signatures(
+            &[(
+                "src/lib.rs",
+                r#"
+                mod a { pub use crate::b::*; pub type X = crate::b::Y; }
+                mod b { pub use crate::a::*; pub type Y = crate::a::X; }
+                use a::Missing;
+                #[no_mangle]
+                pub extern "C" fn f(x: a::X, missing: Missing) {}
+                "#,
+            )],
+            &ScanOptions::default(),
+        );
+        assert_eq!(signatures.len(), 1);
+        assert!(signatures[0].ends_with(", Missing) -> ()"));
+    }
+
+    #[test]
+    fn leaves_out_disabled_items() {
+        let sources = [(
+            "src/lib.rs",
+            r#"
+            #[cfg(windows)]
+            type Char = u16;
+            #[cfg(not(windows))]
+            type Char = u8;
+            #[no_mangle]
+            pub extern "C" fn f(c: Char) {}
+            "#,
+        )];
+        let mut cfg_set = CfgSet::default();
+        cfg_set.insert_cfg("windows");
+        let options = ScanOptions {
+            cfg_set: Some(cfg_set),
+            ..Default::default()
+        };
+        assert_eq!(resolved_signatures(&sources, &options), ["f(u16) -> ()"]);
+        let options = ScanOptions {
+            cfg_set: Some(CfgSet::default()),
+            ..Default::default()
+        };
+        assert_eq!(resolved_signatures(&sources, &options), ["f(u8) -> ()"]);
+    }
+}
@@ -0,0 +1,857 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Read;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+
+use serde::{Deserialize, Serialize};
+use syn::visit::Visit;
+
+use crate::cache::{sha256_hex, Cache, CacheEntry};
+use crate::cfg::{self, CfgPredicate};
+use crate::macro_expansion::{macro_rules_definitions, MacroRulesDefinition, MacroRulesMap};
+use crate::module_tree::ModuleTree;
+use crate::prefilter::prefilter;
+use crate::resolve::{FileScopes, TypeResolver};
+use crate::{NoManglePubExportCFns, NoManglePubExportCFnsVisitor, ParsedFile, ScanOptions};
+
+/// What a single file contributes to the scan of the other files.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub(crate) struct FileContribution {
+    /// The out-of-line `mod` declarations of the file.
+    module_tree: ModuleTree,
+    /// The `#![cfg]` predicates of the file.
+    cfgs: Vec<CfgPredicate>,
+    macro_rules_definitions: Vec<MacroRulesDefinition>,
+    /// The types and imports of the modules defined in the file.
+    type_scopes: FileScopes,
+}
+
+impl FileContribution {
+    fn new(path: &Path, file: &FileState, options: &ScanOptions) -> Self {
+        let file = match file {
+            FileState::Parsed { file, .. } => file,
+            _ => return Self::default(),
+        };
+        let mut contribution = Self {
+            cfgs: cfg::cfg_predicates(&file.attrs),
+            ..Self::default()
+        };
+        contribution.module_tree.add_file(path, file, &options.env);
+        contribution.type_scopes = FileScopes::new(&file.items, options.cfg_set.as_ref());
+        if options.expand_macro_rules {
+            macro_rules_definitions(
+                &file.items,
+                options.cfg_set.as_ref(),
+                &mut contribution.macro_rules_definitions,
+            );
+        }
+        contribution
+    }
+}
+
+/// What the files parsed by a single worker contribute to the scan of the other files.
+#[derive(Default)]
+struct CrateWideInfo {
+    module_tree: ModuleTree,
+    /// The definitions of each file along with the `#![cfg]` predicates of the file.
+    macro_rules_definitions: Vec<(PathBuf, Vec<CfgPredicate>, Vec<MacroRulesDefinition>)>,
+    /// The type scopes of each file along with the `#![cfg]` predicates of the file.
+    type_scopes: Vec<(PathBuf, Vec<CfgPredicate>, FileScopes)>,
+}
+
+impl CrateWideInfo {
+    fn add(&mut self, path: &Path, contribution: FileContribution) {
+        self.module_tree.merge(contribution.module_tree);
+        if !contribution.macro_rules_definitions.is_empty() {
+            self.macro_rules_definitions.push((
+                path.to_path_buf(),
+                contribution.cfgs.clone(),
+                contribution.macro_rules_definitions,
+            ));
+        }
+        self.type_scopes.push((
+            path.to_path_buf(),
+            contribution.cfgs,
+            contribution.type_scopes,
+        ));
+    }
+
+    fn merge(&mut self, other: CrateWideInfo) {
+        self.module_tree.merge(other.module_tree);
+        self.macro_rules_definitions
+            .extend(other.macro_rules_definitions);
+        self.type_scopes.extend(other.type_scopes);
+    }
+}
+
+/// What every worker needs to know once all files have been parsed.
+#[derive(Serialize)]
+struct ScanContext {
+    module_tree: ModuleTree,
+    macro_rules_definitions: Vec<MacroRulesDefinition>,
+    type_resolver: TypeResolver,
+    /// The digest of the above. Cached results computed with a different digest are outdated.
+    #[serde(skip)]
+    digest: String,
+}
+
+impl ScanContext {
+    fn new(crate_wide_info: CrateWideInfo, options: &ScanOptions, is_cached: bool) -> Self {
+        let module_tree = crate_wide_info.module_tree;
+        let is_file_enabled = |path: &Path, inner_cfgs: &[CfgPredicate]| {
+            let mut cfgs = module_tree.cfgs(path);
+            cfgs.extend(inner_cfgs.iter().cloned());
+            cfg::is_cfg_enabled(options.cfg_set.as_ref(), &cfgs)
+        };
+        // Workers report in arbitrary order, while the first definition of a macro takes precedence
+        let mut macro_rules_definitions = crate_wide_info.macro_rules_definitions;
+        macro_rules_definitions.sort_by(|(lhs, ..), (rhs, ..)| lhs.cmp(rhs));
+        // Macros may be invoked in other files than the ones where they are defined
+        let macro_rules_definitions = macro_rules_definitions
+            .into_iter()
+            .filter(|(path, inner_cfgs, _)| is_file_enabled(path, inner_cfgs))
+            .flat_map(|(.., definitions)| definitions)
+            .collect();
+        // Sorted as well, so that the result doesn't depend on the workers when a module is defined by several files
+        let mut type_scopes = crate_wide_info.type_scopes;
+        type_scopes.sort_by(|(lhs, ..), (rhs, ..)| lhs.cmp(rhs));
+        let mut type_resolver = TypeResolver::default();
+        type_scopes
+            .into_iter()
+            .filter(|(path, inner_cfgs, _)| is_file_enabled(path, inner_cfgs))
+            .for_each(|(path, _, file_scopes)| {
+                type_resolver.add_file(&module_tree.module_path(&path), file_scopes)
+            });
+        let mut scan_context = Self {
+            module_tree,
+            macro_rules_definitions,
+            type_resolver,
+            digest: String::new(),
+        };
+        if is_cached {
+            scan_context.digest =
+                sha256_hex(&serde_json::to_vec(&scan_context).unwrap_or_default());
+        }
+        scan_context
+    }
+
+    /// All `#[cfg]` predicates affecting the file, i.e. the ones on the `mod` declarations leading to
+    /// the file followed by the `#![cfg]` predicates of the file itself. Returns [None] if the file is disabled.
+    fn file_cfgs(
+        &self,
+        path: &Path,
+        inner_attrs: &[syn::Attribute],
+        options: &ScanOptions,
+    ) -> Option<Vec<CfgPredicate>> {
+        let mut cfgs = self.module_tree.cfgs(path);
+        cfgs.extend(cfg::cfg_predicates(inner_attrs));
+        cfg::is_cfg_enabled(options.cfg_set.as_ref(), &cfgs).then_some(cfgs)
+    }
+}
+
+/// The state of a file after the first phase of the scan.
+enum FileState {
+    /// The source is kept only if [ScanOptions::utf16_columns] is enabled.
+    Parsed {
+        file: syn::File,
+        source: Option<String>,
+    },
+    /// The file was skipped by the pre-filter. The source is kept along with the names of the invoked
+    /// macros because some of them may turn out to be crate-local `macro_rules!` macros.
+    Skipped(Option<(String, BTreeSet<String>)>),
+    Unparsable,
+    /// The file hasn't changed since the cached scan. The source is kept in case the cached result
+    /// is outdated because of changes in other files.
+    Cached {
+        source: String,
+        context_digest: String,
+        parsed_file: Option<ParsedFile>,
+    },
+    /// The file was dropped after the first phase so that the memory used by a streaming scan doesn't
+    /// grow with the size of the crate. It is loaded again in the second phase.
+    Unloaded,
+}
+
+/// Where the scanned files are read from.
+pub(crate) enum SourceFiles<'a> {
+    FileSystem,
+    /// The contents of the files, by path. Files that aren't in the map can't be read.
+    InMemory(&'a HashMap<PathBuf, String>),
+}
+
+impl SourceFiles<'_> {
+    fn read(&self, path: &Path, buffer: &mut String) -> std::io::Result<()> {
+        match self {
+            // BufReader is unnecessary bc the files are read only once
+            // https://doc.rust-lang.org/std/io/struct.BufReader.html
+            SourceFiles::FileSystem => std::fs::File::open(path)
+                .and_then(|mut file: std::fs::File| file.read_to_string(buffer))
+                .map(|_byted_read| ()),
+            SourceFiles::InMemory(sources) => match sources.get(path) {
+                Some(source) => {
+                    buffer.push_str(source);
+                    Ok(())
+                }
+                None => Err(std::io::ErrorKind::NotFound.into()),
+            },
+        }
+    }
+}
+
+/// A file after the first phase of the scan.
+struct PendingFile {
+    index: usize,
+    /// The hash of the content and the contribution of the file, when results are cached.
+    cache_entry: Option<(String, FileContribution)>,
+    state: FileState,
+}
+
+/// A file after the second phase of the scan.
+struct ScannedFile {
+    index: usize,
+    parsed_file: Option<ParsedFile>,
+    cache_entry: Option<CacheEntry>,
+}
+
+/// Parses the source unless the pre-filter rules it out. The source is taken out of the buffer only
+/// if it is needed later.
+fn parse_source(buffer: &mut String, options: &ScanOptions) -> FileState {
+    if options.prefilter {
+        let summary = prefilter(buffer);
+        if !summary.must_parse {
+            let source = (options.expand_macro_rules && !summary.invoked_macros.is_empty())
+                .then(|| (std::mem::take(buffer), summary.invoked_macros));
+            return FileState::Skipped(source);
+        }
+    }
+    match syn::parse_file(buffer) {
+        Ok(file) => FileState::Parsed {
+            file,
+            source: options.utf16_columns.then(|| std::mem::take(buffer)),
+        },
+        Err(_) => FileState::Unparsable,
+    }
+}
+
+/// Reads the file and parses it, unless the pre-filter rules it out or it is cached. Returns the hash
+/// of the content, when results are cached, along with the contribution and the state of the file,
+/// or [None] if the file can't be read. The buffer is left empty.
+fn load_file(
+    path: &Path,
+    buffer: &mut String,
+    source_files: &SourceFiles,
+    cache: Option<&Cache>,
+    options: &ScanOptions,
+) -> Option<(Option<String>, FileContribution, FileState)> {
+    if source_files.read(path, buffer).is_err() {
+        buffer.truncate(0);
+        return None;
+    }
+    let content_hash = cache.map(|_| sha256_hex(buffer.as_bytes()));
+    let cached = cache
+        .zip(content_hash.as_deref())
+        .and_then(|(cache, content_hash)| cache.get(path, content_hash));
+    let (contribution, state) = match cached {
+        Some(entry) => (
+            entry.contribution.clone(),
+            FileState::Cached {
+                source: std::mem::take(buffer),
+                context_digest: entry.context_digest.clone(),
+                parsed_file: entry.parsed_file.clone(),
+            },
+        ),
+        None => {
+            let state = parse_source(buffer, options);
+            (FileContribution::new(path, &state, options), state)
+        }
+    };
+    buffer.truncate(0);
+    Some((content_hash, contribution, state))
+}
+
+/// Reads and parses the files not yet taken by other workers. [syn::File]s can't be sent to other
+/// threads, so they stay with the worker that parsed them. Unless `retain_states` is set, only the
+/// contributions of the files are kept.
+fn parse_files(
+    paths: &[PathBuf],
+    next: &AtomicUsize,
+    source_files: &SourceFiles,
+    cache: Option<&Cache>,
+    options: &ScanOptions,
+    retain_states: bool,
+) -> (Vec<PendingFile>, CrateWideInfo) {
+    // With prior information, the buffer could be preallocated
+    let mut buffer = String::new();
+    let mut files = Vec::new();
+    let mut crate_wide_info = CrateWideInfo::default();
+
+    // All errors are skipped. In the hindsight, the solution with ? would be better
+    loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        let path = match paths.get(index) {
+            Some(path) => path,
+            None => break,
+        };
+        let (content_hash, contribution, state) =
+            match load_file(path, &mut buffer, source_files, cache, options) {
+                Some(loaded_file) => loaded_file,
+                None => continue,
+            };
+        let cache_entry = content_hash.map(|content_hash| (content_hash, contribution.clone()));
+        crate_wide_info.add(path, contribution);
+        files.push(PendingFile {
+            index,
+            cache_entry,
+            state: if retain_states {
+                state
+            } else {
+                FileState::Unloaded
+            },
+        });
+    }
+    (files, crate_wide_info)
+}
+
+/// Reads and parses the files pulled in by `include!` that aren't among `paths` on the current thread,
+/// along with the files they include in turn, and adds their contributions. They are usually few, e.g.
+/// files generated in `OUT_DIR`. Returns their paths along with the files, whose indices refer to these paths.
+fn parse_included_files(
+    paths: &[PathBuf],
+    crate_wide_info: &mut CrateWideInfo,
+    source_files: &SourceFiles,
+    cache: Option<&Cache>,
+    options: &ScanOptions,
+    retain_states: bool,
+) -> (Vec<PathBuf>, Vec<PendingFile>) {
+    let mut included_paths: Vec<PathBuf> = Vec::new();
+    let mut files = Vec::new();
+    loop {
+        let new_paths: Vec<PathBuf> = crate_wide_info
+            .module_tree
+            .included()
+            .iter()
+            .filter(|path| paths.binary_search(path).is_err() && !included_paths.contains(path))
+            .cloned()
+            .collect();
+        if new_paths.is_empty() {
+            break;
+        }
+        let next = AtomicUsize::new(included_paths.len());
+        included_paths.extend(new_paths);
+        let (new_files, new_crate_wide_info) = parse_files(
+            &included_paths,
+            &next,
+            source_files,
+            cache,
+            options,
+            retain_states,
+        );
+        files.extend(new_files);
+        crate_wide_info.merge(new_crate_wide_info);
+    }
+    (included_paths, files)
+}
+
+/// Finds the exports and the types of the file. Returns [None] if the file isn't reported.
+fn visit_file(
+    path: &Path,
+    state: FileState,
+    macro_rules_map: Option<&MacroRulesMap>,
+    scan_context: &ScanContext,
+    options: &ScanOptions,
+) -> Option<ParsedFile> {
+    let (file, source) = match state {
+        FileState::Parsed { file, source } => (file, source),
+        FileState::Skipped(Some((source, invoked_macros)))
+            if invoked_macros.iter().any(|name| {
+                macro_rules_map.map_or(false, |macro_rules_map| macro_rules_map.contains(name))
+            }) =>
+        {
+            (syn::parse_file(&source).ok()?, Some(source))
+        }
+        FileState::Skipped(_) => {
+            // The pre-filter has ruled out `#![cfg]` attributes
+            scan_context.file_cfgs(path, &[], options)?;
+            return Some(ParsedFile {
+                path: path.to_path_buf(),
+                no_mangle_pub_export_c_fns: NoManglePubExportCFns::default(),
+                type_defs: Vec::new(),
+            });
+        }
+        // Unloaded files are loaded before being visited
+        FileState::Unparsable | FileState::Unloaded => return None,
+        FileState::Cached {
+            mut source,
+            context_digest,
+            parsed_file,
+        } => {
+            if context_digest == scan_context.digest {
+                return parsed_file;
+            }
+            let state = parse_source(&mut source, options);
+            return visit_file(path, state, macro_rules_map, scan_context, options);
+        }
+    };
+    let cfgs = scan_context.file_cfgs(path, &file.attrs, options)?;
+    let mut visitor = NoManglePubExportCFnsVisitor {
+        module_path: scan_context.module_tree.module_path(path),
+        macro_rules_map,
+        ..NoManglePubExportCFnsVisitor::new(options.cfg_set.as_ref(), cfgs)
+    };
+    visitor.visit_file(&file);
+    let mut no_mangle_pub_export_c_fns = visitor.no_mangle_pub_export_c_fns;
+    if let Some(source) = source.filter(|_| options.utf16_columns) {
+        no_mangle_pub_export_c_fns.fill_utf16_columns(&source);
+    }
+    no_mangle_pub_export_c_fns.resolve_types(&scan_context.type_resolver);
+    Some(ParsedFile {
+        path: path.to_path_buf(),
+        no_mangle_pub_export_c_fns,
+        type_defs: visitor.type_defs,
+    })
+}
+
+fn visit_files(
+    paths: &[PathBuf],
+    files: Vec<PendingFile>,
+    scan_context: &ScanContext,
+    options: &ScanOptions,
+) -> Vec<ScannedFile> {
+    let macro_rules_map = options
+        .expand_macro_rules
+        .then(|| MacroRulesMap::new(&scan_context.macro_rules_definitions));
+    files
+        .into_iter()
+        .map(|file: PendingFile| {
+            scan_file(
+                &paths[file.index],
+                file,
+                macro_rules_map.as_ref(),
+                scan_context,
+                options,
+            )
+        })
+        .collect()
+}
+
+fn scan_file(
+    path: &Path,
+    file: PendingFile,
+    macro_rules_map: Option<&MacroRulesMap>,
+    scan_context: &ScanContext,
+    options: &ScanOptions,
+) -> ScannedFile {
+    let parsed_file = visit_file(path, file.state, macro_rules_map, scan_context, options);
+    let cache_entry = file
+        .cache_entry
+        .map(|(content_hash, contribution)| CacheEntry {
+            content_hash,
+            contribution,
+            context_digest: scan_context.digest.clone(),
+            parsed_file: parsed_file.clone(),
+        });
+    ScannedFile {
+        index: file.index,
+        parsed_file,
+        cache_entry,
+    }
+}
+
+/// Joins the worker, passing its panic on to the current thread.
+fn join_worker<T>(worker: std::thread::ScopedJoinHandle<'_, T>) -> T {
+    worker
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+/// Parses the given Rust source files on `options.threads` worker threads. Files that can't be read
+/// or parsed are skipped, unless they are skipped by the pre-filter, in which case they are reported
+/// without exports. The result is sorted by path.
+///
+/// The scan happens in two phases because `#[cfg]` on `mod` declarations and `macro_rules!`
+/// definitions affect other files: first, every worker parses its share of files and reports what
+/// matters to other files; then, every worker visits its files with the combined information.
+///
+/// When a cache is given, files whose content hasn't changed since the cached scan aren't parsed in
+/// the first phase, and their results are reused in the second phase unless the combined information
+/// has changed. The returned cache reflects this scan.
+pub(crate) fn scan_rust_source_files<I>(
+    paths: I,
+    source_files: &SourceFiles,
+    options: &ScanOptions,
+    cache: Option<&Cache>,
+) -> (Vec<ParsedFile>, Option<Cache>)
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let mut paths: Vec<PathBuf> = paths.into_iter().collect();
+    paths.sort();
+    let threads = options.threads.clamp(1, paths.len().max(1));
+    let next = AtomicUsize::new(0);
+
+    let (mut scanned_files, included_paths) = std::thread::scope(|scope| {
+        let (crate_wide_info_sender, crate_wide_info_receiver) = mpsc::channel::<CrateWideInfo>();
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                let crate_wide_info_sender = crate_wide_info_sender.clone();
+                let (scan_context_sender, scan_context_receiver) =
+                    mpsc::channel::<Arc<ScanContext>>();
+                let (paths, next) = (&paths, &next);
+                let worker = scope.spawn(move || {
+                    let (files, crate_wide_info) =
+                        parse_files(paths, next, source_files, cache, options, true);
+                    // The receiver outlives the workers. Dropping the sender lets the main thread
+                    // know that this worker is done parsing
+                    let _ = crate_wide_info_sender.send(crate_wide_info);
+                    drop(crate_wide_info_sender);
+                    match scan_context_receiver.recv() {
+                        Ok(scan_context) => visit_files(paths, files, &scan_context, options),
+                        Err(_) => Vec::new(),
+                    }
+                });
+                (scan_context_sender, worker)
+            })
+            .collect();
+        drop(crate_wide_info_sender);
+
+        let mut crate_wide_info = crate_wide_info_receiver.iter().fold(
+            CrateWideInfo::default(),
+            |mut acc: CrateWideInfo, crate_wide_info: CrateWideInfo| {
+                acc.merge(crate_wide_info);
+                acc
+            },
+        );
+        let (included_paths, included_files) = parse_included_files(
+            &paths,
+            &mut crate_wide_info,
+            source_files,
+            cache,
+            options,
+            true,
+        );
+        let scan_context = Arc::new(ScanContext::new(crate_wide_info, options, cache.is_some()));
+
+        // Every worker gets the context before any of them is joined, so that they visit their files in parallel
+        let workers: Vec<_> = workers
+            .into_iter()
+            .map(|(scan_context_sender, worker)| {
+                // The worker waits for the context, so the receiver is alive
+                let _ = scan_context_sender.send(Arc::clone(&scan_context));
+                worker
+            })
+            .collect();
+        let included_files = visit_files(&included_paths, included_files, &scan_context, options)
+            .into_iter()
+            .map(|mut scanned_file| {
+                scanned_file.index += paths.len();
+                scanned_file
+            });
+        let scanned_files: Vec<ScannedFile> = workers
+            .into_iter()
+            .flat_map(join_worker)
+            .chain(included_files)
+            .collect();
+        (scanned_files, included_paths)
+    });
+    paths.extend(included_paths);
+    scanned_files.sort_by(|lhs, rhs| paths[lhs.index].cmp(&paths[rhs.index]));
+
+    // Entries of files that are gone are dropped
+    let cache = cache.map(|_| {
+        let entries: HashMap<PathBuf, CacheEntry> = scanned_files
+            .iter_mut()
+            .filter_map(|scanned_file| {
+                let cache_entry = scanned_file.cache_entry.take()?;
+                Some((paths[scanned_file.index].clone(), cache_entry))
+            })
+            .collect();
+        Cache::new(options, entries)
+    });
+    let parsed_files = scanned_files
+        .into_iter()
+        .filter_map(|scanned_file| scanned_file.parsed_file)
+        .collect();
+    (parsed_files, cache)
+}
+
+/// Does the same as [scan_rust_source_files] but passes the parsed files to `sink` in path order as
+/// soon as they are ready, until it returns [ControlFlow::Break].
+///
+/// Unlike [scan_rust_source_files], the files aren't kept between the phases but read and parsed
+/// again, so that memory usage doesn't grow with the size of the crate. Since all files have been
+/// read by then, the second phase hands out files to whichever worker is free. The returned cache is
+/// [None] if the scan was stopped.
+pub(crate) fn stream_rust_source_files<I, F>(
+    paths: I,
+    source_files: &SourceFiles,
+    options: &ScanOptions,
+    cache: Option<&Cache>,
+    mut sink: F,
+) -> Option<Cache>
+where
+    I: IntoIterator<Item = PathBuf>,
+    F: FnMut(ParsedFile) -> ControlFlow<()>,
+{
+    let mut paths: Vec<PathBuf> = paths.into_iter().collect();
+    paths.sort();
+    let threads = options.threads.clamp(1, paths.len().max(1));
+
+    let next = AtomicUsize::new(0);
+    let (mut files, mut crate_wide_info) = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let (files, crate_wide_info) =
+                        parse_files(&paths, &next, source_files, cache, options, false);
+                    // Only what is needed to load the files again can be sent between threads
+                    let files: Vec<(usize, Option<(String, FileContribution)>)> = files
+                        .into_iter()
+                        .map(|file| (file.index, file.cache_entry))
+                        .collect();
+                    (files, crate_wide_info)
+                })
+            })
+            .collect();
+        workers.into_iter().map(join_worker).fold(
+            (Vec::new(), CrateWideInfo::default()),
+            |(mut acc_files, mut acc_info), (files, crate_wide_info)| {
+                acc_files.extend(files);
+                acc_info.merge(crate_wide_info);
+                (acc_files, acc_info)
+            },
+        )
+    });
+    let (included_paths, included_files) = parse_included_files(
+        &paths,
+        &mut crate_wide_info,
+        source_files,
+        cache,
+        options,
+        false,
+    );
+    files.extend(
+        included_files
+            .into_iter()
+            .map(|file| (file.index + paths.len(), file.cache_entry)),
+    );
+    paths.extend(included_paths);
+    files.sort_by(|(lhs, _), (rhs, _)| paths[*lhs].cmp(&paths[*rhs]));
+    let scan_context = ScanContext::new(crate_wide_info, options, cache.is_some());
+
+    let next = AtomicUsize::new(0);
+    let is_stopped = AtomicBool::new(false);
+    let mut entries: HashMap<PathBuf, CacheEntry> = HashMap::new();
+    std::thread::scope(|scope| {
+        let (scanned_file_sender, scanned_file_receiver) = mpsc::channel::<(usize, ScannedFile)>();
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                let scanned_file_sender = scanned_file_sender.clone();
+                let (paths, files, next, is_stopped) = (&paths, &files, &next, &is_stopped);
+                let scan_context = &scan_context;
+                scope.spawn(move || {
+                    let macro_rules_map = options
+                        .expand_macro_rules
+                        .then(|| MacroRulesMap::new(&scan_context.macro_rules_definitions));
+                    let mut buffer = String::new();
+                    while !is_stopped.load(Ordering::Relaxed) {
+                        let position = next.fetch_add(1, Ordering::Relaxed);
+                        let (index, cache_entry) = match files.get(position) {
+                            Some(file) => file,
+                            None => break,
+                        };
+                        let path = &paths[*index];
+                        // A file removed since the first phase isn't reported
+                        let state = load_file(path, &mut buffer, source_files, cache, options)
+                            .map_or(FileState::Unparsable, |(.., state)| state);
+                        let file = PendingFile {
+                            index: *index,
+                            cache_entry: cache_entry.clone(),
+                            state,
+                        };
+                        let scanned_file =
+                            scan_file(path, file, macro_rules_map.as_ref(), scan_context, options);
+                        if scanned_file_sender.send((position, scanned_file)).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(scanned_file_sender);
+
+        // Files are finished out of order, so the ones ahead of the next file in order wait here
+        let mut finished: BTreeMap<usize, ScannedFile> = BTreeMap::new();
+        let mut next_position = 0;
+        'receive: for (position, scanned_file) in scanned_file_receiver.iter() {
+            finished.insert(position, scanned_file);
+            while let Some(mut scanned_file) = finished.remove(&next_position) {
+                next_position += 1;
+                if let Some(cache_entry) = scanned_file.cache_entry.take() {
+                    entries.insert(paths[scanned_file.index].clone(), cache_entry);
+                }
+                let parsed_file = match scanned_file.parsed_file {
+                    Some(parsed_file) => parsed_file,
+                    None => continue,
+                };
+                if let ControlFlow::Break(()) = sink(parsed_file) {
+                    is_stopped.store(true, Ordering::Relaxed);
+                    break 'receive;
+                }
+            }
+        }
+        drop(scanned_file_receiver);
+        workers.into_iter().for_each(|worker| join_worker(worker));
+    });
+    (cache.is_some() && !is_stopped.into_inner()).then(|| Cache::new(options, entries))
+}
+
+/// Does the same as [stream_rust_source_files] with the cache in `options.cache_dir`, if any.
+pub(crate) fn stream_source_files<I, F>(
+    paths: I,
+    source_files: &SourceFiles,
+    options: &ScanOptions,
+    sink: F,
+) where
+    I: IntoIterator<Item = PathBuf>,
+    F: FnMut(ParsedFile) -> ControlFlow<()>,
+{
+    let cache_dir = match options.cache_dir.as_deref() {
+        Some(cache_dir) => cache_dir,
+        None => {
+            stream_rust_source_files(paths, source_files, options, None, sink);
+            return;
+        }
+    };
+    let cache = Cache::load(cache_dir, options);
+    // The cache is an optimization, so failing to save it isn't an error
+    if let Some(cache) = stream_rust_source_files(paths, source_files, options, Some(&cache), sink)
+    {
+        let _ = cache.save(cache_dir);
+    }
+}
+
+/// Does the same as [scan_rust_source_files] with the cache in `options.cache_dir`, if any.
+pub(crate) fn parse_source_files<I>(
+    paths: I,
+    source_files: &SourceFiles,
+    options: &ScanOptions,
+) -> Vec<ParsedFile>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let cache_dir = match options.cache_dir.as_deref() {
+        Some(cache_dir) => cache_dir,
+        None => return scan_rust_source_files(paths, source_files, options, None).0,
+    };
+    let cache = Cache::load(cache_dir, options);
+    let (parsed_files, cache) = scan_rust_source_files(paths, source_files, options, Some(&cache));
+    // The cache is an optimization, so failing to save it isn't an error
+    if let Some(cache) = cache {
+        let _ = cache.save(cache_dir);
+    }
+    parsed_files
+}
+
+/// Parses the given Rust source files on disk. See [scan_rust_source_files].
+pub(crate) fn parse_rust_source_files<I>(paths: I, options: &ScanOptions) -> Vec<ParsedFile>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    parse_source_files(paths, &SourceFiles::FileSystem, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        parse_for_no_mangle_pub_extern_c_fns_iter,
+        parse_for_no_mangle_pub_extern_c_fns_with_options, CfgSet, ScanOptions,
+    };
+
+    /// A crate whose files affect each other through `#[cfg]` on `mod` declarations and through
+    /// `macro_rules!` definitions, so that both phases of the scan matter.
+    fn write_crate(crate_root: &std::path::Path) {
+        let src = crate_root.join("src");
+        std::fs::create_dir_all(src.join("nested")).unwrap();
+        let mut lib_rs =
+            String::from("#[macro_use] mod macros;\n#[cfg(feature = \"nested\")] mod nested;\n");
+        (0..40).for_each(|i| {
+            lib_rs.push_str(&format!("mod m{:02};\n", i));
+            std::fs::write(
+                src.join(format!("m{:02}.rs", i)),
+                format!(
+                    "#[no_mangle] pub extern \"C\" fn f{0:02}() {{}}\nexport!(g{0:02});\n",
+                    i
+                ),
+            )
+            .unwrap();
+        });
+        std::fs::write(src.join("lib.rs"), lib_rs).unwrap();
+        std::fs::write(
+            src.join("macros.rs"),
+            "macro_rules! export { ($f:ident) => { #[no_mangle] pub extern \"C\" fn $f() {} }; }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            src.join("nested/mod.rs"),
+            "#[no_mangle] pub extern \"C\" fn nested() {}\n",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn output_is_sorted_and_independent_of_threads() {
+        let crate_root = tempfile::tempdir().unwrap();
+        write_crate(crate_root.path());
+        let crate_root = crate_root.path().to_str().unwrap();
+
+        let mut cfg_set = CfgSet::default();
+        cfg_set.insert_feature("nested");
+        let scan = |threads: usize| {
+            let options = ScanOptions {
+                threads,
+                cfg_set: Some(cfg_set.clone()),
+                relative_paths: true,
+                ..Default::default()
+            };
+            parse_for_no_mangle_pub_extern_c_fns_with_options(crate_root, &options).unwrap()
+        };
+        let parsed_files = scan(1);
+        assert_eq!(parsed_files.len(), 43);
+        assert!(parsed_files
+            .windows(2)
+            .all(|pair| pair[0].path < pair[1].path));
+        let m07 = parsed_files
+            .iter()
+            .find(|parsed_file| parsed_file.path.ends_with("m07.rs"))
+            .unwrap();
+        let symbols: Vec<&str> = m07
+            .no_mangle_pub_export_c_fns
+            .0
+            .iter()
+            .map(|no_mangle_pub_export_c_fn| no_mangle_pub_export_c_fn.symbol.as_str())
+            .collect();
+        assert_eq!(symbols, vec!["f07", "g07"]);
+
+        let expected = serde_json::to_string(&parsed_files).unwrap();
+        [2, 3, 8, 64].iter().for_each(|threads| {
+            assert_eq!(
+                serde_json::to_string(&scan(*threads)).unwrap(),
+                expected,
+                "{} threads",
+                threads
+            );
+        });
+        let options = ScanOptions {
+            threads: 8,
+            cfg_set: Some(cfg_set.clone()),
+            relative_paths: true,
+            ..Default::default()
+        };
+        let streamed: Vec<_> = parse_for_no_mangle_pub_extern_c_fns_iter(crate_root, &options)
+            .unwrap()
+            .collect();
+        assert_eq!(serde_json::to_string(&streamed).unwrap(), expected);
+    }
+}
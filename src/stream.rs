@@ -0,0 +1,135 @@
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use crate::scan::{stream_source_files, SourceFiles};
+use crate::{relativize_path, rust_source_files, ParsedFile, ScanOptions};
+
+/// Does the same as [parse_for_no_mangle_pub_extern_c_fns_with_options](crate::parse_for_no_mangle_pub_extern_c_fns_with_options)
+/// but passes every parsed file to `sink` as soon as it is ready instead of collecting them, so that
+/// large crates can be processed with bounded memory, e.g. written out as JSON lines.
+///
+/// The files are passed in the same order as they would be returned, on the calling thread, so
+/// `sink` doesn't need to be [Send]. Scanning stops when `sink` returns [ControlFlow::Break].
+///
+/// The files are read and parsed twice, once to find the out-of-line `mod` declarations and
+/// `macro_rules!` definitions that affect other files, and once to find the exports, since keeping
+/// the parsed files in between is what makes memory grow with the size of the crate.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Write;
+/// use std::ops::ControlFlow;
+/// use no_mangle_pub_export_c_fn::{parse_for_no_mangle_pub_extern_c_fns_with_sink, ScanOptions};
+///
+/// let crate_root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+/// let mut json_lines = Vec::new();
+/// parse_for_no_mangle_pub_extern_c_fns_with_sink(crate_root.as_str(), &ScanOptions::default(), |parsed_file| {
+///     serde_json::to_writer(&mut json_lines, &parsed_file).unwrap();
+///     writeln!(json_lines).unwrap();
+///     ControlFlow::Continue(())
+/// }).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// Fails if any of the patterns in [ScanOptions::include] and [ScanOptions::exclude] isn't a valid
+/// glob, in which case nothing is passed to `sink`.
+pub fn parse_for_no_mangle_pub_extern_c_fns_with_sink<F>(
+    crate_root: &str,
+    options: &ScanOptions,
+    sink: F,
+) -> Result<(), globset::Error>
+where
+    F: FnMut(ParsedFile) -> ControlFlow<()>,
+{
+    let paths = rust_source_files(crate_root, options)?;
+    stream_paths(crate_root, paths, options, sink);
+    Ok(())
+}
+
+fn stream_paths<I, F>(crate_root: &str, paths: I, options: &ScanOptions, mut sink: F)
+where
+    I: IntoIterator<Item = PathBuf>,
+    F: FnMut(ParsedFile) -> ControlFlow<()>,
+{
+    stream_source_files(
+        paths,
+        &SourceFiles::FileSystem,
+        options,
+        |mut parsed_file: ParsedFile| {
+            relativize_path(&mut parsed_file, Path::new(crate_root), options);
+            sink(parsed_file)
+        },
+    )
+}
+
+/// The iterator returned by [parse_for_no_mangle_pub_extern_c_fns_iter].
+///
+/// Dropping it stops the scan.
+#[derive(Debug)]
+pub struct ParsedFilesIter {
+    parsed_files: mpsc::Receiver<ParsedFile>,
+    scanner: Option<JoinHandle<()>>,
+}
+
+impl Iterator for ParsedFilesIter {
+    type Item = ParsedFile;
+
+    fn next(&mut self) -> Option<ParsedFile> {
+        match self.parsed_files.recv() {
+            Ok(parsed_file) => Some(parsed_file),
+            Err(_) => {
+                // The scan is over. A panic during the scan shouldn't pass for the end of the files
+                if let Some(Err(panic)) = self.scanner.take().map(JoinHandle::join) {
+                    std::panic::resume_unwind(panic);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Does the same as [parse_for_no_mangle_pub_extern_c_fns_with_sink] but returns an iterator over
+/// the parsed files. The scan runs on a background thread, which waits whenever `options.threads`
+/// files are ready but haven't been taken from the iterator yet.
+///
+/// # Example
+///
+/// ```
+/// use no_mangle_pub_export_c_fn::{parse_for_no_mangle_pub_extern_c_fns_iter, ScanOptions};
+///
+/// let crate_root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+/// let exports = parse_for_no_mangle_pub_extern_c_fns_iter(crate_root.as_str(), &ScanOptions::default())
+///     .unwrap()
+///     .flat_map(|parsed_file| parsed_file.no_mangle_pub_export_c_fns.0)
+///     .count();
+/// assert_eq!(exports, 1);
+/// ```
+///
+/// # Errors
+///
+/// Fails if any of the patterns in [ScanOptions::include] and [ScanOptions::exclude] isn't a valid glob.
+pub fn parse_for_no_mangle_pub_extern_c_fns_iter(
+    crate_root: &str,
+    options: &ScanOptions,
+) -> Result<ParsedFilesIter, globset::Error> {
+    let paths = rust_source_files(crate_root, options)?;
+    let (sender, receiver) = mpsc::sync_channel::<ParsedFile>(options.threads.max(1));
+    let (crate_root, options) = (crate_root.to_string(), options.clone());
+    let scanner = std::thread::spawn(move || {
+        stream_paths(&crate_root, paths, &options, |parsed_file| {
+            // The iterator has been dropped
+            match sender.send(parsed_file) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => ControlFlow::Break(()),
+            }
+        })
+    });
+    Ok(ParsedFilesIter {
+        parsed_files: receiver,
+        scanner: Some(scanner),
+    })
+}
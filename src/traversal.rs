@@ -0,0 +1,253 @@
+use std::path::{Path, PathBuf};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+
+use crate::ScanOptions;
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        // As in .gitignore, `*` doesn't match `/`, while `**` does
+        builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+    }
+    builder.build()
+}
+
+/// For the entry points that report errors as [std::io::Error].
+pub(crate) fn invalid_glob(err: globset::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, err)
+}
+
+/// [ScanOptions::include] and [ScanOptions::exclude], matched against paths relative to the crate root.
+pub(crate) struct PathFilter {
+    /// [None] if every file is included.
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    /// Fails if any of the patterns isn't a valid glob.
+    pub(crate) fn new(options: &ScanOptions) -> Result<Self, globset::Error> {
+        let include = match options.include.is_empty() {
+            true => None,
+            false => Some(glob_set(&options.include)?),
+        };
+        Ok(Self {
+            include,
+            exclude: glob_set(&options.exclude)?,
+        })
+    }
+
+    pub(crate) fn is_match(&self, relative_path: &Path) -> bool {
+        self.include
+            .as_ref()
+            .map_or(true, |include| include.is_match(relative_path))
+            && !self.exclude.is_match(relative_path)
+    }
+}
+
+/// Traverses all directories in `crate_root/src/**` and returns the paths of Rust source files,
+/// filtered according to `options`. Fails if any of the patterns in `options` isn't a valid glob.
+pub(crate) fn rust_source_files(
+    crate_root: &str,
+    options: &ScanOptions,
+) -> Result<impl Iterator<Item = PathBuf>, globset::Error> {
+    let crate_root = PathBuf::from(crate_root);
+    let path_filter = PathFilter::new(options)?;
+    // Hidden files and the like are scanned as well, unlike with the defaults of `ignore`
+    let respect_ignore_files = options.respect_ignore_files;
+    let walk = WalkBuilder::new(crate_root.join("src"))
+        .standard_filters(false)
+        .git_ignore(respect_ignore_files)
+        .git_exclude(respect_ignore_files)
+        .ignore(respect_ignore_files)
+        .parents(respect_ignore_files)
+        .follow_links(options.follow_symlinks)
+        .build();
+    // All errors are skipped. In the hindsight, the solution with ? would be better
+    let rust_source_files = walk
+        .flatten()
+        // Symbolic links that aren't followed have their own file type
+        .filter(|entry| {
+            entry
+                .file_type()
+                .map_or(false, |file_type| file_type.is_file())
+        })
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".rs"))
+        .map(ignore::DirEntry::into_path)
+        .filter(move |path| {
+            path.strip_prefix(&crate_root)
+                .map_or(false, |relative_path| path_filter.is_match(relative_path))
+        });
+    Ok(rust_source_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates the files, all empty, and returns the crate root.
+    fn write_files(paths: &[&str]) -> tempfile::TempDir {
+        let crate_root = tempfile::tempdir().unwrap();
+        paths.iter().for_each(|path| {
+            let path = crate_root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        });
+        crate_root
+    }
+
+    fn relative_paths(crate_root: &Path, options: &ScanOptions) -> Vec<String> {
+        let mut paths: Vec<String> = rust_source_files(crate_root.to_str().unwrap(), options)
+            .unwrap()
+            .map(|path| {
+                let relative_path = path.strip_prefix(crate_root).unwrap();
+                relative_path
+                    .iter()
+                    .map(|component| component.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn strings(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn filters_by_include_and_exclude() {
+        let crate_root = write_files(&[
+            "src/lib.rs",
+            "src/ffi/mod.rs",
+            "src/ffi/a.rs",
+            "src/ffi/fixtures/b.rs",
+            "src/c.orig.rs",
+            "src/notes.txt",
+            "tests/t.rs",
+        ]);
+        let crate_root = crate_root.path();
+        assert_eq!(
+            relative_paths(crate_root, &ScanOptions::default()),
+            strings(&[
+                "src/c.orig.rs",
+                "src/ffi/a.rs",
+                "src/ffi/fixtures/b.rs",
+                "src/ffi/mod.rs",
+                "src/lib.rs",
+            ])
+        );
+        let options = ScanOptions {
+            include: strings(&["src/ffi/**"]),
+            exclude: strings(&["src/**/fixtures/**"]),
+            ..Default::default()
+        };
+        assert_eq!(
+            relative_paths(crate_root, &options),
+            strings(&["src/ffi/a.rs", "src/ffi/mod.rs"])
+        );
+        // `*` doesn't match `/`
+        let options = ScanOptions {
+            include: strings(&["src/*.rs"]),
+            exclude: strings(&["**/*.orig.rs"]),
+            ..Default::default()
+        };
+        assert_eq!(
+            relative_paths(crate_root, &options),
+            strings(&["src/lib.rs"])
+        );
+    }
+
+    #[test]
+    fn fails_on_invalid_globs() {
+        let crate_root = write_files(&["src/lib.rs"]);
+        let crate_root = crate_root.path().to_str().unwrap();
+        [
+            ScanOptions {
+                include: strings(&["src/{a,b"]),
+                ..Default::default()
+            },
+            ScanOptions {
+                exclude: strings(&["src/**", "src/[a"]),
+                ..Default::default()
+            },
+        ]
+        .iter()
+        .for_each(|options| {
+            assert!(PathFilter::new(options).is_err());
+            assert!(rust_source_files(crate_root, options).is_err());
+            assert!(
+                crate::parse_for_no_mangle_pub_extern_c_fns_with_options(crate_root, options)
+                    .is_err()
+            );
+            let err = crate::parse_git_revision_for_no_mangle_pub_extern_c_fns(
+                crate_root, "HEAD", options,
+            )
+            .err()
+            .unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        });
+    }
+
+    #[test]
+    fn respects_ignore_files_on_request() {
+        let crate_root = write_files(&[
+            "src/lib.rs",
+            "src/generated.rs",
+            "src/vendored/a.rs",
+            "src/scratch.rs",
+        ]);
+        std::fs::create_dir(crate_root.path().join(".git")).unwrap();
+        std::fs::write(crate_root.path().join(".gitignore"), "/src/generated.rs\n").unwrap();
+        std::fs::write(crate_root.path().join("src/.ignore"), "vendored/\n").unwrap();
+        std::fs::write(crate_root.path().join("src/.gitignore"), "scratch.rs\n").unwrap();
+        let crate_root = crate_root.path();
+
+        assert_eq!(
+            relative_paths(crate_root, &ScanOptions::default()),
+            strings(&[
+                "src/generated.rs",
+                "src/lib.rs",
+                "src/scratch.rs",
+                "src/vendored/a.rs"
+            ])
+        );
+        let options = ScanOptions {
+            respect_ignore_files: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            relative_paths(crate_root, &options),
+            strings(&["src/lib.rs"])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_on_request() {
+        let crate_root = write_files(&["src/lib.rs", "shared/ffi/a.rs", "shared/b.rs"]);
+        let crate_root = crate_root.path();
+        std::os::unix::fs::symlink(crate_root.join("shared/ffi"), crate_root.join("src/ffi"))
+            .unwrap();
+        std::os::unix::fs::symlink(crate_root.join("shared/b.rs"), crate_root.join("src/b.rs"))
+            .unwrap();
+        // A cycle, which is reported as an error by the walk and skipped
+        std::os::unix::fs::symlink(crate_root.join("src"), crate_root.join("src/ffi/src")).unwrap();
+
+        assert_eq!(
+            relative_paths(crate_root, &ScanOptions::default()),
+            strings(&["src/lib.rs"])
+        );
+        let options = ScanOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            relative_paths(crate_root, &options),
+            strings(&["src/b.rs", "src/ffi/a.rs", "src/lib.rs"])
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use quote::ToTokens;
+use serde::{Deserialize, Serialize};
+
+use crate::cfg::{self, CfgSet};
+use crate::{doc_comment, rust_path};
+
+/// The kind of item that defines a [TypeDef].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeDefKind {
+    Struct,
+    Enum,
+    Union,
+    /// `type Alias = ...;`
+    TypeAlias,
+}
+
+/// A variant of an enum without fields.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnumVariant {
+    pub ident: String,
+    /// The explicit discriminant as a token string, e.g. `1 << 2`.
+    pub discriminant: Option<String>,
+}
+
+/// A type defined in a scanned file, e.g. a `#[repr(C)]` struct taken by an export. Types are
+/// recorded whether or not they are referenced by exports, since they may be referenced from other files.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TypeDef {
+    pub ident: String,
+    /// The canonical path of the type, e.g. `crate::ffi::Status`, which [FnArg::resolved_ty](crate::FnArg::resolved_ty)
+    /// refers to as a token string, e.g. `crate :: ffi :: Status`.
+    #[serde(default)]
+    pub rust_path: String,
+    pub kind: TypeDefKind,
+    /// The hints of the `#[repr(...)]` attributes, e.g. `["C", "packed"]` for `#[repr(C, packed(2))]`.
+    #[serde(default)]
+    pub repr: Vec<String>,
+    /// The variants of enums without fields, i.e. of C-like enums. Empty for other types.
+    #[serde(default)]
+    pub variants: Vec<EnumVariant>,
+    /// The doc comment, the same as [NoManglePubExportCFn::doc](crate::NoManglePubExportCFn::doc).
+    pub doc: Option<String>,
+}
+
+impl TypeDef {
+    pub(crate) fn new(
+        ident: &syn::Ident,
+        kind: TypeDefKind,
+        attrs: &[syn::Attribute],
+        module_path: &[String],
+        variants: Vec<EnumVariant>,
+        cfg_set: Option<&CfgSet>,
+    ) -> Self {
+        let attrs = cfg::enabled_attrs(attrs, cfg_set);
+        Self {
+            ident: ident.to_string(),
+            rust_path: rust_path(module_path, &ident.to_string()),
+            kind,
+            repr: attrs
+                .iter()
+                .filter_map(|conditional_meta| match &conditional_meta.meta {
+                    syn::Meta::List(meta_list) if meta_list.path.is_ident("repr") => {
+                        Some(&meta_list.nested)
+                    }
+                    _ => None,
+                })
+                .flatten()
+                .filter_map(|nested_meta| match nested_meta {
+                    syn::NestedMeta::Meta(meta) => meta.path().get_ident().map(ToString::to_string),
+                    syn::NestedMeta::Lit(_) => None,
+                })
+                .collect(),
+            variants,
+            doc: doc_comment(&attrs),
+        }
+    }
+
+    /// Whether the type has the layout that Rust chooses, i.e. it's a struct, an enum or a union
+    /// without `#[repr(C)]`, `#[repr(transparent)]` or, for enums, a primitive representation. Such
+    /// types can only be passed to C behind pointers, as opaque handles.
+    pub fn is_opaque(&self) -> bool {
+        let has_c_layout = self.repr.iter().any(|hint| match hint.as_str() {
+            "C" | "transparent" => true,
+            "packed" | "align" => false,
+            // `u8`, `i32`, etc.
+            _ => self.kind == TypeDefKind::Enum,
+        });
+        self.kind != TypeDefKind::TypeAlias && !has_c_layout
+    }
+}
+
+/// The variants of an enum, if none of them has fields. Variants disabled by `cfg_set` are left out.
+pub(crate) fn enum_variants(
+    item_enum: &syn::ItemEnum,
+    cfg_set: Option<&CfgSet>,
+) -> Vec<EnumVariant> {
+    let variants: Vec<&syn::Variant> = item_enum
+        .variants
+        .iter()
+        .filter(|variant| cfg::is_cfg_enabled(cfg_set, &cfg::cfg_predicates(&variant.attrs)))
+        .collect();
+    if variants
+        .iter()
+        .any(|variant| !matches!(variant.fields, syn::Fields::Unit))
+    {
+        return Vec::new();
+    }
+    variants
+        .into_iter()
+        .map(|variant| EnumVariant {
+            ident: variant.ident.to_string(),
+            discriminant: variant
+                .discriminant
+                .as_ref()
+                .map(|(_, expr)| expr.to_token_stream().to_string()),
+        })
+        .collect()
+}
@@ -0,0 +1,198 @@
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::build_script::write_outputs;
+use crate::cache::Cache;
+use crate::scan::{scan_rust_source_files, SourceFiles};
+use crate::traversal::invalid_glob;
+use crate::{
+    diff_exports, relativize_paths, rust_source_files, BuildScriptOutputs, ExportsDiff, ParsedFile,
+};
+
+/// Editors often save a file with several writes, so events arriving within this period are handled together.
+const DEBOUNCE_PERIOD: Duration = Duration::from_millis(100);
+
+fn to_io_error(err: notify::Error) -> std::io::Error {
+    match err.kind {
+        notify::ErrorKind::Io(err) => err,
+        kind => std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", kind)),
+    }
+}
+
+// Directories have no extension. Their removal or renaming affects the Rust source files inside
+fn is_relevant(event: &notify::Event) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && event
+            .paths
+            .iter()
+            .any(|path: &PathBuf| path.extension().map_or(true, |extension| extension == "rs"))
+}
+
+/// Watches `crate_root/src/**` and keeps the outputs up to date while Rust source files are edited,
+/// e.g. to regenerate a C header during local development. Requires the `watch` feature.
+///
+/// The crate is scanned and the outputs are written right away, then again after every change.
+/// Results for unchanged files are reused, so only changed files are parsed again. After each scan,
+/// `on_scan` receives the exports and their difference from the previous scan; the first scan
+/// reports all exports as added. Watching stops when `on_scan` returns [ControlFlow::Break].
+///
+/// # Example
+///
+/// ```no_run
+/// use std::ops::ControlFlow;
+/// use no_mangle_pub_export_c_fn::{watch_build_script_outputs, BuildScriptOutputs};
+///
+/// fn main() -> std::io::Result<()> {
+///     let outputs = BuildScriptOutputs {
+///         out_dir: Some("include".into()),
+///         c_header: Some("bindings.h".into()),
+///         ..Default::default()
+///     };
+///     watch_build_script_outputs(".", &outputs, |_parsed_files, diff| {
+///         print!("{}", diff);
+///         ControlFlow::Continue(())
+///     })
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Fails if the source tree can't be watched, if a pattern in the scan options isn't a valid glob,
+/// or if an output can't be written. `OUT_DIR` is used when no `out_dir` was given, so outside of
+/// build scripts `out_dir` is usually required.
+pub fn watch_build_script_outputs<F>(
+    crate_root: &str,
+    outputs: &BuildScriptOutputs,
+    mut on_scan: F,
+) -> std::io::Result<()>
+where
+    F: FnMut(&[ParsedFile], &ExportsDiff) -> ControlFlow<()>,
+{
+    let options = &outputs.scan_options;
+    let (event_sender, event_receiver) = mpsc::channel::<notify::Result<notify::Event>>();
+    let mut watcher = notify::recommended_watcher(event_sender).map_err(to_io_error)?;
+    let src_dir = Path::new(crate_root).join("src");
+    watcher
+        .watch(&src_dir, RecursiveMode::Recursive)
+        .map_err(to_io_error)?;
+
+    let mut cache = match options.cache_dir.as_deref() {
+        Some(cache_dir) => Cache::load(cache_dir, options),
+        None => Cache::new(options, Default::default()),
+    };
+    let mut previous: Vec<ParsedFile> = Vec::new();
+    loop {
+        let (mut parsed_files, new_cache) = scan_rust_source_files(
+            rust_source_files(crate_root, options).map_err(invalid_glob)?,
+            &SourceFiles::FileSystem,
+            options,
+            Some(&cache),
+        );
+        relativize_paths(&mut parsed_files, Path::new(crate_root), options);
+        if let Some(new_cache) = new_cache {
+            cache = new_cache;
+        }
+        if let Some(cache_dir) = options.cache_dir.as_deref() {
+            // The cache is an optimization, so failing to save it isn't an error
+            let _ = cache.save(cache_dir);
+        }
+        write_outputs(&parsed_files, outputs)?;
+        let diff = diff_exports(&previous, &parsed_files);
+        if let ControlFlow::Break(()) = on_scan(&parsed_files, &diff) {
+            return Ok(());
+        }
+        previous = parsed_files;
+
+        // Waits for a relevant change, then for the burst of events to settle
+        loop {
+            match event_receiver.recv() {
+                Ok(Ok(event)) if is_relevant(&event) => break,
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => return Err(to_io_error(err)),
+                // The watcher owns the sender and lives as long as this function
+                Err(_) => return Ok(()),
+            }
+        }
+        loop {
+            match event_receiver.recv_timeout(DEBOUNCE_PERIOD) {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => return Err(to_io_error(err)),
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changes_and_rewrites_outputs() {
+        let crate_root = tempfile::tempdir().unwrap();
+        let src_dir = crate_root.path().join("src");
+        std::fs::create_dir(&src_dir).unwrap();
+        std::fs::write(
+            src_dir.join("lib.rs"),
+            "#[no_mangle] pub extern \"C\" fn kept() {}\n\
+             #[no_mangle] pub extern \"C\" fn changed() {}\n\
+             #[no_mangle] pub extern \"C\" fn removed() {}\n",
+        )
+        .unwrap();
+        let outputs = BuildScriptOutputs {
+            out_dir: Some(crate_root.path().join("include")),
+            version_script: Some("exports.map".into()),
+            scan_options: crate::ScanOptions {
+                relative_paths: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (diff_sender, diff_receiver) = mpsc::channel::<String>();
+        let crate_root_path = crate_root.path().to_str().unwrap().to_string();
+        let watch_outputs = outputs.clone();
+        let lib_rs = src_dir.join("lib.rs");
+        // The thread is left behind if no change is reported, so that the test fails instead of hanging
+        std::thread::spawn(move || {
+            let mut scans = 0;
+            watch_build_script_outputs(&crate_root_path, &watch_outputs, |_parsed_files, diff| {
+                scans += 1;
+                diff_sender.send(diff.to_string()).unwrap();
+                if scans == 1 {
+                    std::fs::write(
+                        &lib_rs,
+                        "#[no_mangle] pub extern \"C\" fn kept() {}\n\
+                         #[no_mangle] pub extern \"C\" fn changed(x: u8) {}\n\
+                         #[no_mangle] pub extern \"C\" fn added() {}\n",
+                    )
+                    .unwrap();
+                }
+                match scans {
+                    1 => ControlFlow::Continue(()),
+                    _ => ControlFlow::Break(()),
+                }
+            })
+            .unwrap();
+        });
+
+        let timeout = Duration::from_secs(30);
+        assert_eq!(
+            diff_receiver.recv_timeout(timeout).unwrap(),
+            "+ changed (src/lib.rs)\n+ kept (src/lib.rs)\n+ removed (src/lib.rs)\n"
+        );
+        assert_eq!(
+            diff_receiver.recv_timeout(timeout).unwrap(),
+            "+ added (src/lib.rs)\n- removed (src/lib.rs)\n~ changed (src/lib.rs)\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(crate_root.path().join("include/exports.map")).unwrap(),
+            "{\n  global:\n    kept;\n    changed;\n    added;\n  local: *;\n};\n"
+        );
+    }
+}